@@ -0,0 +1,238 @@
+/*
+ *	Command line of the aden binary.
+ */
+use std::path::PathBuf;
+
+use server_side::config::Override;
+
+pub const USAGE: &str = "Usage: aden [options]
+       aden config dump [options]
+       aden serve <dir> [--bind <address>] [--port <port>] [--log-level <level>]
+
+Options:
+  -c, --config <file>       Config file, <root>/config/config.conf by default.
+  -r, --root <dir>          Server root: paths in the config are under it.
+                            The current directory by default.
+  -b, --bind <address>      Listen on <address>, over server_address.
+  -p, --port <port>         Listen on <port>, over server_port.
+  -l, --log-level <level>   error, warn, info or debug, over [logging] level.
+  -t, --test-config         Check the configuration and exit: 0 when it is OK.
+  -V, --version             Show the version and exit.
+  -h, --help                Show this help and exit.
+
+`aden config dump` shows the settings in effect and where each was set.
+`aden serve <dir>` serves <dir> with the built-in settings, without reading
+any config file.
+
+Settings are read from, later ones winning: defaults, the config file and the
+files it includes, `ADEN_<KEY>` and `ADEN_LOGGING_<KEY>` environment variables
+(`ADEN_SERVER_PORT=8000`), then the options above.";
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+	Run,
+	TestConfig,
+	DumpConfig,
+	Version,
+	Help
+}
+
+#[derive(Debug)]
+pub struct Options {
+	pub action: Action,
+	// None: `<root>/config/config.conf`.
+	pub config_path: Option<PathBuf>,
+	pub root: Option<PathBuf>,
+	// `aden serve <dir>`: <dir> is the root, no config file is read.
+	pub serve_dir: Option<PathBuf>,
+	pub overrides: Vec<Override>
+}
+
+impl Options {
+	// Arguments without the program name.
+	pub fn parse(args: &[String]) -> Result<Options, String> {
+		let mut options = Options {
+			action: Action::Run,
+			config_path: None,
+			root: None,
+			serve_dir: None,
+			overrides: vec![]
+		};
+
+		let mut args = args.iter();
+		let mut serve = false;
+		// `aden config dump`
+		let mut config = false;
+		while let Some(arg) = args.next() {
+			// `--port=8000` as well as `--port 8000`.
+			let (flag, inline_value) = match arg.find('=') {
+				Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_string())),
+				_ => (arg.as_str(), None)
+			};
+			let takes_value = matches!(flag, "-c" | "--config" | "-r" | "--root" | "-b" | "--bind"
+				| "-p" | "--port" | "-l" | "--log-level");
+			let value = if takes_value {
+				// `-p -l debug`: the port is missing, not `-l`.
+				let value = match inline_value {
+					Some(value) => Some(value),
+					None if args.as_slice().first().is_some_and(|next| !next.starts_with('-')) => args.next().cloned(),
+					None => None
+				};
+				match value {
+					Some(value) => value,
+					None => return Err(format!("`{}` needs a value", flag))
+				}
+			} else if inline_value.is_some() {
+				return Err(format!("`{}` takes no value", flag));
+			} else {
+				String::new()
+			};
+
+			match flag {
+				"-c" | "--config" => options.config_path = Some(PathBuf::from(value)),
+				"-r" | "--root" => options.root = Some(PathBuf::from(value)),
+				"-b" | "--bind" => options.add_override(false, "server_address", value, flag),
+				"-p" | "--port" => options.add_override(false, "server_port", value, flag),
+				"-l" | "--log-level" => options.add_override(true, "level", value, flag),
+				"-t" | "--test-config" | "--check-config" => options.action = Action::TestConfig,
+				"-V" | "--version" => options.action = Action::Version,
+				"-h" | "--help" => options.action = Action::Help,
+				"serve" if !serve && !config => serve = true,
+				"config" if !serve && !config => config = true,
+				"dump" if config && options.action == Action::Run => options.action = Action::DumpConfig,
+				_ if serve && options.serve_dir.is_none() && !flag.starts_with('-') =>
+					options.serve_dir = Some(PathBuf::from(flag)),
+				_ if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+				_ => return Err(format!("unexpected argument `{}`", flag))
+			}
+		}
+
+		if config && options.action != Action::DumpConfig {
+			return Err("`config` needs a command: `dump`".to_string());
+		}
+		if serve {
+			if options.serve_dir.is_none() {
+				return Err("`serve` needs a directory".to_string());
+			}
+			if options.config_path.is_some() || options.root.is_some() {
+				return Err("`serve` reads no config file, its directory is the root".to_string());
+			}
+		}
+		Ok(options)
+	}
+
+	fn add_override(&mut self, logging: bool, key: &str, value: String, flag: &str) {
+		self.overrides.push(Override {
+			logging,
+			key: key.to_string(),
+			value,
+			source: format!("command line `{}`", flag)
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(args: &str) -> Result<Options, String> {
+		let args: Vec<String> = args.split_whitespace().map(|arg| arg.to_string()).collect();
+		Options::parse(&args)
+	}
+
+	fn get_overrides(options: &Options) -> Vec<(bool, &str, &str, &str)> {
+		options.overrides.iter()
+			.map(|o| (o.logging, o.key.as_str(), o.value.as_str(), o.source.as_str()))
+			.collect()
+	}
+
+	#[test]
+	fn runs_with_no_arguments() {
+		let options = parse("").unwrap();
+		assert_eq!(options.action, Action::Run);
+		assert!(options.config_path.is_none() && options.root.is_none() && options.serve_dir.is_none());
+		assert!(options.overrides.is_empty());
+	}
+
+	#[test]
+	fn reads_options_with_their_values() {
+		let options = parse("-c my.conf -r /srv -b 127.0.0.1 -p 8000 -l debug").unwrap();
+		assert_eq!(options.action, Action::Run);
+		assert_eq!(options.config_path, Some(PathBuf::from("my.conf")));
+		assert_eq!(options.root, Some(PathBuf::from("/srv")));
+		assert_eq!(get_overrides(&options), vec![
+			(false, "server_address", "127.0.0.1", "command line `-b`"),
+			(false, "server_port", "8000", "command line `-p`"),
+			(true, "level", "debug", "command line `-l`"),
+		]);
+
+		// Long forms, with the value after `=` or as the next argument.
+		let options = parse("--config=a=b.conf --root /srv --bind=::1 --port 9000 --log-level=warn").unwrap();
+		assert_eq!(options.config_path, Some(PathBuf::from("a=b.conf")));
+		assert_eq!(options.root, Some(PathBuf::from("/srv")));
+		assert_eq!(get_overrides(&options), vec![
+			(false, "server_address", "::1", "command line `--bind`"),
+			(false, "server_port", "9000", "command line `--port`"),
+			(true, "level", "warn", "command line `--log-level`"),
+		]);
+
+		// Given twice: both kept, the last one wins when applied.
+		assert_eq!(parse("-p 1 -p 2").unwrap().overrides.len(), 2);
+		// An inline value leaves the next argument alone.
+		assert_eq!(parse("--port=80 serve a").unwrap().serve_dir, Some(PathBuf::from("a")));
+	}
+
+	#[test]
+	fn reads_actions() {
+		for args in ["-t", "--test-config", "--check-config", "-c x.conf --test-config"].iter() {
+			assert_eq!(parse(args).unwrap().action, Action::TestConfig, "{}", args);
+		}
+		assert_eq!(parse("-V").unwrap().action, Action::Version);
+		assert_eq!(parse("--version").unwrap().action, Action::Version);
+		assert_eq!(parse("-h").unwrap().action, Action::Help);
+		assert_eq!(parse("--help").unwrap().action, Action::Help);
+	}
+
+	#[test]
+	fn reads_config_dump() {
+		let options = parse("config dump -r /srv --port 8000").unwrap();
+		assert_eq!(options.action, Action::DumpConfig);
+		assert_eq!(options.root, Some(PathBuf::from("/srv")));
+		assert_eq!(options.overrides.len(), 1);
+
+		assert_eq!(parse("config").unwrap_err(), "`config` needs a command: `dump`");
+		assert_eq!(parse("config show").unwrap_err(), "unexpected argument `show`");
+		assert_eq!(parse("dump").unwrap_err(), "unexpected argument `dump`");
+		assert_eq!(parse("config dump dump").unwrap_err(), "unexpected argument `dump`");
+	}
+
+	#[test]
+	fn reads_serve() {
+		let options = parse("serve ./public --port 8000").unwrap();
+		assert_eq!(options.action, Action::Run);
+		assert_eq!(options.serve_dir, Some(PathBuf::from("./public")));
+		assert_eq!(options.overrides.len(), 1);
+
+		assert_eq!(parse("serve").unwrap_err(), "`serve` needs a directory");
+		assert_eq!(parse("serve a b").unwrap_err(), "unexpected argument `b`");
+		assert_eq!(parse("serve a -c x.conf").unwrap_err(), "`serve` reads no config file, its directory is the root");
+		assert_eq!(parse("-r /srv serve a").unwrap_err(), "`serve` reads no config file, its directory is the root");
+		assert_eq!(parse("serve config dump").unwrap_err(), "unexpected argument `dump`");
+	}
+
+	// main() exits with 2 on these.
+	#[test]
+	fn refuses_bad_arguments() {
+		assert_eq!(parse("--verbose").unwrap_err(), "unknown option `--verbose`");
+		assert_eq!(parse("-x").unwrap_err(), "unknown option `-x`");
+		assert_eq!(parse("serve a --verbose").unwrap_err(), "unknown option `--verbose`");
+		assert_eq!(parse("public").unwrap_err(), "unexpected argument `public`");
+		assert_eq!(parse("--help=yes").unwrap_err(), "`--help` takes no value");
+		for flag in ["-c", "--config", "-r", "--root", "-b", "--bind", "-p", "--port", "-l", "--log-level"].iter() {
+			assert_eq!(parse(flag).unwrap_err(), format!("`{}` needs a value", flag));
+		}
+		assert_eq!(parse("serve a --port").unwrap_err(), "`--port` needs a value");
+		assert_eq!(parse("-p -l debug").unwrap_err(), "`-p` needs a value");
+		assert_eq!(parse("-c --test-config").unwrap_err(), "`-c` needs a value");
+	}
+}
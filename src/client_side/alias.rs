@@ -0,0 +1,283 @@
+// Aliases and rewrites of request paths, from `config/alias.conf` or the
+// `alias_file` of a virtual host.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error};
+
+use server_side::log;
+use server_side::regex;
+use server_side::regex::Regex;
+use server_side::resolver;
+use server_side::status;
+use server_side::status::HttpStatus;
+use server_side::utils;
+
+// Internal rewrites of one request before it is taken for a loop.
+const MAX_REWRITES: usize = 10;
+
+#[derive(Debug)]
+pub enum Rewritten {
+	// Path (normalized) and query string to serve.
+	Path(String, String),
+	// Send the client there with this status.
+	Redirect(HttpStatus, String)
+}
+
+#[derive(Debug)]
+struct Rewrite {
+	pattern: Regex,
+	replacement: String,
+	// None: rewritten inside the server, the client sees nothing.
+	redirect: Option<HttpStatus>
+}
+
+#[derive(Debug)]
+pub struct Aliases {
+	// Where the rules come from, for error messages.
+	file: String,
+	// URL prefix, directory (written like home_dir).
+	prefixes: Vec<(String, String)>,
+	rewrites: Vec<Rewrite>
+}
+
+impl Aliases {
+	// Load `file` (a path from the server root), no such file or an empty name
+	// means no aliases.
+	pub fn from_file(file: &str) -> Result<Aliases, Error> {
+		if file.is_empty() {
+			return Aliases::parse(file, &[]);
+		}
+		let root_path = utils::get_root_path();
+		let alias_path = utils::to_root_path(file, &root_path);
+		let alias_file = match File::open(&alias_path) {
+			Ok(f) => f,
+			Err(_) => return Aliases::parse(file, &[])
+		};
+
+		let mut lines = vec![];
+		for line in BufReader::new(alias_file).lines() {
+			lines.push(line?);
+		}
+		Aliases::parse(file, &lines)
+	}
+
+	// One rule a line:
+	//	alias <url prefix> <directory>
+	//	rewrite <regex> <replacement> [last | 301 | 302 | 307 | 308]
+	// All the errors are in the message, one a line as `file:line: error`.
+	pub fn parse(file: &str, lines: &[String]) -> Result<Aliases, Error> {
+		let mut aliases = Aliases { file: file.to_string(), prefixes: vec![], rewrites: vec![] };
+		let mut errors: Vec<String> = vec![];
+
+		for (i, line_raw) in lines.iter().enumerate() {
+			let line = line_raw.trim();
+			if line.starts_with('#') || line.is_empty() {
+				continue;
+			}
+
+			let words: Vec<&str> = line.split_whitespace().collect();
+			let added = match (words[0], words.len()) {
+				("alias", 3) if words[1].starts_with('/') => {
+					aliases.prefixes.push((words[1].to_string(), words[2].to_string()));
+					Ok(())
+				},
+				("rewrite", 3) | ("rewrite", 4) => Aliases::parse_rewrite(&words)
+					.map(|rewrite| aliases.rewrites.push(rewrite)),
+				_ => Err("expected `alias <prefix> <dir>` or `rewrite <regex> <replacement> [flag]`".to_string())
+			};
+
+			if let Err(e) = added {
+				errors.push(format!("{}:{}: `{}` ({})", file, i + 1, line, e));
+			}
+		}
+
+		if errors.is_empty() {
+			Ok(aliases)
+		}
+		else {
+			Err(Error::other(errors.join("\n")))
+		}
+	}
+
+	fn parse_rewrite(words: &[&str]) -> Result<Rewrite, String> {
+		let redirect = match words.get(3) {
+			None | Some(&"last") => None,
+			Some(&"301") => Some(status::MOVED_PERMANENTLY),
+			Some(&"302") => Some(status::FOUND),
+			Some(&"307") => Some(status::TEMPORARY_REDIRECT),
+			Some(&"308") => Some(status::PERMANENT_REDIRECT),
+			Some(flag) => return Err(format!("unknown flag `{}`", flag))
+		};
+		if redirect.is_none() && !words[2].starts_with('/') {
+			return Err("an internal rewrite must give a path".to_string());
+		}
+
+		Ok(Rewrite {
+			pattern: Regex::new(words[1])?,
+			replacement: words[2].to_string(),
+			redirect
+		})
+	}
+
+	// URL prefixes and their directories, for the path resolver.
+	pub fn get_prefixes(&self) -> &[(String, String)] {
+		&self.prefixes
+	}
+
+	// Run the rewrites over a normalized path, in file order. An internal
+	// rewrite starts over from the first rule with the new path, until no
+	// rule changes it. A path coming back is a loop: 500.
+	pub fn rewrite(&self, path: &str, query: &str) -> Result<Rewritten, HttpStatus> {
+		let mut path = path.to_string();
+		let mut query = query.to_string();
+		let mut seen = vec![path.clone()];
+
+		'rules: loop {
+			for rule in self.rewrites.iter() {
+				let captures = match rule.pattern.captures(&path) {
+					Some(captures) => captures,
+					None => continue
+				};
+				let target = regex::expand(&rule.replacement, &path, &captures);
+				let (target_path, target_query) = match target.find('?') {
+					Some(mark) => (&target[..mark], target[mark + 1..].to_string()),
+					None => (&target[..], query.to_owned())
+				};
+
+				if let Some(status) = rule.redirect {
+					let location = if target.contains("://") {
+						target.to_owned()
+					} else if resolver::collapse_path(target_path).ok().as_ref() == Some(&path) {
+						// The client would come back here forever.
+						log::error(format_args!("{} redirects `{}` to itself", self.file, path));
+						return Err(status::INTERNAL_SERVER_ERROR);
+					} else if target_query.is_empty() {
+						utils::percent_encode_path(target_path)
+					} else {
+						utils::percent_encode_path(target_path) + "?" + &target_query
+					};
+					return Ok(Rewritten::Redirect(status, location));
+				}
+
+				let new_path = resolver::collapse_path(target_path)?;
+				if new_path == path {
+					query = target_query;
+					break;
+				}
+				if seen.contains(&new_path) || seen.len() > MAX_REWRITES {
+					log::error(format_args!("{} rewrites loop on `{}`", self.file, seen.join("` -> `")));
+					return Err(status::INTERNAL_SERVER_ERROR);
+				}
+
+				seen.push(new_path.clone());
+				path = new_path;
+				query = target_query;
+				continue 'rules;
+			}
+
+			return Ok(Rewritten::Path(path, query));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn aliases(lines: &[&str]) -> Aliases {
+		let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+		Aliases::parse("alias.conf", &lines).unwrap()
+	}
+
+	fn served(aliases: &Aliases, path: &str) -> Result<(String, String), HttpStatus> {
+		match aliases.rewrite(path, "q=0")? {
+			Rewritten::Path(path, query) => Ok((path, query)),
+			Rewritten::Redirect(status, location) => panic!("{} to {}", status, location)
+		}
+	}
+
+	fn redirected(aliases: &Aliases, path: &str) -> (HttpStatus, String) {
+		match aliases.rewrite(path, "q=0") {
+			Ok(Rewritten::Redirect(status, location)) => (status, location),
+			other => panic!("{:?}", other)
+		}
+	}
+
+	#[test]
+	fn parses_rules_and_reports_every_error() {
+		let rules = aliases(&["# comment", "", "alias /docs /share/docs", "rewrite ^/a$ /b last"]);
+		assert_eq!(rules.get_prefixes(), &[("/docs".to_string(), "/share/docs".to_string())]);
+		assert_eq!(rules.rewrites.len(), 1);
+
+		let lines: Vec<String> = ["alias docs /share", "rewrite ^/a$ b", "rewrite ^/a$ /b 303", "rewrite ( /b"]
+			.iter().map(|line| line.to_string()).collect();
+		let message = Aliases::parse("alias.conf", &lines).unwrap_err().to_string();
+		for line in 1..5 {
+			assert!(message.contains(&format!("alias.conf:{}:", line)), "{}", message);
+		}
+	}
+
+	#[test]
+	fn rewrites_inside_the_server() {
+		let rules = aliases(&[
+			"rewrite ^/blog/(\\d+)/?$ /blog.html?id=$1",
+			"rewrite ^/old/(.*)$ /new/$1",
+			"rewrite ^/new/(.*)$ /current/$1",
+			"rewrite ^/same$ /same?from=same"
+		]);
+		assert_eq!(served(&rules, "/blog/42/").unwrap(), ("/blog.html".to_string(), "id=42".to_string()));
+		// Each new path starts over from the first rule, the query stays.
+		assert_eq!(served(&rules, "/old/a/b").unwrap(), ("/current/a/b".to_string(), "q=0".to_string()));
+		assert_eq!(served(&rules, "/other").unwrap(), ("/other".to_string(), "q=0".to_string()));
+		// Back to the same path: done, with the new query.
+		assert_eq!(served(&rules, "/same").unwrap(), ("/same".to_string(), "from=same".to_string()));
+		// The result is collapsed but may not climb above the root.
+		let rules = aliases(&["rewrite ^/up/(.*)$ /a/$1"]);
+		assert_eq!(served(&rules, "/up/../x").unwrap().0, "/x");
+		let rules = aliases(&["rewrite ^/up$ /../etc/passwd"]);
+		assert_eq!(served(&rules, "/up"), Err(status::FORBIDDEN));
+	}
+
+	#[test]
+	fn redirects_the_client() {
+		let rules = aliases(&[
+			"rewrite ^/old/(.*)$ /new/$1 301",
+			"rewrite ^/tmp$ /now?x=1 302",
+			"rewrite ^/ext$ https://example.com/$0 308",
+			"rewrite ^/space$ /a%20b\\sc 307"
+		]);
+		assert_eq!(redirected(&rules, "/old/a b"), (status::MOVED_PERMANENTLY, "/new/a%20b?q=0".to_string()));
+		assert_eq!(redirected(&rules, "/tmp"), (status::FOUND, "/now?x=1".to_string()));
+		assert_eq!(redirected(&rules, "/ext"), (status::PERMANENT_REDIRECT, "https://example.com//ext".to_string()));
+		assert_eq!(redirected(&rules, "/space").0, status::TEMPORARY_REDIRECT);
+	}
+
+	#[test]
+	fn refuses_a_redirect_to_the_same_path() {
+		let rules = aliases(&["rewrite ^/self/?$ /self 301", "rewrite ^/dots$ /a/./../dots 302"]);
+		assert_eq!(rules.rewrite("/self", "").unwrap_err(), status::INTERNAL_SERVER_ERROR);
+		// Dropping the slash is a move, not a loop.
+		assert_eq!(redirected(&rules, "/self/"), (status::MOVED_PERMANENTLY, "/self?q=0".to_string()));
+		assert_eq!(rules.rewrite("/dots", "").unwrap_err(), status::INTERNAL_SERVER_ERROR);
+	}
+
+	#[test]
+	fn refuses_rewrite_loops() {
+		let rules = aliases(&["rewrite ^/a$ /b", "rewrite ^/b$ /c", "rewrite ^/c$ /a"]);
+		assert_eq!(served(&rules, "/b"), Err(status::INTERNAL_SERVER_ERROR));
+		assert_eq!(served(&rules, "/c"), Err(status::INTERNAL_SERVER_ERROR));
+	}
+
+	#[test]
+	fn stops_after_max_rewrites() {
+		// Each pass adds an `x`: MAX_REWRITES of them are fine, one more is not.
+		let rules = aliases(&[&format!("rewrite ^/(x{{0,{}}})$ /$1x", MAX_REWRITES - 1)]);
+		let expected = format!("/{}", "x".repeat(MAX_REWRITES));
+		assert_eq!(served(&rules, "/").unwrap().0, expected);
+
+		let rules = aliases(&[&format!("rewrite ^/(x{{0,{}}})$ /$1x", MAX_REWRITES)]);
+		assert_eq!(served(&rules, "/"), Err(status::INTERNAL_SERVER_ERROR));
+		let rules = aliases(&["rewrite ^/(x*)$ /$1x"]);
+		assert_eq!(served(&rules, "/"), Err(status::INTERNAL_SERVER_ERROR));
+	}
+}
@@ -0,0 +1,231 @@
+// Decoding of `Transfer-Encoding: chunked` request bodies.
+
+use client_side::request::{RequestData, RequestParser};
+use server_side::status;
+use server_side::status::HttpStatus;
+
+// Longest chunk-size line accepted (size + extensions).
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Debug)]
+enum State {
+	Size,
+	Data(usize),
+	DataEnd,
+	Trailer,
+	Done
+}
+
+// Turns chunks into plain body bytes as they arrive.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+	state: State,
+	max_trailer_size: usize,
+	max_trailer_count: usize,
+	trailer_size: usize
+}
+
+impl ChunkedDecoder {
+	pub fn new(max_trailer_size: usize, max_trailer_count: usize) -> ChunkedDecoder {
+		ChunkedDecoder {
+			state: State::Size,
+			max_trailer_size,
+			max_trailer_count,
+			trailer_size: 0
+		}
+	}
+
+	// Decode what `buffer` holds, draining the consumed bytes: data goes to
+	// `content`, trailer fields to `trailer`. Ok(true) once the body is complete.
+	pub fn decode(&mut self, buffer: &mut Vec<u8>, content: &mut Vec<u8>,
+		trailer: &mut Vec<RequestData>, max_body_size: usize) -> Result<bool, HttpStatus> {
+		loop {
+			match self.state {
+				State::Size => {
+					let line = match ChunkedDecoder::take_line(buffer, MAX_CHUNK_LINE)? {
+						Some(line) => line,
+						None => return Ok(false),
+					};
+
+					let size = ChunkedDecoder::parse_chunk_size(&line)?;
+					if size > max_body_size.saturating_sub(content.len()) {
+						return Err(status::PAYLOAD_TOO_LARGE);
+					}
+
+					self.state = if size == 0 { State::Trailer } else { State::Data(size) };
+				}
+				State::Data(remaining) => {
+					if buffer.is_empty() {
+						return Ok(false);
+					}
+
+					let len = remaining.min(buffer.len());
+					content.extend(buffer.drain(..len));
+					self.state = if len == remaining { State::DataEnd } else { State::Data(remaining - len) };
+				}
+				State::DataEnd => {
+					match ChunkedDecoder::take_line(buffer, 2)? {
+						Some(ref line) if line.is_empty() => self.state = State::Size,
+						Some(_) => return Err(status::BAD_REQUEST),
+						None => return Ok(false),
+					}
+				}
+				State::Trailer => {
+					// Counted against the header limits, like the header block.
+					let limit = self.max_trailer_size.saturating_sub(self.trailer_size);
+					let line = match ChunkedDecoder::take_line(buffer, limit)
+						.map_err(|_| status::REQUEST_HEADER_FIELDS_TOO_LARGE)? {
+						Some(line) => line,
+						None => return Ok(false),
+					};
+
+					if line.is_empty() {
+						self.state = State::Done;
+						continue;
+					}
+
+					self.trailer_size += line.len() + 2;
+					if trailer.len() >= self.max_trailer_count {
+						return Err(status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+					}
+
+					// Same rules as header fields, obs-fold included.
+					if line[0] == b' ' || line[0] == b'\t' {
+						return Err(status::BAD_REQUEST);
+					}
+					trailer.push(RequestParser::parse_header_field(&line)?);
+				}
+				State::Done => return Ok(true),
+			}
+		}
+	}
+
+	// Take a line (without its CRLF or LF) from the front of `buffer`.
+	fn take_line(buffer: &mut Vec<u8>, max_len: usize) -> Result<Option<Vec<u8>>, HttpStatus> {
+		let line_end = match buffer.iter().position(|b| *b == b'\n') {
+			Some(pos) => pos,
+			None if buffer.len() > max_len + 1 => return Err(status::BAD_REQUEST),
+			None => return Ok(None),
+		};
+
+		let mut line: Vec<u8> = buffer.drain(..line_end + 1).collect();
+		line.pop();
+		if line.ends_with(b"\r") {
+			line.pop();
+		}
+
+		if line.len() > max_len {
+			return Err(status::BAD_REQUEST);
+		}
+
+		Ok(Some(line))
+	}
+
+	// `1a3f;name=value` -> 0x1a3f, chunk extensions are ignored.
+	fn parse_chunk_size(line: &[u8]) -> Result<usize, HttpStatus> {
+		let hex_len = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+		let rest = &line[hex_len..];
+		let rest_ok = rest.is_empty() || rest[0] == b';' || rest[0] == b' ' || rest[0] == b'\t';
+		if hex_len == 0 || hex_len > 16 || !rest_ok {
+			return Err(status::BAD_REQUEST);
+		}
+
+		let hex = String::from_utf8_lossy(&line[..hex_len]);
+		match usize::from_str_radix(&hex, 16) {
+			Ok(size) => Ok(size),
+			Err(_) => Err(status::BAD_REQUEST),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Decode `raw` in one go with room for a 100 byte body.
+	fn decode_all(raw: &[u8]) -> Result<(Vec<u8>, Vec<RequestData>), HttpStatus> {
+		let mut decoder = ChunkedDecoder::new(64, 2);
+		let (mut content, mut trailer) = (vec![], vec![]);
+		let mut buffer = raw.to_vec();
+		assert!(decoder.decode(&mut buffer, &mut content, &mut trailer, 100)?);
+		Ok((content, trailer))
+	}
+
+	#[test]
+	fn decodes_a_body_split_across_reads() {
+		let raw = b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\nGET /";
+		let mut decoder = ChunkedDecoder::new(64, 2);
+		let (mut content, mut trailer) = (vec![], vec![]);
+		let mut buffer = vec![];
+		// One byte at a time: every state has to wait for the rest.
+		for (i, b) in raw.iter().enumerate() {
+			buffer.push(*b);
+			let done = decoder.decode(&mut buffer, &mut content, &mut trailer, 100).unwrap();
+			assert_eq!(done, i >= raw.len() - 6, "after {} bytes", i + 1);
+		}
+		assert_eq!(content, b"Wikipedia");
+		// What follows the body is left for the next request.
+		assert_eq!(buffer, b"GET /");
+	}
+
+	#[test]
+	fn ignores_chunk_extensions() {
+		let (content, _) = decode_all(b"3;name=value\r\nabc\r\n2 ; x\r\nde\r\n0;last\r\n\r\n").unwrap();
+		assert_eq!(content, b"abcde");
+		// Bare LF line ends are taken too.
+		let (content, _) = decode_all(b"3\nabc\n0\n\n").unwrap();
+		assert_eq!(content, b"abc");
+	}
+
+	#[test]
+	fn bad_chunk_sizes_are_400() {
+		for raw in [&b"\r\nabc\r\n0\r\n\r\n"[..], b"x3\r\n", b"3x\r\n", b"-1\r\n",
+			b"00000000000000003\r\nabc\r\n0\r\n\r\n"].iter() {
+			assert_eq!(decode_all(raw).unwrap_err(), status::BAD_REQUEST, "{:?}", String::from_utf8_lossy(raw));
+		}
+		// 16 hex digits are fine, leading zeros included.
+		let (content, _) = decode_all(b"0000000000000003\r\nabc\r\n0\r\n\r\n").unwrap();
+		assert_eq!(content, b"abc");
+		// A size line that never ends.
+		let mut decoder = ChunkedDecoder::new(64, 2);
+		let mut buffer = vec![b'1'; MAX_CHUNK_LINE + 2];
+		assert_eq!(decoder.decode(&mut buffer, &mut vec![], &mut vec![], 100).unwrap_err(), status::BAD_REQUEST);
+	}
+
+	#[test]
+	fn data_must_end_with_crlf() {
+		for raw in [&b"3\r\nabcd\r\n0\r\n\r\n"[..], b"3\r\nabc0\r\n\r\n", b"3\r\nabcxy\r\n"].iter() {
+			assert_eq!(decode_all(raw).unwrap_err(), status::BAD_REQUEST, "{:?}", String::from_utf8_lossy(raw));
+		}
+	}
+
+	#[test]
+	fn big_bodies_are_413() {
+		assert_eq!(decode_all(b"65\r\n").unwrap_err(), status::PAYLOAD_TOO_LARGE);
+		// Counted over all the chunks.
+		let raw = format!("32\r\n{}\r\n33\r\n", "a".repeat(50));
+		assert_eq!(decode_all(raw.as_bytes()).unwrap_err(), status::PAYLOAD_TOO_LARGE);
+		assert_eq!(decode_all(b"ffffffffffffffff\r\n").unwrap_err(), status::PAYLOAD_TOO_LARGE);
+	}
+
+	#[test]
+	fn reads_trailer_fields() {
+		let (content, trailer) = decode_all(b"1\r\na\r\n0\r\nX-Sum: 12\r\nX-Other: b\r\n\r\n").unwrap();
+		assert_eq!(content, b"a");
+		assert_eq!(trailer.len(), 2);
+		assert_eq!((trailer[0].key.as_str(), trailer[0].value.as_str()), ("X-Sum", "12"));
+		assert_eq!(decode_all(b"0\r\n X-Folded: a\r\n\r\n").unwrap_err(), status::BAD_REQUEST);
+	}
+
+	#[test]
+	fn trailer_over_the_header_limits_is_431() {
+		// More fields than max_header_count.
+		let raw = b"0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+		assert_eq!(decode_all(raw).unwrap_err(), status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+		// More bytes than max_header_size, in one field or over several.
+		let raw = format!("0\r\nA: {}\r\n\r\n", "a".repeat(70));
+		assert_eq!(decode_all(raw.as_bytes()).unwrap_err(), status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+		let raw = format!("0\r\nA: {}\r\nB: {}\r\n\r\n", "a".repeat(30), "b".repeat(30));
+		assert_eq!(decode_all(raw.as_bytes()).unwrap_err(), status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+	}
+}
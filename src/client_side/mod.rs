@@ -1,2 +1,4 @@
+pub mod alias;
 
+pub mod chunked;
 pub mod request;
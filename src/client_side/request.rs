@@ -1,18 +1,30 @@
-
-// TODO: Support more method. Now, just GET and POST
+// HTTP/1.x request parsing, straight from the bytes received.
 
 use std::io::Error;
-use std::io::ErrorKind;
+use client_side::chunked::ChunkedDecoder;
 use server_side::status;
 use server_side::status::HttpStatus;
 
+// Requests with a bigger header block are refused with 431.
+pub const MAX_HEADER_SIZE: usize = 8192;
+// Same for requests with more header fields than this.
+pub const MAX_HEADER_COUNT: usize = 100;
+// Bigger bodies are refused with 413.
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// Interim response to `Expect: 100-continue`, the client sends its body after it.
+pub const CONTINUE_RESPONSE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
 #[derive(Debug)]
 pub struct Request {
 	pub method: String,
 	pub req_path: String,
 	pub http_ver: String,
 	pub header: Vec<RequestData>,
-	pub content: String		// for method::POST
+	// Host from an absolute-form target (`GET http://host/path HTTP/1.1`).
+	pub authority: Option<String>,
+	pub content: Vec<u8>,
+	// Fields sent after a chunked body.
+	pub trailer: Vec<RequestData>
 }
 
 // for easier, change directly.
@@ -22,93 +34,533 @@ pub struct RequestData {
 	pub value: String
 }
 
-impl Request {
-	pub fn new(raw_req: &[u8]) -> Result<Request, HttpStatus> {
-		let req_string = String::from_utf8_lossy(raw_req);
+// How the body of a parsed header is delimited.
+#[derive(Debug)]
+enum Body {
+	Length(usize),
+	Chunked(ChunkedDecoder)
+}
 
-		// remove this condition and following function soon, because of performance.
-		if !Request::is_valid_http_request(&req_string.to_string()) {
-			return Err(status::BAD_REQUEST);
+// Parses requests out of a connection buffer as bytes arrive.
+// One parser per connection: it remembers how far it has looked so every
+// byte is scanned once, and keeps a parsed header while its body comes in.
+#[derive(Debug)]
+pub struct RequestParser {
+	max_header_size: usize,
+	max_header_count: usize,
+	max_body_size: usize,
+	// Bytes at the front of the buffer already known not to end the header.
+	scanned: usize,
+	// Header is parsed (and drained from the buffer), its body is still coming.
+	pending: Option<(Request, Body)>,
+	// The pending request waits for CONTINUE_RESPONSE before sending its body.
+	expects_continue: bool
+}
+
+impl RequestParser {
+	pub fn new() -> RequestParser {
+		RequestParser::with_limits(MAX_HEADER_SIZE, MAX_HEADER_COUNT)
+	}
+
+	pub fn with_limits(max_header_size: usize, max_header_count: usize) -> RequestParser {
+		RequestParser {
+			max_header_size,
+			max_header_count,
+			max_body_size: MAX_BODY_SIZE,
+			scanned: 0,
+			pending: None,
+			expects_continue: false
 		}
+	}
+
+	pub fn set_max_body_size(&mut self, max_body_size: usize) {
+		self.max_body_size = max_body_size;
+	}
+
+	// Take one whole request (header and body) from the front of `buffer`.
+	// Ok(None) means more bytes are needed. Bytes of other requests
+	// (pipelining) are left in `buffer` for the next call.
+	pub fn parse(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Request>, HttpStatus> {
+		if self.pending.is_none() {
+			// Empty lines before a request line must be ignored.
+			let leading_crlf = buffer.iter()
+				.take_while(|b| **b == b'\r' || **b == b'\n')
+				.count();
+			if leading_crlf > 0 {
+				buffer.drain(..leading_crlf);
+				self.scanned = 0;
+			}
+
+			let header_len = match self.find_header_end(buffer) {
+				Some(len) => len,
+				None if buffer.len() > self.max_header_size => {
+					return Err(self.too_large(buffer));
+				}
+				None => return Ok(None),
+			};
+
+			if header_len > self.max_header_size {
+				return Err(self.too_large(buffer));
+			}
+
+			let req = self.parse_header(&buffer[..header_len])?;
+			// A body over max_body_size is refused here, before the client sends it.
+			let body = self.get_body(&req)?;
+			self.expects_continue = RequestParser::expects_continue(&req)?;
+			buffer.drain(..header_len);
+			self.scanned = 0;
+			self.pending = Some((req, body));
+		}
+
+		let complete = match self.pending {
+			Some((_, Body::Length(len))) => buffer.len() >= len,
+			Some((ref mut req, Body::Chunked(ref mut decoder))) => {
+				decoder.decode(buffer, &mut req.content, &mut req.trailer, self.max_body_size)?
+			}
+			None => false,
+		};
+
+		if !complete {
+			return Ok(None);
+		}
+
+		self.expects_continue = false;
+		let (mut req, body) = self.pending.take().unwrap();
+		if let Body::Length(len) = body {
+			req.content = buffer.drain(..len).collect();
+		}
+		Ok(Some(req))
+	}
+
+	// Part of a request is in `buffer`, or its header was parsed and its
+	// body is coming: the connection is not idle.
+	pub fn is_receiving(&self, buffer: &[u8]) -> bool {
+		self.pending.is_some() || buffer.iter().any(|b| *b != b'\r' && *b != b'\n')
+	}
+
+	// True once when the request being received asked for `100 Continue`
+	// and its body hasn't come yet: the caller sends CONTINUE_RESPONSE.
+	pub fn take_continue(&mut self) -> bool {
+		std::mem::replace(&mut self.expects_continue, false)
+	}
+
+	// Length of the header block including the empty line, lines may end with CRLF or LF.
+	fn find_header_end(&mut self, buffer: &[u8]) -> Option<usize> {
+		let mut pos = self.scanned;
+		while let Some(offset) = buffer[pos..].iter().position(|b| *b == b'\n') {
+			let line_end = pos + offset;
+			let line = &buffer[pos..line_end];
+			if line.is_empty() || line == b"\r" {
+				return Some(line_end + 1);
+			}
+			pos = line_end + 1;
+		}
+
+		// Only whole lines are skipped next time.
+		self.scanned = pos;
+		None
+	}
+
+	// A request line that alone doesn't fit is a too long URI, whatever
+	// arrived after it.
+	fn too_large(&self, buffer: &[u8]) -> HttpStatus {
+		let line_len = buffer.iter().position(|b| *b == b'\n').unwrap_or(buffer.len());
+		if line_len > self.max_header_size {
+			status::URI_TOO_LONG
+		}
+		else {
+			status::REQUEST_HEADER_FIELDS_TOO_LARGE
+		}
+	}
+
+	fn parse_header(&self, raw: &[u8]) -> Result<Request, HttpStatus> {
+		let mut lines = raw.split(|b| *b == b'\n')
+			.map(|line| if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line });
 
-		let mut lines  = req_string.lines();
 		let request_line = match lines.next() {
-			Some(data) => data,
-			None => {
-				// TODO: GET and POST must have more headers, not just request line.
+			Some(line) => line,
+			None => return Err(status::BAD_REQUEST),
+		};
+		let mut req = RequestParser::parse_request_line(request_line)?;
 
+		for line in lines {
+			if line.is_empty() {
+				// reached to bottom of http request.
+				break;
+			}
+
+			// obs-fold (a line continuing the previous field) is obsolete, refuse it.
+			if line[0] == b' ' || line[0] == b'\t' {
 				return Err(status::BAD_REQUEST);
 			}
-		};
 
-		let request_line_vec: Vec<&str> = request_line.split_whitespace().collect();
-		if request_line_vec.len() != 3 {
-			//
-			// TODO: check != 3 is bad, just join 1..(request_line_vec.len()-1)
-			// TODO: show log to file or screen.
-			//
-			
+			if req.header.len() >= self.max_header_count {
+				return Err(status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+			}
+
+			req.header.push(RequestParser::parse_header_field(line)?);
+		}
+
+		Ok(req)
+	}
+
+	fn parse_request_line(line: &[u8]) -> Result<Request, HttpStatus> {
+		let parts: Vec<&[u8]> = line.split(|b| *b == b' ').collect();
+		if parts.len() != 3 {
 			return Err(status::BAD_REQUEST);
 		}
 
-		let mut request_data: Vec<RequestData> = vec![];
-		while let Some(line) = lines.next() {
-			if line == "" {
-				// reached to bottom of http request - POST data.
-				break;
+		let (method, target, http_ver) = (parts[0], parts[1], parts[2]);
+		if method.is_empty() || !method.iter().all(|b| is_tchar(*b)) {
+			return Err(status::BAD_REQUEST);
+		}
+
+		if target.is_empty() || !target.iter().all(|b| *b > b' ' && *b < 0x7f) {
+			return Err(status::BAD_REQUEST);
+		}
+
+		if http_ver.len() != 8 || !http_ver.starts_with(b"HTTP/") || http_ver[6] != b'.'
+			|| !http_ver[5].is_ascii_digit() || !http_ver[7].is_ascii_digit() {
+			return Err(status::BAD_REQUEST);
+		}
+
+		if http_ver[5] != b'1' {
+			return Err(status::HTTP_VERSION_NOT_SUPPORTED);
+		}
+
+		// Checked above, all of them are ASCII.
+		let method = String::from_utf8_lossy(method).into_owned();
+		let target = String::from_utf8_lossy(target).into_owned();
+		let (req_path, authority) = RequestParser::parse_target(&method, &target)?;
+
+		Ok(Request {
+			method,
+			req_path,
+			http_ver: String::from_utf8_lossy(http_ver).into_owned(),
+			header: vec![],
+			authority,
+			content: vec![],
+			trailer: vec![]
+		})
+	}
+
+	// Split a request target into path (with query string) and authority.
+	fn parse_target(method: &str, target: &str) -> Result<(String, Option<String>), HttpStatus> {
+		// origin-form: /path?query
+		if target.starts_with('/') {
+			return Ok((target.to_string(), None));
+		}
+
+		// asterisk-form: OPTIONS * HTTP/1.1
+		if target == "*" {
+			return if method == "OPTIONS" {
+				Ok((target.to_string(), None))
 			}
+			else {
+				Err(status::BAD_REQUEST)
+			};
+		}
 
-			let mut line_vec: Vec<&str> = line.split(":").collect();
-			let key = line_vec[0];
+		// authority-form: CONNECT host:port HTTP/1.1
+		if method == "CONNECT" {
+			return Ok((target.to_string(), Some(target.to_string())));
+		}
 
-			line_vec.remove(0);
-			let val_raw = line_vec.join(":");
-			let val = val_raw.trim();
-			let rd: RequestData = RequestData{key: key.to_string(), value: val.to_string()};
-			request_data.push(rd);
+		// absolute-form: http://host/path?query
+		let lower_target = target.to_lowercase();
+		let scheme_len = if lower_target.starts_with("http://") {
+			7
+		}
+		else if lower_target.starts_with("https://") {
+			8
 		}
+		else {
+			return Err(status::BAD_REQUEST);
+		};
 
-		let post_data = match lines.next() {
-			Some(data) => data.to_string(),
-			None => "".to_string()
+		let rest = &target[scheme_len..];
+		let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+		let authority = &rest[..authority_end];
+		if authority.is_empty() {
+			return Err(status::BAD_REQUEST);
+		}
+
+		let req_path = match &rest[authority_end..] {
+			"" => "/".to_string(),
+			p if p.starts_with('?') => "/".to_string() + p,
+			p => p.to_string(),
 		};
 
-		Ok(Request {
-			method: request_line_vec[0].to_lowercase(),
-			req_path: request_line_vec[1].to_string(),
-			http_ver: request_line_vec[2].to_string(),
-			header: request_data,
-			content: post_data
+		Ok((req_path, Some(authority.to_string())))
+	}
+
+	pub fn parse_header_field(line: &[u8]) -> Result<RequestData, HttpStatus> {
+		let colon = match line.iter().position(|b| *b == b':') {
+			Some(pos) => pos,
+			None => return Err(status::BAD_REQUEST),
+		};
+
+		// No whitespace allowed between the field name and colon.
+		let key = &line[..colon];
+		if key.is_empty() || !key.iter().all(|b| is_tchar(*b)) {
+			return Err(status::BAD_REQUEST);
+		}
+
+		let value = &line[colon + 1..];
+		if value.iter().any(|b| (*b < b' ' && *b != b'\t') || *b == 0x7f) {
+			return Err(status::BAD_REQUEST);
+		}
+
+		Ok(RequestData {
+			key: String::from_utf8_lossy(key).into_owned(),
+			value: String::from_utf8_lossy(value).trim_matches(|c| c == ' ' || c == '\t').to_string()
 		})
 	}
 
+	// Find how the body is delimited (RFC 7230 3.3.3). Both `Transfer-Encoding`
+	// and `Content-Length` is a smuggling attempt, refuse it.
+	fn get_body(&self, req: &Request) -> Result<Body, HttpStatus> {
+		let codings: Vec<String> = req.header.iter()
+			.filter(|rd| rd.key.eq_ignore_ascii_case("Transfer-Encoding"))
+			.flat_map(|rd| rd.value.split(','))
+			.map(|coding| coding.trim().to_lowercase())
+			.filter(|coding| !coding.is_empty())
+			.collect();
+
+		if codings.is_empty() {
+			let content_len = RequestParser::get_content_length(req)?;
+			if content_len > self.max_body_size {
+				return Err(status::PAYLOAD_TOO_LARGE);
+			}
+			return Ok(Body::Length(content_len));
+		}
+
+		let has_content_length = req.header.iter()
+			.any(|rd| rd.key.eq_ignore_ascii_case("Content-Length"));
+		if has_content_length || req.http_ver == "HTTP/1.0" {
+			return Err(status::BAD_REQUEST);
+		}
+
+		// Chunked must be the last coding, otherwise the body length is unknown.
+		if codings.last().map(|c| c.as_str()) != Some("chunked") {
+			return Err(status::BAD_REQUEST);
+		}
+
+		// Other codings (gzip, ...) under chunked are not decoded.
+		if codings.len() > 1 {
+			return Err(status::NOT_IMPLEMENTED);
+		}
+
+		Ok(Body::Chunked(ChunkedDecoder::new(self.max_header_size, self.max_header_count)))
+	}
+
+	// `Expect: 100-continue` of an HTTP/1.1 request with a body. It is the only
+	// expectation defined, others get 417. HTTP/1.0 clients don't wait for it.
+	fn expects_continue(req: &Request) -> Result<bool, HttpStatus> {
+		let mut expects = false;
+		for rd in req.header.iter().filter(|rd| rd.key.eq_ignore_ascii_case("Expect")) {
+			if !rd.value.eq_ignore_ascii_case("100-continue") {
+				return Err(status::EXPECTATION_FAILED);
+			}
+			expects = true;
+		}
+		Ok(expects && req.http_ver == "HTTP/1.1")
+	}
+
+	// Body length from `Content-Length`, repeated fields must agree.
+	fn get_content_length(req: &Request) -> Result<usize, HttpStatus> {
+		let mut content_len: Option<usize> = None;
+		for rd in req.header.iter().filter(|rd| rd.key.eq_ignore_ascii_case("Content-Length")) {
+			for value in rd.value.split(',') {
+				let value = value.trim();
+				if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+					return Err(status::BAD_REQUEST);
+				}
+
+				let len: usize = match value.parse() {
+					Ok(len) => len,
+					Err(_) => return Err(status::BAD_REQUEST),
+				};
+
+				match content_len {
+					Some(prev) if prev != len => return Err(status::BAD_REQUEST),
+					_ => content_len = Some(len),
+				}
+			}
+		}
+
+		Ok(content_len.unwrap_or(0))
+	}
+}
+
+impl Default for RequestParser {
+	fn default() -> RequestParser {
+		RequestParser::new()
+	}
+}
+
+// Characters allowed in methods and header field names (RFC 7230 token).
+fn is_tchar(b: u8) -> bool {
+	b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+impl Request {
+	// Parse a complete request held in `raw_req`.
+	pub fn new(raw_req: &[u8]) -> Result<Request, HttpStatus> {
+		let mut buffer = raw_req.to_vec();
+		match RequestParser::new().parse(&mut buffer)? {
+			Some(req) => Ok(req),
+			None => Err(status::BAD_REQUEST),
+		}
+	}
+
 	pub fn get_header(&self, key: String) -> Result<String, Error> {
 		for rd in self.header.iter() {
 			if key.to_lowercase() == rd.key.to_lowercase() {
 				return Ok(rd.value.to_owned());
 			}
 		}
-		Err(Error::new(ErrorKind::Other, "field not found"))
+		Err(Error::other("field not found"))
 	}
 
-	pub fn is_valid_http_request(req: &String) -> bool {
-		let lines: Vec<&str> = req.lines().collect();
-		if lines.len() < 3 {
-			return false;
-		}
-
-		let req_line: Vec<&str> = lines[0].split_whitespace().collect();
+	// HTTP/1.1 keeps the connection unless told to close, HTTP/1.0 only when asked.
+	pub fn is_keep_alive(&self) -> bool {
+		let connection = self.get_header("Connection".to_string())
+			.unwrap_or_default()
+			.to_lowercase();
+		let tokens: Vec<&str> = connection.split(',').map(|t| t.trim()).collect();
 
-		if req_line.len() != 3 {
+		if tokens.contains(&"close") {
 			false
 		}
-		else if !vec!["GET", "POST"].contains(&req_line[0].to_uppercase().as_str()) {
-			// use vector to be easy to upgrade,
-			// only support for GET and POST method.
-			false
+		else if self.http_ver == "HTTP/1.1" {
+			true
 		}
 		else {
-			true
+			tokens.contains(&"keep-alive")
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse_all(parser: &mut RequestParser, raw: &[u8]) -> Result<Option<Request>, HttpStatus> {
+		let mut buffer = raw.to_vec();
+		parser.parse(&mut buffer)
+	}
+
+	#[test]
+	fn parses_a_request_in_pieces() {
+		let mut parser = RequestParser::new();
+		let mut buffer = b"GET /a?b=1 HTT".to_vec();
+		assert!(parser.parse(&mut buffer).unwrap().is_none());
+		buffer.extend_from_slice(b"P/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nab");
+		assert!(parser.parse(&mut buffer).unwrap().is_none());
+		buffer.extend_from_slice(b"cGET / HTTP/1.1\r\n\r\n");
+
+		let req = parser.parse(&mut buffer).unwrap().unwrap();
+		assert_eq!(req.method, "GET");
+		assert_eq!(req.req_path, "/a?b=1");
+		assert_eq!(req.get_header("host".to_string()).unwrap(), "x");
+		assert_eq!(req.content, b"abc");
+		// The pipelined request is left for the next call.
+		assert_eq!(buffer, b"GET / HTTP/1.1\r\n\r\n");
+	}
+
+	#[test]
+	fn long_request_line_is_414() {
+		let target = "/".to_string() + &"a".repeat(9000);
+		let mut parser = RequestParser::new();
+		// Alone, then together with its header in one read.
+		let raw = format!("GET {}", target);
+		assert_eq!(parse_all(&mut parser, raw.as_bytes()).unwrap_err(), status::URI_TOO_LONG);
+		let mut parser = RequestParser::new();
+		let raw = format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target);
+		assert_eq!(parse_all(&mut parser, raw.as_bytes()).unwrap_err(), status::URI_TOO_LONG);
+	}
+
+	#[test]
+	fn big_header_block_is_431() {
+		let raw = format!("GET / HTTP/1.1\r\nX-A: {}\r\n\r\n", "a".repeat(9000));
+		let mut parser = RequestParser::new();
+		assert_eq!(
+			parse_all(&mut parser, raw.as_bytes()).unwrap_err(),
+			status::REQUEST_HEADER_FIELDS_TOO_LARGE
+		);
+		let mut parser = RequestParser::with_limits(8192, 2);
+		let raw = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+		assert_eq!(parse_all(&mut parser, raw).unwrap_err(), status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+	}
+
+	#[test]
+	fn refuses_smuggling_and_bad_lengths() {
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n";
+		assert_eq!(parse_all(&mut RequestParser::new(), raw).unwrap_err(), status::BAD_REQUEST);
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n";
+		assert_eq!(parse_all(&mut RequestParser::new(), raw).unwrap_err(), status::BAD_REQUEST);
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n";
+		assert_eq!(parse_all(&mut RequestParser::new(), raw).unwrap_err(), status::BAD_REQUEST);
+
+		let mut parser = RequestParser::new();
+		parser.set_max_body_size(10);
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\n";
+		assert_eq!(parse_all(&mut parser, raw).unwrap_err(), status::PAYLOAD_TOO_LARGE);
+	}
+
+	#[test]
+	fn asks_for_the_body_with_100_continue() {
+		let mut parser = RequestParser::new();
+		let mut buffer = b"PUT /a HTTP/1.1\r\nExpect: 100-Continue\r\nContent-Length: 2\r\n\r\n".to_vec();
+		assert!(parser.parse(&mut buffer).unwrap().is_none());
+		assert!(parser.take_continue());
+		assert!(!parser.take_continue());
+		buffer.extend_from_slice(b"ab");
+		assert_eq!(parser.parse(&mut buffer).unwrap().unwrap().content, b"ab");
+
+		// The body came along, or there is none: nothing to ask for.
+		let mut buffer = b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nab".to_vec();
+		assert!(parser.parse(&mut buffer).unwrap().is_some());
+		assert!(!parser.take_continue());
+		let mut buffer = b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\n\r\n".to_vec();
+		assert!(parser.parse(&mut buffer).unwrap().is_some());
+		assert!(!parser.take_continue());
+
+		// HTTP/1.0 clients don't wait for it.
+		let mut buffer = b"PUT /a HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n".to_vec();
+		assert!(parser.parse(&mut buffer).unwrap().is_none());
+		assert!(!parser.take_continue());
+	}
+
+	#[test]
+	fn refuses_expectations_right_away() {
+		let raw = b"PUT /a HTTP/1.1\r\nExpect: 200-ok\r\nContent-Length: 2\r\n\r\n";
+		assert_eq!(parse_all(&mut RequestParser::new(), raw).unwrap_err(), status::EXPECTATION_FAILED);
+
+		let mut parser = RequestParser::new();
+		parser.set_max_body_size(10);
+		let raw = b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 11\r\n\r\n";
+		assert_eq!(parse_all(&mut parser, raw).unwrap_err(), status::PAYLOAD_TOO_LARGE);
+		assert!(!parser.take_continue());
+	}
+
+	#[test]
+	fn tells_when_a_request_is_coming_in() {
+		let mut parser = RequestParser::new();
+		let mut buffer = b"\r\n".to_vec();
+		assert!(!parser.is_receiving(&buffer));
+		buffer.extend_from_slice(b"GE");
+		assert!(parser.is_receiving(&buffer));
+
+		// Header parsed, the body not there yet.
+		buffer.extend_from_slice(b"T / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\n");
+		assert!(parser.parse(&mut buffer).unwrap().is_none());
+		assert!(buffer.is_empty());
+		assert!(parser.is_receiving(&buffer));
+
+		buffer.extend_from_slice(b"body");
+		assert!(parser.parse(&mut buffer).unwrap().is_some());
+		assert!(!parser.is_receiving(&buffer));
+	}
+}
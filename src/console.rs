@@ -0,0 +1,209 @@
+/*
+ *	Admin commands read from stdin. With stdin not a terminal (a service
+ *	manager, a pipe) there is no prompt nor line editing, and the end of
+ *	input leaves the server running without a console.
+ */
+use std::io;
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use line_editor::LineEditor;
+use server_side::live::LiveConfig;
+use server_side::log;
+use server_side::log::Level;
+use server_side::monitor::Monitor;
+
+const HELP: &str = "Commands:
+  status              Uptime, open connections and requests served.
+  stats               Counters since start.
+  connections         Open connections: peer, age, requests and current path.
+  reload              Read the configuration again.
+  loglevel [<level>]  Show or set the log level until the next reload:
+                      error, warn, info or debug.
+  kick <ip>           Close the connections of <ip>.
+  ban [<ip>]          Close the connections of <ip> and refuse new ones,
+                      or list the banned addresses.
+  unban <ip>          Accept <ip> again.
+  help                Show this help.
+  quit                Stop the server once the requests being answered
+                      are done, see drain_timeout.";
+
+const COMMANDS: &[&str] = &[
+	"status", "stats", "connections", "reload", "loglevel", "kick", "ban", "unban", "help", "quit", "exit"
+];
+
+pub struct Console {
+	live: Arc<LiveConfig>,
+	monitor: Arc<Monitor>,
+	interactive: bool
+}
+
+// What the console stopped on.
+#[derive(Debug, PartialEq)]
+pub enum Exit {
+	Quit,
+	EndOfInput
+}
+
+impl Console {
+	pub fn new(live: Arc<LiveConfig>, monitor: Arc<Monitor>) -> Console {
+		Console { live, monitor, interactive: io::stdin().is_terminal() }
+	}
+
+	pub fn is_interactive(&self) -> bool {
+		self.interactive
+	}
+
+	// Run commands until `quit` or the end of input.
+	pub fn run(&self) -> Exit {
+		let mut editor = LineEditor::new();
+		loop {
+			let read = if self.interactive {
+				editor.read_line("aden> ")
+			} else {
+				read_line()
+			};
+			let line = match read {
+				Ok(Some(line)) => line,
+				Ok(None) => return Exit::EndOfInput,
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				Err(e) => {
+					log::warn(format_args!("Can't read console commands because: {}", e));
+					return Exit::EndOfInput;
+				}
+			};
+
+			if self.execute(line.trim()) == Some(Exit::Quit) {
+				return Exit::Quit;
+			}
+		}
+	}
+
+	fn execute(&self, line: &str) -> Option<Exit> {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		match (words.first().cloned().unwrap_or(""), words.len()) {
+			("", _) => {},
+			("help", 1) => println!("{}", HELP),
+			("status", 1) => self.print_status(),
+			("stats", 1) => self.print_stats(),
+			("connections", 1) => self.print_connections(),
+			("reload", 1) => match self.live.reload() {
+				Ok(()) => println!("Configuration reloaded."),
+				Err(e) => println!("E: Reload failed, still running the previous configuration:\n{}", e)
+			},
+			("loglevel", 1) => println!("Log level: {}.", log::get_level().get_name()),
+			("loglevel", 2) => match Level::from_name(words[1]) {
+				Some(level) => {
+					log::set_level(level);
+					println!("Log level: {}.", level.get_name());
+				},
+				None => println!("E: Expected `error`, `warn`, `info` or `debug`, not `{}`.", words[1])
+			},
+			("kick", 2) => if let Some(ip) = parse_ip(words[1]) {
+				println!("Closed {} connection(s) of {}.", self.monitor.kick(ip), ip);
+			},
+			("ban", 1) => {
+				let banned = self.monitor.get_banned();
+				if banned.is_empty() {
+					println!("No address is banned.");
+				}
+				for ip in banned {
+					println!("{}", ip);
+				}
+			},
+			("ban", 2) => if let Some(ip) = parse_ip(words[1]) {
+				if !self.monitor.ban(ip) {
+					println!("{} is already banned.", ip);
+				} else {
+					println!("Banned {}, closed {} connection(s).", ip, self.monitor.kick(ip));
+				}
+			},
+			("unban", 2) => if let Some(ip) = parse_ip(words[1]) {
+				if self.monitor.unban(ip) {
+					println!("{} is accepted again.", ip);
+				} else {
+					println!("{} is not banned.", ip);
+				}
+			},
+			("quit", 1) | ("exit", 1) => return Some(Exit::Quit),
+			(command, _) if COMMANDS.contains(&command) =>
+				println!("E: Wrong arguments for `{}`, type `help`.", command),
+			(command, _) => println!("E: Unknown command `{}`, type `help`.", command)
+		}
+		None
+	}
+
+	fn print_status(&self) {
+		let snapshot = self.live.get();
+		let stats = self.monitor.get_stats();
+		println!("Up {}, {} connection(s) open, {} request(s) served.",
+			format_duration(self.monitor.get_uptime()), self.monitor.get_connections().len(),
+			stats.requests);
+		println!("Listening on {}:{} with io_engine {}, log level {}, configuration `{}`.",
+			snapshot.config.server.address, snapshot.config.server.port,
+			snapshot.config.server.io_engine, log::get_level().get_name(),
+			snapshot.config.path.display());
+	}
+
+	fn print_stats(&self) {
+		let stats = self.monitor.get_stats();
+		let average = match stats.requests {
+			0 => 0.0,
+			n => stats.response_time.as_secs_f64() * 1000.0 / n as f64
+		};
+		println!("Connections accepted: {}", stats.accepted);
+		println!("Requests served:      {} ({:.3} ms on average)", stats.requests, average);
+		for (i, count) in stats.statuses.iter().enumerate() {
+			println!("  {}xx:                {}", i + 1, count);
+		}
+		println!("Busy (503):           {}", stats.busy);
+		println!("Refused (banned):     {}", stats.refused);
+		println!("Kicked:               {}", stats.kicked);
+	}
+
+	fn print_connections(&self) {
+		let connections = self.monitor.get_connections();
+		if connections.is_empty() {
+			println!("No connection is open.");
+		}
+		for conn in connections {
+			println!("{:<40} {:>8} {:>5} req  {}", conn.peer, format_duration(conn.since.elapsed()),
+				conn.requests, match conn.path {
+					Some(ref path) => path.as_str(),
+					None if conn.pending => "(pending)",
+					None => "(idle)"
+				});
+		}
+	}
+}
+
+// A line from stdin as it comes, None at the end of input.
+fn read_line() -> io::Result<Option<String>> {
+	let mut line = String::new();
+	match io::stdin().lock().read_line(&mut line)? {
+		0 => Ok(None),
+		_ => Ok(Some(line))
+	}
+}
+
+fn parse_ip(word: &str) -> Option<IpAddr> {
+	match word.parse() {
+		Ok(ip) => Some(ip),
+		Err(_) => {
+			println!("E: `{}` is not an IP address.", word);
+			None
+		}
+	}
+}
+
+// `1h 2m 3s`
+fn format_duration(duration: Duration) -> String {
+	let secs = duration.as_secs();
+	match (secs / 3600, secs / 60 % 60, secs % 60) {
+		(0, 0, s) => format!("{}s", s),
+		(0, m, s) => format!("{}m {}s", m, s),
+		(h, m, s) => format!("{}h {}m {}s", h, m, s)
+	}
+}
@@ -0,0 +1,424 @@
+/*
+ *	Line editing for the console on a terminal: the cursor moves with the
+ *	arrow keys, Home and End, Backspace and Delete remove a char, Ctrl-U the
+ *	whole line, and Up and Down go through the lines entered before.
+ *
+ *	The terminal is put out of canonical mode and echo only while a line is
+ *	read, signals stay on so Ctrl-C still stops the server. Where termios
+ *	can't be used, lines are read as they come.
+ */
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::sync::Mutex;
+
+// Lines kept for Up and Down.
+const MAX_HISTORY: usize = 100;
+
+#[cfg(target_os = "linux")]
+mod termios {
+	use std::io;
+	use std::os::raw::c_int;
+
+	pub const ICANON: u32 = 0o2;
+	pub const ECHO: u32 = 0o10;
+	pub const IEXTEN: u32 = 0o100000;
+	pub const VTIME: usize = 5;
+	pub const VMIN: usize = 6;
+	const TCSANOW: c_int = 0;
+	const STDIN: c_int = 0;
+
+	// struct termios of glibc and musl.
+	#[repr(C)]
+	#[derive(Clone, Copy)]
+	pub struct Termios {
+		pub c_iflag: u32,
+		pub c_oflag: u32,
+		pub c_cflag: u32,
+		pub c_lflag: u32,
+		pub c_line: u8,
+		pub c_cc: [u8; 32],
+		pub c_ispeed: u32,
+		pub c_ospeed: u32
+	}
+
+	extern "C" {
+		fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+		fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+	}
+
+	pub fn get() -> io::Result<Termios> {
+		let mut termios = Termios { c_iflag: 0, c_oflag: 0, c_cflag: 0, c_lflag: 0, c_line: 0,
+			c_cc: [0; 32], c_ispeed: 0, c_ospeed: 0 };
+		if unsafe { tcgetattr(STDIN, &mut termios) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(termios)
+	}
+
+	pub fn set(termios: &Termios) -> io::Result<()> {
+		if unsafe { tcsetattr(STDIN, TCSANOW, termios) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+}
+
+// Settings of the terminal before a line is read, until they are put back.
+#[cfg(target_os = "linux")]
+static SAVED: Mutex<Option<termios::Termios>> = Mutex::new(None);
+
+// The terminal as it was before, to call before the process exits: the
+// console thread may be waiting for a key.
+#[cfg(target_os = "linux")]
+pub fn restore_terminal() {
+	if let Some(saved) = SAVED.lock().unwrap().take() {
+		termios::set(&saved);
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restore_terminal() {}
+
+// Keys read one at a time, echo off, until dropped.
+struct RawMode;
+
+impl RawMode {
+	#[cfg(target_os = "linux")]
+	fn enable() -> io::Result<RawMode> {
+		let saved = termios::get()?;
+		let mut raw = saved;
+		raw.c_lflag &= !(termios::ICANON | termios::ECHO | termios::IEXTEN);
+		raw.c_cc[termios::VMIN] = 1;
+		raw.c_cc[termios::VTIME] = 0;
+		*SAVED.lock().unwrap() = Some(saved);
+		termios::set(&raw)?;
+		Ok(RawMode)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn enable() -> io::Result<RawMode> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "no termios here"))
+	}
+}
+
+impl Drop for RawMode {
+	fn drop(&mut self) {
+		restore_terminal();
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+	Char(char),
+	Enter,
+	Backspace,
+	Delete,
+	Left,
+	Right,
+	Home,
+	End,
+	Up,
+	Down,
+	// Ctrl-U
+	Kill,
+	// Ctrl-D
+	EndOfInput,
+	Ignored
+}
+
+// What a key did to the line.
+#[derive(Debug, PartialEq)]
+enum Step {
+	Edited,
+	Submit,
+	EndOfInput
+}
+
+// The line being entered.
+struct Edit {
+	line: Vec<char>,
+	cursor: usize,
+	// Line of the history shown, `history.len()` for the new one.
+	shown: usize,
+	// The new line, while older ones are shown.
+	draft: Vec<char>
+}
+
+pub struct LineEditor {
+	history: Vec<String>,
+	// Termios refused once: read whole lines from then on.
+	plain: bool
+}
+
+impl LineEditor {
+	pub fn new() -> LineEditor {
+		LineEditor { history: vec![], plain: false }
+	}
+
+	// Show `prompt` and read a line, None at the end of input.
+	pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+		print!("{}", prompt);
+		io::stdout().flush();
+
+		let raw_mode = if self.plain { None } else { RawMode::enable().ok() };
+		if raw_mode.is_none() {
+			self.plain = true;
+			let mut line = String::new();
+			return match io::stdin().lock().read_line(&mut line)? {
+				0 => Ok(None),
+				_ => Ok(Some(line))
+			};
+		}
+
+		let stdin = io::stdin();
+		let mut input = stdin.lock();
+		let mut edit = Edit { line: vec![], cursor: 0, shown: self.history.len(), draft: vec![] };
+		loop {
+			let key = match read_key(&mut input)? {
+				Some(key) => key,
+				None if edit.line.is_empty() => return Ok(None),
+				None => Key::Enter
+			};
+
+			match self.apply(&mut edit, key) {
+				Step::Edited => redraw(prompt, &edit),
+				Step::Submit => {
+					println!();
+					let line: String = edit.line.iter().collect();
+					self.remember(&line);
+					return Ok(Some(line));
+				},
+				Step::EndOfInput => {
+					println!();
+					return Ok(None);
+				}
+			}
+		}
+	}
+
+	fn apply(&self, edit: &mut Edit, key: Key) -> Step {
+		match key {
+			Key::Char(c) => {
+				edit.line.insert(edit.cursor, c);
+				edit.cursor += 1;
+			},
+			Key::Enter => return Step::Submit,
+			Key::Backspace if edit.cursor > 0 => {
+				edit.cursor -= 1;
+				edit.line.remove(edit.cursor);
+			},
+			Key::Delete if edit.cursor < edit.line.len() => {
+				edit.line.remove(edit.cursor);
+			},
+			Key::Left if edit.cursor > 0 => edit.cursor -= 1,
+			Key::Right if edit.cursor < edit.line.len() => edit.cursor += 1,
+			Key::Home => edit.cursor = 0,
+			Key::End => edit.cursor = edit.line.len(),
+			Key::Up if edit.shown > 0 => {
+				if edit.shown == self.history.len() {
+					edit.draft = edit.line.clone();
+				}
+				edit.shown -= 1;
+				edit.line = self.history[edit.shown].chars().collect();
+				edit.cursor = edit.line.len();
+			},
+			Key::Down if edit.shown < self.history.len() => {
+				edit.shown += 1;
+				edit.line = match self.history.get(edit.shown) {
+					Some(line) => line.chars().collect(),
+					None => edit.draft.clone()
+				};
+				edit.cursor = edit.line.len();
+			},
+			Key::Kill => {
+				edit.line.clear();
+				edit.cursor = 0;
+			},
+			// Like a terminal: the end of input on an empty line only.
+			Key::EndOfInput if edit.line.is_empty() => return Step::EndOfInput,
+			Key::EndOfInput => return self.apply(edit, Key::Delete),
+			_ => {}
+		}
+		Step::Edited
+	}
+
+	// Keep `line` for Up, without blank lines or the same line twice in a row.
+	fn remember(&mut self, line: &str) {
+		let line = line.trim();
+		if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+			return;
+		}
+		if self.history.len() == MAX_HISTORY {
+			self.history.remove(0);
+		}
+		self.history.push(line.to_string());
+	}
+}
+
+// Write the prompt and the line again, the cursor where it is in the line.
+fn redraw(prompt: &str, edit: &Edit) {
+	let line: String = edit.line.iter().collect();
+	let mut out = format!("\r{}{}\x1b[K", prompt, line);
+	let back = edit.line.len() - edit.cursor;
+	if back > 0 {
+		out += &format!("\x1b[{}D", back);
+	}
+	print!("{}", out);
+	io::stdout().flush();
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+	let mut byte = [0];
+	loop {
+		match input.read(&mut byte) {
+			Ok(0) => return Ok(None),
+			Ok(_) => return Ok(Some(byte[0])),
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+			Err(e) => return Err(e)
+		}
+	}
+}
+
+// Next key from the terminal, None at the end of input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+	let byte = match read_byte(input)? {
+		Some(byte) => byte,
+		None => return Ok(None)
+	};
+
+	let key = match byte {
+		b'\r' | b'\n' => Key::Enter,
+		0x7f | 0x08 => Key::Backspace,
+		0x01 => Key::Home,
+		0x04 => Key::EndOfInput,
+		0x05 => Key::End,
+		0x15 => Key::Kill,
+		0x1b => read_escape(input)?,
+		0x00..=0x1f => Key::Ignored,
+		0x20..=0x7e => Key::Char(byte as char),
+		_ => {
+			// The rest of a UTF-8 char, its length told by the first byte.
+			let len = match byte {
+				0xc0..=0xdf => 2,
+				0xe0..=0xef => 3,
+				0xf0..=0xf7 => 4,
+				_ => return Ok(Some(Key::Ignored))
+			};
+			let mut bytes = vec![byte];
+			for _ in 1..len {
+				match read_byte(input)? {
+					Some(byte) => bytes.push(byte),
+					None => return Ok(None)
+				}
+			}
+			match std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()) {
+				Some(c) => Key::Char(c),
+				None => Key::Ignored
+			}
+		}
+	};
+	Ok(Some(key))
+}
+
+// After ESC: `ESC [ <digits> <final>` or `ESC O <final>`.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+	match read_byte(input)? {
+		Some(b'[') | Some(b'O') => {},
+		_ => return Ok(Key::Ignored)
+	}
+
+	let mut param = String::new();
+	loop {
+		let byte = match read_byte(input)? {
+			Some(byte) => byte,
+			None => return Ok(Key::Ignored)
+		};
+		if !(0x40..=0x7e).contains(&byte) {
+			param.push(byte as char);
+			continue;
+		}
+
+		return Ok(match (byte, param.as_str()) {
+			(b'A', _) => Key::Up,
+			(b'B', _) => Key::Down,
+			(b'C', _) => Key::Right,
+			(b'D', _) => Key::Left,
+			(b'H', _) | (b'~', "1") | (b'~', "7") => Key::Home,
+			(b'F', _) | (b'~', "4") | (b'~', "8") => Key::End,
+			(b'~', "3") => Key::Delete,
+			_ => Key::Ignored
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn keys(input: &[u8]) -> Vec<Key> {
+		let mut input = input;
+		let mut keys = vec![];
+		while let Some(key) = read_key(&mut input).unwrap() {
+			keys.push(key);
+		}
+		keys
+	}
+
+	// The line after typing `input`, and how it ended.
+	fn type_keys(editor: &LineEditor, input: &[u8]) -> (String, Step) {
+		let mut edit = Edit { line: vec![], cursor: 0, shown: editor.history.len(), draft: vec![] };
+		let mut step = Step::Edited;
+		for key in keys(input) {
+			step = editor.apply(&mut edit, key);
+			if step != Step::Edited {
+				break;
+			}
+		}
+		(edit.line.iter().collect(), step)
+	}
+
+	#[test]
+	fn decodes_keys() {
+		assert_eq!(keys(b"a\x7f\r"), vec![Key::Char('a'), Key::Backspace, Key::Enter]);
+		assert_eq!(keys(b"\x1b[A\x1b[B\x1b[C\x1b[D\x1bOH\x1b[F"),
+			vec![Key::Up, Key::Down, Key::Right, Key::Left, Key::Home, Key::End]);
+		assert_eq!(keys(b"\x1b[3~\x1b[1~\x1b[4~\x1b[1;5C\x1b[15~"),
+			vec![Key::Delete, Key::Home, Key::End, Key::Right, Key::Ignored]);
+		assert_eq!(keys("é€\x15\x04\x07".as_bytes()),
+			vec![Key::Char('é'), Key::Char('€'), Key::Kill, Key::EndOfInput, Key::Ignored]);
+		assert_eq!(keys(b"\xff\xc3"), vec![Key::Ignored]);
+	}
+
+	#[test]
+	fn edits_at_the_cursor() {
+		let editor = LineEditor::new();
+		assert_eq!(type_keys(&editor, b"stxtus\x1b[D\x1b[D\x1b[D\x7fa\r"), ("status".to_string(), Step::Submit));
+		assert_eq!(type_keys(&editor, b"xban\x01\x1b[3~\x05 1\r"), ("ban 1".to_string(), Step::Submit));
+		assert_eq!(type_keys(&editor, b"\x7f\x1b[3~\x1b[D\x1b[Cab\x1b[C\x1b[C\x7f\r"), ("a".to_string(), Step::Submit));
+		assert_eq!(type_keys(&editor, b"kick 10.0.0.1\x15help\r"), ("help".to_string(), Step::Submit));
+		assert_eq!(type_keys(&editor, b"ab\x1b[D\x04\r"), ("a".to_string(), Step::Submit));
+		assert_eq!(type_keys(&editor, b"\x04"), (String::new(), Step::EndOfInput));
+	}
+
+	#[test]
+	fn goes_through_history() {
+		let mut editor = LineEditor::new();
+		for line in ["status", "", "stats", "stats", "  connections "] {
+			editor.remember(line);
+		}
+		assert_eq!(editor.history, vec!["status", "stats", "connections"]);
+
+		assert_eq!(type_keys(&editor, b"\x1b[A\r").0, "connections");
+		assert_eq!(type_keys(&editor, b"\x1b[A\x1b[A\x1b[A\x1b[A\r").0, "status");
+		assert_eq!(type_keys(&editor, b"\x1b[A\x1b[Ax\r").0, "statsx");
+		// Down past the newest line brings back what was being typed.
+		assert_eq!(type_keys(&editor, b"rel\x1b[A\x1b[A\x1b[B\x1b[Boad\r").0, "reload");
+		assert_eq!(type_keys(&editor, b"\x1b[B\r").0, "");
+
+		for i in 0..MAX_HISTORY + 5 {
+			editor.remember(&format!("kick 10.0.0.{}", i));
+		}
+		assert_eq!(editor.history.len(), MAX_HISTORY);
+		assert_eq!(editor.history[0], "kick 10.0.0.5");
+	}
+}
@@ -6,7 +6,7 @@
 #![allow(unused_assignments)]
 #![macro_use]
 
-/**
+/*
  *	KPenter SERVER version 0.1
  *	Author: Nick Lauri
  *	Email: khoanta.96@gmail.com
@@ -14,48 +14,214 @@
  */
 
 use std::io::Read;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod version;
+mod cli;
+mod console;
+mod line_editor;
 mod server_side;
 mod client_side;
 
-use server_side::config::Configuration;
+use cli::{Action, Options};
+use console::{Console, Exit};
+use server_side::config::{Configuration, Override};
+use server_side::live::{ConfigSource, LiveConfig, Snapshot};
+use server_side::log;
+use server_side::signal;
+use server_side::stopper::Drained;
+use server_side::utils;
+
+// How often a stop is looked for.
+const STOP_TICK: Duration = Duration::from_millis(100);
 
 fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let options = match Options::parse(&args) {
+		Ok(options) => options,
+		Err(e) => {
+			println!("E: {}.\nSee `aden --help`.", e);
+			process::exit(2);
+		}
+	};
+
+	match options.action {
+		Action::Help => {
+			println!("{} {}: {}\n\n{}", version::PRODUCT_NAME, version::VERSION,
+				version::PRODUCT_DESCRIPTION, cli::USAGE);
+			process::exit(0);
+		},
+		Action::Version => {
+			println!("{} {}", version::PRODUCT_NAME, version::VERSION);
+			process::exit(0);
+		},
+		_ => {}
+	}
+
+	// The command line wins over the environment.
+	let mut overrides = Configuration::get_env_overrides();
+	overrides.extend(options.overrides);
+	let source = match get_config_source(options.root.or(options.serve_dir.clone()),
+		options.serve_dir.is_some(), options.config_path, overrides) {
+		Ok(source) => source,
+		Err(e) => {
+			println!("E: {}", e);
+			process::exit(2);
+		}
+	};
+
+	if options.action == Action::DumpConfig {
+		match Snapshot::load(&source, None) {
+			Ok(snapshot) => print!("{}", snapshot.config.dump()),
+			Err(e) => {
+				println!("E: Configuration has errors:\n{}", e);
+				process::exit(1);
+			}
+		}
+		process::exit(0);
+	}
+
+	if options.action == Action::TestConfig {
+		process::exit(match check_configuration(&source) {
+			Ok(path) => {
+				println!("Configuration `{}` is OK.", path);
+				0
+			},
+			Err(e) => {
+				println!("E: Configuration has errors:\n{}", e);
+				1
+			}
+		});
+	}
+
 	println!("KProject Aden Server version {}\nCopyright (c) by {} <{}> {}\nWritten in Rust <3",
 		version::VERSION, version::AUTHOR_NAME, version::AUTHOR_EMAIL,
 		version::PRODUCT_RELEASE_YEAR);
 
-	let config = match Configuration::new() {
-		Ok(c) => c,
+	let snapshot = match Snapshot::load(&source, None) {
+		Ok(s) => s,
 		Err(e) => {
-			println!("E: Can't start the server because of: {}", e.to_string());
-			return;
+			println!("E: Can't start the server because of:\n{}", e);
+			process::exit(1);
 		}
 	};
+	if let Err(e) = log::configure(&snapshot.config.logging) {
+		println!("E: Can't open the log file `{}` because: {}", snapshot.config.logging.file, e);
+		process::exit(1);
+	}
+	let live = Arc::new(LiveConfig::new(source, snapshot));
 
-	let server_address = config.get_value_or("server_address", "localhost");
-	let server_port = config.get_value_or("server_port", "8080");
-	let server = match server_side::Server::new(server_address.as_str(), server_port.as_str()) {
+	let server = match server_side::Server::new(&live) {
 		Ok(s) => s,
 		Err(e) => {
-			println!("The server can't start because: {}", e.to_string());
-			return;
+			println!("The server can't start because: {}", e);
+			process::exit(1);
+		}
+	};
+
+	let stopper = match server.get_stopper() {
+		Ok(stopper) => stopper,
+		Err(e) => {
+			println!("The server can't start because: {}", e);
+			process::exit(1);
 		}
 	};
+	for &signum in [signal::SIGINT, signal::SIGTERM].iter() {
+		if let Err(e) = signal::listen(signum) {
+			log::warn(format_args!("Can't stop gracefully on signal {} because: {}", signum, e));
+		}
+	}
 
-	println!("The server is running @ {}:{} . Type `quit` or Ctrl-C to quit the server.", server_address, server_port);
+	println!("The server is running @ {}:{} .", server.address, server.port);
+	let monitor = server.get_monitor();
+	LiveConfig::start_watcher(Arc::clone(&live));
 	std::thread::spawn(move || {
-		server.start_with_thread(&config);
+		server.start();
 	});
 
-	// Need module command.
-	loop {
-		let mut line = String::new();
-		std::io::stdin().read_line(&mut line);
-		if line.trim().starts_with("quit") {
-			println!("Exiting...");
-			std::process::exit(0);
+	let console = Console::new(Arc::clone(&live), monitor);
+	if console.is_interactive() {
+		println!("Type `help` for the console commands, `quit` or Ctrl-C to quit the server.");
+	}
+	let (quit_sender, quit_receiver) = mpsc::channel();
+	std::thread::spawn(move || {
+		if console.run() == Exit::Quit {
+			quit_sender.send(());
+		} else {
+			// No more input, as under a service manager.
+			log::info(format_args!("No console input, the server runs until it is stopped."));
+		}
+	});
+
+	wait_for_stop(&quit_receiver);
+	line_editor::restore_terminal();
+	println!("Exiting...");
+	let drain_timeout = live.get().config.server.drain_timeout;
+	let exit_code = match stopper.drain(drain_timeout, &is_stop_requested) {
+		Drained::Done => 0,
+		Drained::TimedOut(closed) => {
+			log::warn(format_args!("Drain timeout reached, closed {} connection(s).", closed));
+			0
+		},
+		Drained::Interrupted(closed) => {
+			log::warn(format_args!("Stopped at once, closed {} connection(s).", closed));
+			1
+		}
+	};
+	log::flush();
+	process::exit(exit_code);
+}
+
+// Block until `quit` on the console, SIGINT or SIGTERM.
+fn wait_for_stop(quit: &mpsc::Receiver<()>) {
+	let mut console_open = true;
+	while !is_stop_requested() {
+		if !console_open {
+			std::thread::sleep(STOP_TICK);
+			continue;
 		}
+		match quit.recv_timeout(STOP_TICK) {
+			Ok(()) => return,
+			Err(mpsc::RecvTimeoutError::Timeout) => {},
+			Err(mpsc::RecvTimeoutError::Disconnected) => console_open = false
+		}
+	}
+}
+
+fn is_stop_requested() -> bool {
+	signal::take(signal::SIGINT) | signal::take(signal::SIGTERM)
+}
+
+// Set the server root, `root` must be a directory, and tell where the
+// settings come from.
+fn get_config_source(root: Option<PathBuf>, builtin: bool, config_path: Option<PathBuf>,
+	overrides: Vec<Override>) -> Result<ConfigSource, String> {
+	if let Some(root) = root {
+		match std::fs::canonicalize(&root) {
+			Ok(ref path) if path.is_dir() => utils::set_root_path(path),
+			_ => return Err(format!("`{}` is not a directory", root.display()))
+		}
+	}
+
+	Ok(ConfigSource {
+		path: if builtin {
+			None
+		} else {
+			Some(config_path.unwrap_or_else(Configuration::get_default_path))
+		},
+		overrides
+	})
+}
+
+// Load everything the server would at start, without listening.
+fn check_configuration(source: &ConfigSource) -> Result<String, std::io::Error> {
+	let snapshot = Snapshot::load(source, None)?;
+	if !snapshot.config.logging.file.is_empty() {
+		log::configure(&snapshot.config.logging)?;
 	}
+	Ok(snapshot.config.path.display().to_string())
 }
@@ -0,0 +1,317 @@
+/*
+ *	Listings of directories without an index file, for the dirs of
+ *	`autoindex_dir`: an HTML page sortable by column, or JSON.
+ */
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use client_side::request::Request;
+use server_side::compress::Compression;
+use server_side::mimetype::Mimetype;
+use server_side::resolver::PathResolver;
+use server_side::response::Response;
+use server_side::utils;
+use server_side::xml;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortKey {
+    fn from_str_or(s: &str, default: SortKey) -> SortKey {
+        match s {
+            "name" => SortKey::Name,
+            "size" => SortKey::Size,
+            "modified" => SortKey::Modified,
+            "type" => SortKey::Type,
+            _ => default,
+        }
+    }
+
+    fn get_name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ListEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    // None for directories.
+    mime: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirListing {
+    // Http path of the directory, ending with `/`.
+    href: String,
+    entries: Vec<ListEntry>,
+}
+
+impl DirListing {
+    // What is in `dir`, but dotfiles, forbidden dirs and symlinks leading
+    // out of home_dir.
+    pub fn read(
+        href: &str,
+        dir: &Path,
+        resolver: &PathResolver,
+        mimetype: &Mimetype,
+    ) -> io::Result<DirListing> {
+        let mut entries = vec![];
+        for child in fs::read_dir(dir)? {
+            let child = child?;
+            let name = match child.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let path = child.path();
+            if name.starts_with('.')
+                || resolver.is_forbidden(&path)
+                || resolver.check_path(&path).is_err()
+            {
+                continue;
+            }
+            let meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+
+            let mime = if meta.is_dir() {
+                None
+            } else {
+                Some(mimetype.get_mimetype_or(&name, "application/octet-stream"))
+            };
+            entries.push(ListEntry {
+                name,
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified: meta.modified().ok(),
+                mime,
+            });
+        }
+
+        Ok(DirListing {
+            href: href.to_string(),
+            entries,
+        })
+    }
+
+    // Answer with the listing, as JSON for `?format=json` or clients
+    // preferring `application/json`, else as HTML.
+    pub fn set_response(
+        mut self,
+        req: &Request,
+        res: &mut Response,
+        query: &str,
+        compression: &Compression,
+    ) {
+        let sort = SortKey::from_str_or(
+            &utils::get_query_value(query, "sort").unwrap_or_default(),
+            SortKey::Name,
+        );
+        let descending = utils::get_query_value(query, "order").as_deref() == Some("desc");
+        self.sort(sort, descending);
+
+        let (body, content_type) = if wants_json(req, query) {
+            (self.get_json(), "application/json")
+        } else {
+            (self.get_html(sort, descending), "text/html; charset=utf-8")
+        };
+        let length = body.len() as u64;
+
+        res.set_response_text(Some("1.1"), Some(200), Some("OK"));
+        res.add_header("Server", "Aden 0.1");
+        res.add_header("Content-Type", content_type);
+        res.add_header("Vary", "Accept, Accept-Encoding");
+        res.add_content_from_string(body);
+        // Refused as it is: sent anyway, a listing is no file to refuse.
+        if let Ok(Some(coding)) = compression.choose(req, content_type, length) {
+            res.set_content_encoding(coding);
+        }
+    }
+
+    // Directories first, then by `key`.
+    fn sort(&mut self, key: SortKey, descending: bool) {
+        self.entries.sort_by(|a, b| {
+            let by_key = match key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+                SortKey::Type => a.mime.cmp(&b.mime),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let by_key = if descending { by_key.reverse() } else { by_key };
+            b.is_dir.cmp(&a.is_dir).then(by_key)
+        });
+    }
+
+    fn get_html(&self, sort: SortKey, descending: bool) -> String {
+        let title = format!("Index of {}", xml::escape(&self.href));
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\n\
+             <style>body{{font-family:sans-serif}}th,td{{padding:2px 12px;text-align:left}}\
+             td.size{{text-align:right}}</style></head>\n<body><h1>{0}</h1>\n<table>\n<tr>",
+            title
+        );
+
+        // A column sorts up, or down when it already sorts up.
+        for &(key, label) in [
+            (SortKey::Name, "Name"),
+            (SortKey::Size, "Size"),
+            (SortKey::Modified, "Last modified"),
+            (SortKey::Type, "Type"),
+        ]
+        .iter()
+        {
+            let order = if key == sort && !descending { "desc" } else { "asc" };
+            html += &format!(
+                "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+                key.get_name(),
+                order,
+                label
+            );
+        }
+        html += "</tr>\n";
+
+        if self.href != "/" {
+            html += "<tr><td><a href=\"../\">../</a></td><td></td><td></td><td></td></tr>\n";
+        }
+        for entry in self.entries.iter() {
+            let slash = if entry.is_dir { "/" } else { "" };
+            let size = if entry.is_dir {
+                "-".to_string()
+            } else {
+                entry.size.to_string()
+            };
+            let modified = entry.modified.map_or(String::new(), |modified| {
+                utils::format_rfc3339(modified)
+                    .replace('T', " ")
+                    .replace('Z', "")
+            });
+            html += &format!(
+                "<tr><td><a href=\"{0}{2}\">{1}{2}</a></td><td class=\"size\">{3}</td>\
+                 <td>{4}</td><td>{5}</td></tr>\n",
+                xml::escape(&utils::percent_encode_path(&entry.name)),
+                xml::escape(&entry.name),
+                slash,
+                size,
+                modified,
+                xml::escape(entry.mime.as_deref().unwrap_or("directory"))
+            );
+        }
+
+        html + "</table>\n</body></html>\n"
+    }
+
+    fn get_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let mut json = format!(
+                    "{{\"name\":{},\"type\":\"{}\"",
+                    json_string(&entry.name),
+                    if entry.is_dir { "directory" } else { "file" }
+                );
+                if !entry.is_dir {
+                    json += &format!(",\"size\":{}", entry.size);
+                }
+                if let Some(modified) = entry.modified {
+                    json += &format!(",\"modified\":\"{}\"", utils::format_rfc3339(modified));
+                }
+                if let Some(ref mime) = entry.mime {
+                    json += &format!(",\"mime\":{}", json_string(mime));
+                }
+                json + "}"
+            })
+            .collect();
+
+        format!(
+            "{{\"path\":{},\"entries\":[{}]}}\n",
+            json_string(&self.href),
+            entries.join(",")
+        )
+    }
+}
+
+// `?format=` decides, else `Accept` must rank JSON above HTML.
+fn wants_json(req: &Request, query: &str) -> bool {
+    match utils::get_query_value(query, "format").as_deref() {
+        Some("json") => return true,
+        Some("html") => return false,
+        _ => {}
+    }
+
+    match req.get_header("Accept".to_string()) {
+        Ok(accept) => {
+            get_media_qvalue(&accept, "application/json") > get_media_qvalue(&accept, "text/html")
+        }
+        Err(_) => false,
+    }
+}
+
+// q-value `Accept` gives to `media_type`, from its most specific match:
+// `type/subtype`, then `type/*`, then `*/*`.
+fn get_media_qvalue(accept: &str, media_type: &str) -> f32 {
+    let family = media_type.split('/').next().unwrap_or("").to_string() + "/*";
+    let mut best: Option<(u8, f32)> = None;
+
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim().to_lowercase();
+        let specificity = if name == media_type {
+            3
+        } else if name == family {
+            2
+        } else if name == "*/*" {
+            1
+        } else {
+            continue;
+        };
+
+        let mut q: f32 = 1.0;
+        for param in params {
+            let mut kv = param.splitn(2, '=');
+            if kv.next().unwrap_or("").trim().eq_ignore_ascii_case("q") {
+                q = kv.next().unwrap_or("").trim().parse().unwrap_or(0.0);
+            }
+        }
+        if best.is_none_or(|b| specificity > b.0) {
+            best = Some((specificity, q));
+        }
+    }
+
+    best.map_or(0.0, |b| b.1)
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,495 @@
+/*
+ *	Response compression: which bodies to compress (config), which coding
+ *	the client wants (Accept-Encoding) and a reader compressing a body as it
+ *	is sent.
+ */
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use client_side::request::Request;
+use server_side::config::SiteConf;
+use server_side::deflate::{ContentCoding, Encoder};
+use server_side::status;
+use server_side::status::HttpStatus;
+
+// Sibling files compressed ahead of time, by preference on equal q-values.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+// Bytes taken from the original body at once.
+const INPUT_PIECE_SIZE: usize = 64 * 1024;
+
+// Compression settings, from config.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    enabled: bool,
+    // Serve `file.br` / `file.gz` next to `file` when the client accepts them.
+    precompressed: bool,
+    min_size: u64,
+    // `text/html`, or `text/*` for a whole family.
+    types: Vec<String>,
+}
+
+impl Compression {
+    pub fn from_config(config: &SiteConf) -> Compression {
+        Compression {
+            enabled: config.compression,
+            precompressed: config.precompressed,
+            min_size: config.compression_min_size,
+            types: config.compression_types.clone(),
+        }
+    }
+
+    // On or off for one request, as its [location] says.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // Bodies of this type may be compressed, so responses depend on
+    // Accept-Encoding even when they are not.
+    pub fn is_compressible(&self, content_type: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+        self.types.iter().any(|t| match t.find("/*") {
+            Some(slash) => mime.starts_with(&t[..slash + 1]),
+            None => *t == mime,
+        })
+    }
+
+    // The coding to send a body of `length` bytes with, if any. 406 when
+    // the client refuses it as it is and we don't compress it.
+    pub fn choose(
+        &self,
+        req: &Request,
+        content_type: &str,
+        length: u64,
+    ) -> Result<Option<ContentCoding>, HttpStatus> {
+        let accept_encoding = match req.get_header("Accept-Encoding".to_string()) {
+            Ok(accept_encoding) => accept_encoding,
+            Err(_) => return Ok(None),
+        };
+
+        if length < self.min_size || !self.is_compressible(content_type) {
+            return if accepts_identity(&accept_encoding) {
+                Ok(None)
+            } else {
+                Err(status::NOT_ACCEPTABLE)
+            };
+        }
+        negotiate(&accept_encoding)
+    }
+
+    // A compressed sibling of `file_path` the client accepts, with its coding.
+    // Only plain files are taken, not symlinks that could lead anywhere.
+    pub fn find_precompressed(
+        &self,
+        req: &Request,
+        file_path: &Path,
+    ) -> Option<(PathBuf, &'static str)> {
+        if !self.precompressed {
+            return None;
+        }
+        let accept_encoding = req.get_header("Accept-Encoding".to_string()).ok()?;
+
+        let mut best: Option<(PathBuf, &'static str, f32)> = None;
+        for &(coding, extension) in PRECOMPRESSED.iter() {
+            let q = get_qvalue(&accept_encoding, coding);
+            if q <= 0.0 || best.as_ref().is_some_and(|b| b.2 >= q) {
+                continue;
+            }
+
+            let mut sibling = file_path.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(extension);
+            let sibling = PathBuf::from(sibling);
+            match fs::symlink_metadata(&sibling) {
+                Ok(ref meta) if meta.is_file() => best = Some((sibling, coding, q)),
+                _ => {}
+            }
+        }
+
+        best.map(|(sibling, coding, _)| (sibling, coding))
+    }
+}
+
+// q-value `Accept-Encoding` gives to `coding`; `*` stands for every coding not named.
+pub fn get_qvalue(accept_encoding: &str, coding: &str) -> f32 {
+    find_qvalue(accept_encoding, coding).unwrap_or(0.0)
+}
+
+// None when `coding` is neither named nor covered by `*`.
+fn find_qvalue(accept_encoding: &str, coding: &str) -> Option<f32> {
+    let mut any_q: Option<f32> = None;
+
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim().to_lowercase();
+        let mut q: f32 = 1.0;
+        for param in params {
+            let mut kv = param.splitn(2, '=');
+            if kv.next().unwrap_or("").trim().eq_ignore_ascii_case("q") {
+                q = kv.next().unwrap_or("").trim().parse().unwrap_or(0.0);
+            }
+        }
+
+        if name == coding || (coding == "gzip" && name == "x-gzip") {
+            return Some(q);
+        }
+        if name == "*" {
+            any_q = Some(q);
+        }
+    }
+
+    any_q
+}
+
+// A body as it is (`identity`) is taken unless refused by name or by `*;q=0`.
+fn accepts_identity(accept_encoding: &str) -> bool {
+    find_qvalue(accept_encoding, "identity").is_none_or(|q| q > 0.0)
+}
+
+// Best coding of `Accept-Encoding` we can compress with, gzip on a tie.
+// None to send the body as it is, 406 when the client refuses that too.
+pub fn negotiate(accept_encoding: &str) -> Result<Option<ContentCoding>, HttpStatus> {
+    let gzip_q = get_qvalue(accept_encoding, "gzip");
+    let deflate_q = get_qvalue(accept_encoding, "deflate");
+    if gzip_q > 0.0 || deflate_q > 0.0 {
+        Ok(Some(if gzip_q >= deflate_q {
+            ContentCoding::Gzip
+        } else {
+            ContentCoding::Deflate
+        }))
+    } else if accepts_identity(accept_encoding) {
+        Ok(None)
+    } else {
+        Err(status::NOT_ACCEPTABLE)
+    }
+}
+
+// Reads `inner` compressed with `coding`.
+pub struct CompressReader {
+    inner: Box<dyn Read + Send>,
+    encoder: Encoder,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_pos: usize,
+    done: bool,
+}
+
+impl CompressReader {
+    pub fn new(inner: Box<dyn Read + Send>, coding: ContentCoding) -> CompressReader {
+        CompressReader {
+            inner,
+            encoder: Encoder::new(coding),
+            input: vec![0; INPUT_PIECE_SIZE],
+            output: vec![],
+            output_pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for CompressReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.output_pos < self.output.len() {
+                let len = (self.output.len() - self.output_pos).min(buf.len());
+                buf[..len].copy_from_slice(&self.output[self.output_pos..self.output_pos + len]);
+                self.output_pos += len;
+                return Ok(len);
+            }
+
+            if self.done {
+                return Ok(0);
+            }
+
+            let len = match self.inner.read(&mut self.input) {
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            self.output_pos = 0;
+            if len == 0 {
+                self.output = self.encoder.finish();
+                self.done = true;
+            } else {
+                self.output = self.encoder.write(&self.input[..len]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client_side::request::RequestParser;
+    use server_side::config::Configuration;
+    use server_side::deflate::tests::{inflate, noise, words};
+    use std::env;
+    use std::io::Cursor;
+    use std::process;
+
+    fn get_request(accept_encoding: Option<&str>) -> Request {
+        let field = accept_encoding.map_or(String::new(), |value| {
+            format!("Accept-Encoding: {}\r\n", value)
+        });
+        let raw = format!("GET /a HTTP/1.1\r\nHost: x\r\n{}\r\n", field);
+        RequestParser::new()
+            .parse(&mut raw.into_bytes())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_qvalues() {
+        assert_eq!(get_qvalue("gzip", "gzip"), 1.0);
+        assert_eq!(get_qvalue("deflate, GZIP;q=0.5", "gzip"), 0.5);
+        assert_eq!(get_qvalue("gzip ; Q = 0.25", "gzip"), 0.25);
+        assert_eq!(get_qvalue("x-gzip", "gzip"), 1.0);
+        assert_eq!(get_qvalue("deflate", "gzip"), 0.0);
+        assert_eq!(get_qvalue("*;q=0.3, deflate", "gzip"), 0.3);
+        // A named coding wins over `*`, wherever it is.
+        assert_eq!(get_qvalue("*, gzip;q=0", "gzip"), 0.0);
+        assert_eq!(get_qvalue("gzip;q=oops", "gzip"), 0.0);
+    }
+
+    #[test]
+    fn negotiates_the_best_coding() {
+        assert_eq!(negotiate("gzip, deflate"), Ok(Some(ContentCoding::Gzip)));
+        assert_eq!(negotiate("deflate"), Ok(Some(ContentCoding::Deflate)));
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate;q=0.8"),
+            Ok(Some(ContentCoding::Deflate))
+        );
+        assert_eq!(
+            negotiate("deflate;q=0.5, gzip;q=0.5"),
+            Ok(Some(ContentCoding::Gzip))
+        );
+        assert_eq!(negotiate("*"), Ok(Some(ContentCoding::Gzip)));
+        assert_eq!(
+            negotiate("*;q=0.5, gzip;q=0.1"),
+            Ok(Some(ContentCoding::Deflate))
+        );
+        assert_eq!(negotiate("br"), Ok(None));
+        assert_eq!(negotiate(""), Ok(None));
+    }
+
+    #[test]
+    fn q_zero_refuses_a_coding() {
+        assert_eq!(
+            negotiate("gzip;q=0, deflate"),
+            Ok(Some(ContentCoding::Deflate))
+        );
+        assert_eq!(negotiate("*, gzip;q=0"), Ok(Some(ContentCoding::Deflate)));
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0"), Ok(None));
+        assert_eq!(negotiate("gzip;q=0.000"), Ok(None));
+    }
+
+    #[test]
+    fn refusing_identity_is_406_without_a_coding() {
+        assert_eq!(negotiate("identity;q=0"), Err(status::NOT_ACCEPTABLE));
+        assert_eq!(negotiate("br, identity;q=0"), Err(status::NOT_ACCEPTABLE));
+        assert_eq!(negotiate("*;q=0"), Err(status::NOT_ACCEPTABLE));
+        assert_eq!(
+            negotiate("gzip;q=0, identity;q=0"),
+            Err(status::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            negotiate("identity;q=0, gzip"),
+            Ok(Some(ContentCoding::Gzip))
+        );
+        assert_eq!(negotiate("*;q=0, identity"), Ok(None));
+
+        // Bodies we don't compress: as they are, if the client takes that.
+        let compression = Compression::from_config(&Configuration::builtin(&[]).unwrap().site);
+        let req = get_request(Some("identity;q=0"));
+        assert_eq!(
+            compression.choose(&req, "image/png", 5000),
+            Err(status::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            compression.choose(&req, "text/plain", 10),
+            Err(status::NOT_ACCEPTABLE)
+        );
+        let req = get_request(Some("gzip, identity;q=0"));
+        assert_eq!(
+            compression.choose(&req, "text/plain", 5000),
+            Ok(Some(ContentCoding::Gzip))
+        );
+        assert_eq!(
+            compression.choose(&req, "image/png", 5000),
+            Err(status::NOT_ACCEPTABLE)
+        );
+        let req = get_request(Some("gzip"));
+        assert_eq!(compression.choose(&req, "image/png", 5000), Ok(None));
+        assert_eq!(
+            compression.choose(&req, "Text/HTML; charset=utf-8", 1024),
+            Ok(Some(ContentCoding::Gzip))
+        );
+        assert_eq!(compression.choose(&req, "text/plain", 1023), Ok(None));
+        assert_eq!(
+            compression.choose(&get_request(None), "text/plain", 5000),
+            Ok(None)
+        );
+    }
+
+    // Gives `data` a few bytes at a time, with an interruption first.
+    struct Trickle {
+        data: Cursor<Vec<u8>>,
+        interrupted: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            let len = buf.len().min(777);
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn compress_reader_round_trip() {
+        let mut input = words(150000);
+        input.extend(noise(70000, 13));
+        input.extend(words(20000));
+
+        for coding in [ContentCoding::Gzip, ContentCoding::Deflate].iter() {
+            let inners: [Box<dyn Read + Send>; 2] = [
+                Box::new(Cursor::new(input.clone())),
+                Box::new(Trickle {
+                    data: Cursor::new(input.clone()),
+                    interrupted: false,
+                }),
+            ];
+            for inner in inners {
+                let mut reader = CompressReader::new(inner, *coding);
+                // Odd sizes, so reads end inside what the encoder gave.
+                let mut out = vec![];
+                let mut buf = [0; 1000];
+                loop {
+                    let len = reader.read(&mut buf).unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    out.extend_from_slice(&buf[..len]);
+                }
+                assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+                let header_len = if *coding == ContentCoding::Gzip {
+                    10
+                } else {
+                    2
+                };
+                let inflated = inflate(&out[header_len..]);
+                assert!(inflated.data == input, "{:?}", coding);
+                let trailer_len = if *coding == ContentCoding::Gzip { 8 } else { 4 };
+                assert_eq!(out.len(), header_len + inflated.consumed + trailer_len);
+                assert!(out.len() < input.len() * 2 / 3);
+            }
+        }
+
+        let mut reader = CompressReader::new(Box::new(Cursor::new(vec![])), ContentCoding::Gzip);
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert!(inflate(&out[10..]).data.is_empty());
+    }
+
+    #[test]
+    fn finds_precompressed_siblings() {
+        let dir = env::temp_dir().join(format!("aden-compress-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "both.js",
+            "both.js.br",
+            "both.js.gz",
+            "gz.css",
+            "gz.css.gz",
+            "plain.txt",
+        ]
+        .iter()
+        {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let mut compression = Compression::from_config(&Configuration::builtin(&[]).unwrap().site);
+        let find = |compression: &Compression, name: &str, accept_encoding: Option<&str>| {
+            compression
+                .find_precompressed(&get_request(accept_encoding), &dir.join(name))
+                .map(|(path, coding)| {
+                    (
+                        path.file_name().unwrap().to_string_lossy().into_owned(),
+                        coding,
+                    )
+                })
+        };
+        let found = |name: &str, coding| Some((name.to_string(), coding));
+
+        // `.br` on a tie, else the one with the higher q-value.
+        assert_eq!(
+            find(&compression, "both.js", Some("gzip, br")),
+            found("both.js.br", "br")
+        );
+        assert_eq!(
+            find(&compression, "both.js", Some("*")),
+            found("both.js.br", "br")
+        );
+        assert_eq!(
+            find(&compression, "both.js", Some("br;q=0.5, gzip")),
+            found("both.js.gz", "gzip")
+        );
+        assert_eq!(
+            find(&compression, "both.js", Some("x-gzip")),
+            found("both.js.gz", "gzip")
+        );
+        // The best one missing: the next one.
+        assert_eq!(
+            find(&compression, "gz.css", Some("br, gzip;q=0.1")),
+            found("gz.css.gz", "gzip")
+        );
+
+        // Not accepted: the file itself.
+        assert_eq!(find(&compression, "both.js", Some("deflate")), None);
+        assert_eq!(
+            find(&compression, "both.js", Some("br;q=0, gzip;q=0")),
+            None
+        );
+        assert_eq!(find(&compression, "both.js", Some("*;q=0")), None);
+        assert_eq!(find(&compression, "both.js", None), None);
+        assert_eq!(find(&compression, "gz.css", Some("br")), None);
+        assert_eq!(find(&compression, "plain.txt", Some("gzip, br")), None);
+
+        compression.precompressed = false;
+        assert_eq!(find(&compression, "both.js", Some("gzip, br")), None);
+        compression.precompressed = true;
+
+        // A directory or a symlink in place of a sibling is not taken, the
+        // symlink could lead out of home_dir.
+        fs::create_dir(dir.join("plain.txt.br")).unwrap();
+        assert_eq!(find(&compression, "plain.txt", Some("br")), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(dir.join("gz.css.gz"), dir.join("plain.txt.gz")).unwrap();
+            assert_eq!(find(&compression, "plain.txt", Some("gzip, br")), None);
+            symlink(dir.join("both.js.br"), dir.join("gz.css.br")).unwrap();
+            assert_eq!(
+                find(&compression, "gz.css", Some("br, gzip")),
+                found("gz.css.gz", "gzip")
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
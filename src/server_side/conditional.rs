@@ -0,0 +1,251 @@
+/*
+ *	Validators of a file (ETag, Last-Modified) and conditional requests
+ *	on them: If-Match, If-None-Match, If-Modified-Since, If-Unmodified-Since
+ *	and If-Range.
+ */
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use client_side::request::Request;
+use server_side::status;
+use server_side::status::HttpStatus;
+use server_side::utils;
+
+#[derive(Debug, Clone)]
+pub struct Validators {
+    // Quoted, with `W/` in front when weak.
+    etag: String,
+    weak: bool,
+    // Rounded down to seconds, like HTTP-dates.
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    // `encoding` is the coding of a precompressed file, it goes in the tag so
+    // each representation has its own even when sizes and dates are equal.
+    pub fn from_metadata(meta: &Metadata, encoding: Option<&str>) -> Validators {
+        let modified = meta.modified().ok();
+        let since_epoch = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_else(|| Duration::from_secs(0));
+
+        // A file written during the current second may change again without
+        // its mtime moving, so its tag can't promise byte equality.
+        let weak = match modified.map(|time| SystemTime::now().duration_since(time)) {
+            Some(Ok(age)) => age < Duration::from_secs(1),
+            _ => true,
+        };
+
+        let tag = format!(
+            "\"{:x}-{:x}{:08x}{}\"",
+            meta.len(),
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos(),
+            encoding.map(|coding| format!("-{}", coding)).unwrap_or_default()
+        );
+
+        Validators {
+            etag: if weak { format!("W/{}", tag) } else { tag },
+            weak,
+            last_modified: modified
+                .map(|_| UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())),
+        }
+    }
+
+    pub fn get_etag(&self) -> &str {
+        &self.etag
+    }
+
+    // Tag of a transformed body, e.g. compressed: same content, other bytes.
+    pub fn get_weak_etag(&self) -> String {
+        if self.weak {
+            self.etag.to_owned()
+        } else {
+            format!("W/{}", self.etag)
+        }
+    }
+
+    pub fn get_last_modified(&self) -> Option<String> {
+        self.last_modified.map(utils::format_http_date)
+    }
+
+    // The status to answer instead of the file (304 or 412), if any.
+    // Evaluated in the order of RFC 7232 section 6.
+    pub fn evaluate(&self, req: &Request) -> Option<HttpStatus> {
+        let is_get = req.method == "GET" || req.method == "HEAD";
+
+        if let Ok(if_match) = req.get_header("If-Match".to_string()) {
+            if !self.matches_any(&if_match, true) {
+                return Some(status::PRECONDITION_FAILED);
+            }
+        } else if let Ok(date) = req.get_header("If-Unmodified-Since".to_string()) {
+            if let (Some(date), Some(modified)) =
+                (utils::parse_http_date(&date), self.last_modified)
+            {
+                if modified > date {
+                    return Some(status::PRECONDITION_FAILED);
+                }
+            }
+        }
+
+        if let Ok(if_none_match) = req.get_header("If-None-Match".to_string()) {
+            if self.matches_any(&if_none_match, false) {
+                return Some(if is_get {
+                    status::NOT_MODIFIED
+                } else {
+                    status::PRECONDITION_FAILED
+                });
+            }
+        } else if let Ok(date) = req.get_header("If-Modified-Since".to_string()) {
+            if let (true, Some(date), Some(modified)) =
+                (is_get, utils::parse_http_date(&date), self.last_modified)
+            {
+                if modified <= date {
+                    return Some(status::NOT_MODIFIED);
+                }
+            }
+        }
+
+        None
+    }
+
+    // If-Range: the Range only applies while the file is the one the client
+    // has, given by a strong ETag or its exact Last-Modified date.
+    pub fn if_range_matches(&self, value: &str) -> bool {
+        let value = value.trim();
+        if value.starts_with('"') || value.starts_with("W/") {
+            return !self.weak && self.matches_any(value, true);
+        }
+
+        match (utils::parse_http_date(value), self.last_modified) {
+            (Some(date), Some(modified)) => !self.weak && date == modified,
+            _ => false,
+        }
+    }
+
+    // Compare against a list of entity-tags (or `*`), strongly or weakly.
+    fn matches_any(&self, list: &str, strong: bool) -> bool {
+        if list.trim() == "*" {
+            return true;
+        }
+        if strong && self.weak {
+            return false;
+        }
+
+        let own_tag = self.etag.trim_start_matches("W/");
+        parse_etag_list(list)
+            .iter()
+            .any(|&(weak, tag)| tag == own_tag && !(strong && weak))
+    }
+}
+
+// `"a", W/"b"` -> [(false, "\"a\""), (true, "\"b\"")], stops at the first malformed tag.
+fn parse_etag_list(list: &str) -> Vec<(bool, &str)> {
+    let mut tags: Vec<(bool, &str)> = vec![];
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches([',', ' ', '\t']);
+        if rest.is_empty() {
+            return tags;
+        }
+
+        let weak = rest.starts_with("W/");
+        if weak {
+            rest = &rest[2..];
+        }
+        if !rest.starts_with('"') {
+            return tags;
+        }
+
+        match rest[1..].find('"') {
+            Some(end) => {
+                tags.push((weak, &rest[..end + 2]));
+                rest = &rest[end + 2..];
+            }
+            None => return tags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client_side::request::RequestParser;
+
+    const MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn validators(weak: bool) -> Validators {
+        Validators {
+            etag: if weak { "W/\"abc\"" } else { "\"abc\"" }.to_string(),
+            weak,
+            last_modified: utils::parse_http_date(MODIFIED),
+        }
+    }
+
+    fn request(method: &str, header: &str) -> Request {
+        let raw = format!("{} /a HTTP/1.1\r\nHost: a\r\n{}\r\n\r\n", method, header);
+        let mut buffer = raw.into_bytes();
+        RequestParser::new().parse(&mut buffer).unwrap().unwrap()
+    }
+
+    fn evaluate(weak: bool, method: &str, header: &str) -> Option<HttpStatus> {
+        validators(weak).evaluate(&request(method, header))
+    }
+
+    #[test]
+    fn if_match_needs_a_strong_match() {
+        assert_eq!(evaluate(false, "PUT", "If-Match: \"abc\""), None);
+        assert_eq!(evaluate(false, "PUT", "If-Match: \"x\", \"abc\""), None);
+        assert_eq!(evaluate(false, "PUT", "If-Match: *"), None);
+        assert_eq!(evaluate(false, "PUT", "If-Match: \"x\""), Some(status::PRECONDITION_FAILED));
+        assert_eq!(evaluate(false, "PUT", "If-Match: W/\"abc\""), Some(status::PRECONDITION_FAILED));
+        assert_eq!(evaluate(true, "PUT", "If-Match: W/\"abc\""), Some(status::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert_eq!(evaluate(false, "GET", "If-None-Match: \"abc\""), Some(status::NOT_MODIFIED));
+        assert_eq!(evaluate(true, "HEAD", "If-None-Match: \"abc\""), Some(status::NOT_MODIFIED));
+        assert_eq!(evaluate(false, "GET", "If-None-Match: W/\"abc\""), Some(status::NOT_MODIFIED));
+        assert_eq!(evaluate(false, "GET", "If-None-Match: \"x\""), None);
+        assert_eq!(evaluate(false, "PUT", "If-None-Match: *"), Some(status::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn compares_dates() {
+        let ims = |date: &str| evaluate(false, "GET", &format!("If-Modified-Since: {}", date));
+        assert_eq!(ims(MODIFIED), Some(status::NOT_MODIFIED));
+        assert_eq!(ims("Mon, 07 Nov 1994 08:49:37 GMT"), Some(status::NOT_MODIFIED));
+        assert_eq!(ims("Sun, 06 Nov 1994 08:49:36 GMT"), None);
+        // Unreadable or out of range dates are ignored.
+        assert_eq!(ims("Sun, 06 Nov 999999999999999999 08:49:37 GMT"), None);
+        assert_eq!(ims("not a date"), None);
+        // Only for GET and HEAD.
+        assert_eq!(evaluate(false, "PUT", &format!("If-Modified-Since: {}", MODIFIED)), None);
+
+        let ius = |date: &str| evaluate(false, "PUT", &format!("If-Unmodified-Since: {}", date));
+        assert_eq!(ius(MODIFIED), None);
+        assert_eq!(ius("Sat, 05 Nov 1994 08:49:37 GMT"), Some(status::PRECONDITION_FAILED));
+        assert_eq!(ius("Sat, 05 Nov 99999999999999999999 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn tags_win_over_dates() {
+        // If-Match replaces If-Unmodified-Since, If-None-Match If-Modified-Since.
+        let header = "If-Match: \"abc\"\r\nIf-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT";
+        assert_eq!(evaluate(false, "PUT", header), None);
+        let header = format!("If-None-Match: \"x\"\r\nIf-Modified-Since: {}", MODIFIED);
+        assert_eq!(evaluate(false, "GET", &header), None);
+    }
+
+    #[test]
+    fn if_range_needs_a_strong_validator() {
+        assert!(validators(false).if_range_matches("\"abc\""));
+        assert!(validators(false).if_range_matches(MODIFIED));
+        assert!(!validators(false).if_range_matches("\"x\""));
+        assert!(!validators(false).if_range_matches("W/\"abc\""));
+        assert!(!validators(false).if_range_matches("Sun, 06 Nov 1994 08:49:38 GMT"));
+        assert!(!validators(true).if_range_matches("W/\"abc\""));
+        assert!(!validators(true).if_range_matches(MODIFIED));
+    }
+}
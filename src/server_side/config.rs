@@ -1,80 +1,1273 @@
-use std::path;
+// Settings of `config/config.conf`, in sections and typed: every line is
+// checked when the file is loaded and the server refuses to start on errors.
+
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use server_side::log;
+use server_side::log::Level;
+use server_side::pool::OverflowPolicy;
 use server_side::utils;
 
+#[derive(Debug, Clone)]
+pub struct Configuration {
+	// Main file the settings were read from.
+	pub path: PathBuf,
+	pub server: ServerConf,
+	pub logging: LoggingConf,
+	// Site of [server], its settings are also the defaults of every [host].
+	pub site: SiteConf,
+	pub hosts: Vec<HostConf>,
+	// Config files read: `path` and the ones it includes.
+	pub files: Vec<PathBuf>,
+	// Section, key and where its value was set, for `config dump`.
+	pub origins: Vec<(String, String, String)>,
+	// `file:line: warning` for keys set again, logged when read.
+	pub warnings: Vec<String>
+}
+
+// [server]: listener, connections and worker pool.
+#[derive(Debug, Clone)]
+pub struct ServerConf {
+	pub address: String,
+	pub port: u16,
+	pub io_engine: String,
+	pub max_alive_thread: usize,
+	pub pool_queue_size: usize,
+	pub pool_overflow: OverflowPolicy,
+	pub retry_after: Duration,
+	// None: no timeout.
+	pub tcp_read_timeout: Option<Duration>,
+	pub keep_alive_timeout: Option<Duration>,
+	pub tcp_write_timeout: Option<Duration>,
+	pub keep_alive_max_requests: usize,
+	pub max_header_size: usize,
+	pub max_header_count: usize,
+	pub max_body_size: usize,
+	// Empty: the site of [server] answers unknown hosts.
+	pub default_host: String,
+	pub reject_unknown_hosts: bool,
+	// Reload when a config file changes (Linux).
+	pub watch_config: bool,
+	// How long a stop waits for requests being answered.
+	pub drain_timeout: Duration
+}
+
+// [logging]
+#[derive(Debug, Clone)]
+pub struct LoggingConf {
+	pub level: Level,
+	pub access_log: bool,
+	// Empty: stdout.
+	pub file: String
+}
+
+// What is served and how, for [server] and each [host].
+#[derive(Debug, Clone)]
+pub struct SiteConf {
+	pub home_dir: String,
+	pub home_dir_error: String,
+	pub index_files: Vec<String>,
+	pub alternative_index_basename: String,
+	pub forbidden_dirs: Vec<String>,
+	pub autoindex_dirs: Vec<String>,
+	pub upload_dirs: Vec<String>,
+	// `user:password`
+	pub upload_users: Vec<String>,
+	pub webdav: bool,
+	pub restrict_symlinks: bool,
+	pub compression: bool,
+	pub compression_min_size: u64,
+	pub compression_types: Vec<String>,
+	pub precompressed: bool,
+	// Empty: no aliases.
+	pub alias_file: String,
+	pub locations: Vec<LocationConf>
+}
+
+// [host <name> ...]
+#[derive(Debug, Clone)]
+pub struct HostConf {
+	pub names: Vec<String>,
+	pub site: SiteConf
+}
+
+// [location <url prefix>]: settings of the site above it changed for the
+// URLs under the prefix. None: as the site says.
+#[derive(Debug, Clone)]
+pub struct LocationConf {
+	pub prefix: String,
+	pub deny: bool,
+	pub autoindex: Option<bool>,
+	pub compression: Option<bool>,
+	pub index_files: Option<Vec<String>>,
+	pub alternative_index_basename: Option<String>
+}
+
+// A [server] or [logging] key set outside the file, over its value there.
+#[derive(Debug, Clone)]
+pub struct Override {
+	pub logging: bool,
+	pub key: String,
+	pub value: String,
+	// Where it comes from, for errors: `--port`.
+	pub source: String
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SectionKind {
+	Server,
+	Logging,
+	Host(Vec<String>),
+	// Prefix and the index of the [server] or [host] section it belongs to.
+	Location(String, usize),
+	// Header with errors, its keys are left out.
+	Invalid
+}
+
+// Lines of the config files, includes read in place, as (index in
+// `files`, line number, text). Lines are referred to by their position.
 #[derive(Debug)]
-pub struct Configuration(Vec<ConfUnit>);
+struct ConfigText {
+	files: Vec<PathBuf>,
+	lines: Vec<(usize, usize, String)>,
+	// Include errors, by position.
+	errors: Vec<(usize, String)>
+}
 
+// Lines of one section, as (position, key, value).
 #[derive(Debug)]
-pub struct ConfUnit(pub String, pub String);
+struct Section {
+	kind: SectionKind,
+	line: usize,
+	entries: Vec<(usize, String, String)>
+}
 
 impl Configuration {
 	pub fn new() -> Result<Configuration, Error> {
-		let mut config: Vec<ConfUnit> = vec![];
-		let root_path = utils::get_root_path();
-		let config_pathbuf = path::PathBuf::from(utils::to_root_path("/config/config.conf", &root_path));
-		let config_path: &str = config_pathbuf.to_str().unwrap();
-		let config_file_handle = match File::open(config_path) {
-			Ok(ok) => ok,
-			Err(e) => return Err(Error::new(ErrorKind::Other, format!("can't find `{:?}`", config_path)))
+		Configuration::from_file(&Configuration::get_default_path(), &[])
+	}
+
+	pub fn get_default_path() -> PathBuf {
+		utils::to_root_path("/config/config.conf", &utils::get_root_path())
+	}
+
+	pub fn from_file(path: &Path, overrides: &[Override]) -> Result<Configuration, Error> {
+		let mut text = ConfigText { files: vec![], lines: vec![], errors: vec![] };
+		text.read(path, &mut vec![])?;
+		Configuration::parse(path, text, overrides)
+	}
+
+	// Settings for serving the server root as it is, without config files.
+	pub fn builtin(overrides: &[Override]) -> Result<Configuration, Error> {
+		let lines = ["home_dir = /", "home_dir_error = /", "alias_file ="].iter()
+			.enumerate()
+			.map(|(i, line)| (0, i + 1, line.to_string()))
+			.collect();
+		let text = ConfigText { files: vec![PathBuf::from("(built-in)")], lines, errors: vec![] };
+		Configuration::parse(Path::new("(built-in)"), text, overrides)
+	}
+
+	// [server] and [logging] keys from `ADEN_<KEY>` and `ADEN_LOGGING_<KEY>`
+	// environment variables: `ADEN_SERVER_PORT=8000` sets `server_port`.
+	pub fn get_env_overrides() -> Vec<Override> {
+		Configuration::read_env_overrides(std::env::vars())
+	}
+
+	// Other `ADEN_` variables may belong to something else: they are left
+	// out with a warning.
+	fn read_env_overrides<I: Iterator<Item = (String, String)>>(vars: I) -> Vec<Override> {
+		let mut overrides: Vec<Override> = vars
+			.filter(|(name, _)| name.starts_with("ADEN_"))
+			.filter_map(|(name, value)| {
+				let key = name["ADEN_".len()..].to_lowercase();
+				let logging = key.starts_with("logging_");
+				let key = if logging { key["logging_".len()..].to_string() } else { key };
+				// A known key with a bad value is kept, for its error.
+				let known = if logging {
+					LoggingConf::new().set(&key, &value).unwrap_or(true)
+				} else {
+					ServerConf::new().set(&key, &value).unwrap_or(true)
+						|| SiteConf::new().set(&key, &value).unwrap_or(true)
+				};
+				if !known {
+					log::warn(format_args!("Environment `{}` is not a setting, it is ignored.", name));
+					return None;
+				}
+				Some(Override { logging, key, value, source: format!("environment `{}`", name) })
+			})
+			.collect();
+		overrides.sort_by(|a, b| a.source.cmp(&b.source));
+		overrides
+	}
+
+	// All the errors are in the message, one a line as `file:line: error`.
+	// Keys before the first section belong to [server], `overrides` are
+	// applied after them.
+	fn parse(path: &Path, text: ConfigText, overrides: &[Override]) -> Result<Configuration, Error> {
+		// Position in `text.lines` and message, sorted at the end.
+		let mut errors: Vec<(usize, String)> = text.errors.clone();
+		let sections = Configuration::read_sections(&text, &mut |line, message| {
+			errors.push((line, message));
+		});
+
+		let mut config = Configuration {
+			path: path.to_path_buf(),
+			files: text.files.clone(),
+			server: ServerConf::new(),
+			logging: LoggingConf::new(),
+			site: SiteConf::new(),
+			hosts: vec![],
+			origins: vec![],
+			warnings: vec![]
 		};
-		let config_bufreader = BufReader::new(&config_file_handle);
+		let mut report = |line: usize, message: String| errors.push((line, message));
 
-		let mut line_num: usize = 0;
-		for line_unwrap in config_bufreader.lines() {
-			line_num += 1;
-			let line_raw = line_unwrap.unwrap();
-			let line = line_raw.trim();
+		// Keys set so far by section, to warn about the ones set again: the
+		// last value wins.
+		let mut seen: Vec<(SectionKind, String, usize)> = vec![];
+		let mut warnings: Vec<String> = vec![];
+		let mut check_duplicate = |section: &Section, line: usize, key: &str| {
+			match seen.iter_mut().find(|s| s.0 == section.kind && s.1 == key) {
+				Some(previous) => {
+					warnings.push(format!("{}: `{}` is set again in {}, {} is overridden",
+						text.locate(line), key, section.get_name(), text.locate(previous.2)));
+					previous.2 = line;
+				},
+				None => seen.push((section.kind.clone(), key.to_string(), line))
+			}
+		};
 
-			if line.starts_with("#") || line.len() == 0 {
+		// [server] and [logging] first, every [host] starts from their site.
+		for section in sections.iter() {
+			if section.kind != SectionKind::Server && section.kind != SectionKind::Logging {
 				continue;
 			}
+			for &(line, ref key, ref value) in section.entries.iter() {
+				check_duplicate(section, line, key);
+				let known = match section.kind {
+					SectionKind::Logging => config.logging.set(key, value),
+					_ => config.server.set(key, value)
+						.and_then(|known| if known { Ok(true) } else { config.site.set(key, value) })
+				};
+				match known {
+					Ok(true) => config.set_origin(&section.get_name(), key, text.locate(line)),
+					Ok(false) => report(line, format!("unknown key `{}` in {}", key, section.get_name())),
+					Err(e) => report(line, format!("`{}`: {}", key, e))
+				}
+			}
+		}
+
+		let mut override_errors: Vec<String> = vec![];
+		for o in overrides.iter() {
+			let known = if o.logging {
+				config.logging.set(&o.key, &o.value)
+			} else {
+				config.server.set(&o.key, &o.value)
+					.and_then(|known| if known { Ok(true) } else { config.site.set(&o.key, &o.value) })
+			};
+			let section = if o.logging { "[logging]" } else { "[server]" };
+			match known {
+				Ok(true) => config.set_origin(section, &o.key, o.source.clone()),
+				Ok(false) => override_errors.push(format!("{}: unknown key `{}`", o.source, o.key)),
+				Err(e) => override_errors.push(format!("{}: {}", o.source, e))
+			}
+		}
+
+		for section in sections.iter() {
+			let names = match section.kind {
+				SectionKind::Host(ref names) => names,
+				_ => continue
+			};
+			let mut site = config.site.clone();
+			site.locations.clear();
+			for &(line, ref key, ref value) in section.entries.iter() {
+				check_duplicate(section, line, key);
+				match site.set(key, value) {
+					Ok(true) => config.set_origin(&section.get_name(), key, text.locate(line)),
+					Ok(false) if ServerConf::new().set(key, value).unwrap_or(true) =>
+						report(line, format!("`{}` can only be set in [server]", key)),
+					Ok(false) => report(line, format!("unknown key `{}` in {}", key, section.get_name())),
+					Err(e) => report(line, format!("`{}`: {}", key, e))
+				}
+			}
+
+			for host in config.hosts.iter() {
+				for name in names.iter().filter(|name| host.names.contains(name)) {
+					report(section.line, format!("host `{}` already has a section", name));
+				}
+			}
+			config.hosts.push(HostConf { names: names.to_vec(), site });
+		}
+
+		// Locations go to their site once it is complete.
+		for section in sections.iter() {
+			let (prefix, owner) = match section.kind {
+				SectionKind::Location(ref prefix, owner) => (prefix, owner),
+				_ => continue
+			};
+			// Named after its site too, the same prefix may be in several.
+			let origin_section = format!("{} {}", sections[owner].get_name(), section.get_name());
+			let mut location = LocationConf::new(prefix);
+			for &(line, ref key, ref value) in section.entries.iter() {
+				check_duplicate(section, line, key);
+				match location.set(key, value) {
+					Ok(true) => config.set_origin(&origin_section, key, text.locate(line)),
+					Ok(false) => report(line, format!("unknown key `{}` in {}", key, section.get_name())),
+					Err(e) => report(line, format!("`{}`: {}", key, e))
+				}
+			}
+
+			let site = match sections[owner].kind {
+				SectionKind::Host(ref names) => match config.hosts.iter_mut().find(|host| host.names == *names) {
+					Some(host) => &mut host.site,
+					None => continue
+				},
+				_ => &mut config.site
+			};
+			if site.locations.iter().any(|l| l.prefix == location.prefix) {
+				report(section.line, format!("location `{}` already has a section in this site", prefix));
+			}
+			site.locations.push(location);
+		}
+
+		if !config.server.default_host.is_empty()
+			&& !config.hosts.iter().any(|host| host.names.contains(&config.server.default_host)) {
+			let message = format!("default_host `{}` has no [host] section", config.server.default_host);
+			match overrides.iter().rfind(|o| !o.logging && o.key == "default_host") {
+				Some(o) => override_errors.push(format!("{}: {}", o.source, message)),
+				None => {
+					let line = sections.iter()
+						.flat_map(|section| section.entries.iter())
+						.rfind(|entry| entry.1 == "default_host")
+						.map_or(0, |entry| entry.0);
+					report(line, message);
+				}
+			}
+		}
+
+		for warning in warnings.iter() {
+			log::warn(format_args!("{}", warning));
+		}
+		config.warnings = warnings;
+
+		if errors.is_empty() && override_errors.is_empty() {
+			return Ok(config);
+		}
+		errors.sort_by_key(|error| error.0);
+		let mut errors: Vec<String> = errors.iter()
+			.map(|error| format!("{}: {}", text.locate(error.0), error.1))
+			.collect();
+		errors.append(&mut override_errors);
+		Err(Error::other(errors.join("\n")))
+	}
 
-			let mut split: Vec<&str> = line.split("=").collect();
-			if split.len() < 2 {
-				println!("E: config file has error on line {}: `{}`", line_num, line_raw);
+	// Split the lines in sections, reporting lines that are not
+	// `key = value`, a section header or an include.
+	fn read_sections(text: &ConfigText, report: &mut dyn FnMut(usize, String)) -> Vec<Section> {
+		let mut sections = vec![Section { kind: SectionKind::Server, line: 0, entries: vec![] }];
+		// Section the next [location] belongs to.
+		let mut site_section: usize = 0;
+
+		for (line_num, (_, _, line_raw)) in text.lines.iter().enumerate() {
+			let line = line_raw.trim();
+			if line.starts_with('#') || line.is_empty() || get_include(line).is_some() {
 				continue;
 			}
 
-			let key = split[0].trim().to_string();
-			split.remove(0);
-			let value = split.join("").trim().to_string();
+			if line.starts_with('[') {
+				let kind = match SectionKind::parse(line, site_section) {
+					Ok(kind) => kind,
+					Err(e) => {
+						report(line_num, e);
+						SectionKind::Invalid
+					}
+				};
+				if let SectionKind::Server | SectionKind::Host(_) = kind {
+					site_section = sections.len();
+				}
+				sections.push(Section { kind, line: line_num, entries: vec![] });
+				continue;
+			}
 
-			for cu in config.iter_mut() {
-				if cu.0 == key {
-					cu.1 = value.to_owned();
-					break;
+			// Values may hold `=`, only the first one splits.
+			let mut split = line.splitn(2, '=');
+			let key = split.next().unwrap_or("").trim();
+			let value = match split.next() {
+				Some(value) if !key.is_empty() => value.trim(),
+				_ => {
+					report(line_num, format!("expected `key = value` or `[section]`, not `{}`", line));
+					continue;
 				}
+			};
+
+			let section = sections.last_mut().unwrap();
+			if section.kind != SectionKind::Invalid {
+				section.entries.push((line_num, key.to_string(), value.to_string()));
 			}
+		}
+
+		sections
+	}
 
-			config.push(ConfUnit(key, value));
+	fn set_origin(&mut self, section: &str, key: &str, origin: String) {
+		match self.origins.iter_mut().find(|o| o.0 == section && o.1 == key) {
+			Some(o) => o.2 = origin,
+			None => self.origins.push((section.to_string(), key.to_string(), origin))
 		}
+	}
 
-		Ok(Configuration(config))
+	fn get_origin(&self, section: &str, key: &str) -> Option<&str> {
+		self.origins.iter()
+			.find(|o| o.0 == section && o.1 == key)
+			.map(|o| o.2.as_str())
 	}
 
-	pub fn to_owned(&self) -> Configuration {
-		let mut config: Vec<ConfUnit> = vec![];
+	// Effective settings in config file syntax, each with where it was set.
+	pub fn dump(&self) -> String {
+		let mut out = String::new();
+		out.push_str("# Effective settings and where each was set, later ones win:\n");
+		out.push_str("# default < config files (in reading order) < environment < command line.\n");
+		for file in self.files.iter() {
+			out.push_str(&format!("# Read {}\n", file.display()));
+		}
+		for warning in self.warnings.iter() {
+			out.push_str(&format!("# Warning: {}\n", warning));
+		}
+
+		let mut values = self.server.get_values();
+		values.append(&mut self.site.get_values());
+		self.dump_section(&mut out, "[server]", values, None);
+		self.dump_locations(&mut out, "[server]", &self.site);
+		self.dump_section(&mut out, "[logging]", self.logging.get_values(), None);
 
-		for cu in self.0.iter() {
-			config.push(ConfUnit(cu.0.to_owned(), cu.1.to_owned()));
+		for host in self.hosts.iter() {
+			let name = format!("[host {}]", host.names.join(" "));
+			self.dump_section(&mut out, &name, host.site.get_values(), Some("[server]"));
+			self.dump_locations(&mut out, &name, &host.site);
 		}
+		out
+	}
 
-		Configuration(config)
+	// Keys not set in `section` are from `parent` when it has one.
+	fn dump_section(&self, out: &mut String, section: &str, values: Vec<(&str, String)>, parent: Option<&str>) {
+		out.push_str(&format!("\n{}\n", section));
+		for (key, value) in values {
+			let origin = match (self.get_origin(section, key), parent) {
+				(Some(origin), _) => origin.to_string(),
+				(None, Some(parent)) => match self.get_origin(parent, key) {
+					Some(origin) => format!("as {}, {}", parent, origin),
+					None => format!("as {}, default", parent)
+				},
+				(None, None) => "default".to_string()
+			};
+			out.push_str(&format!("{} = {}  # {}\n", key, value, origin));
+		}
 	}
 
-	pub fn get_value_or(&self, key: &str, default: &str) -> String {
-		let key_string = key.to_string();
-		for cu in self.0.iter() {
-			if key == cu.0 {
-				return cu.1.to_owned();
+	fn dump_locations(&self, out: &mut String, site_section: &str, site: &SiteConf) {
+		for location in site.locations.iter() {
+			let name = format!("[location {}]", location.prefix);
+			out.push_str(&format!("\n{}\n", name));
+			for (key, value) in location.get_values() {
+				let origin = self.get_origin(&format!("{} {}", site_section, name), key).unwrap_or("default");
+				out.push_str(&format!("{} = {}  # {}\n", key, value, origin));
 			}
 		}
+	}
+}
+
+impl ConfigText {
+	// Append the lines of `path`, with the files it includes in place of
+	// their `include` line. `including` holds the files being read, to
+	// refuse an include loop.
+	fn read(&mut self, path: &Path, including: &mut Vec<PathBuf>) -> Result<(), Error> {
+		let config_file_handle = match File::open(path) {
+			Ok(ok) => ok,
+			Err(e) => return Err(Error::other(format!("can't find `{}`", path.display())))
+		};
+		let file = self.files.len();
+		self.files.push(path.to_path_buf());
+		including.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+
+		for (i, line) in BufReader::new(&config_file_handle).lines().enumerate() {
+			let line = line?;
+			let position = self.lines.len();
+			let pattern = get_include(line.trim()).map(|pattern| pattern.to_string());
+			self.lines.push((file, i + 1, line));
+
+			let pattern = match pattern {
+				Some(pattern) => pattern,
+				None => continue
+			};
+			let dir = path.parent().unwrap_or_else(|| Path::new("."));
+			let included = match find_includes(&pattern, dir) {
+				Ok(included) => included,
+				Err(e) => {
+					self.errors.push((position, e));
+					continue;
+				}
+			};
+			for included_path in included {
+				let canonical = included_path.canonicalize().unwrap_or_else(|_| included_path.clone());
+				if including.contains(&canonical) {
+					self.errors.push((position, format!("`{}` is already being read: include loop", included_path.display())));
+				} else if let Err(e) = self.read(&included_path, including) {
+					self.errors.push((position, e.to_string()));
+				}
+			}
+		}
+
+		including.pop();
+		Ok(())
+	}
+
+	// `file:line` of a line.
+	fn locate(&self, position: usize) -> String {
+		match self.lines.get(position) {
+			Some(&(file, number, _)) => format!("{}:{}", self.files[file].display(), number),
+			None => format!("{}:0", self.files.first().map_or(String::new(), |file| file.display().to_string()))
+		}
+	}
+}
+
+impl SectionKind {
+	fn parse(header: &str, site_section: usize) -> Result<SectionKind, String> {
+		if !header.ends_with(']') {
+			return Err(format!("`{}` has no closing `]`", header));
+		}
+		let words: Vec<&str> = header[1..header.len() - 1].split_whitespace().collect();
+
+		match (words.first().cloned(), words.len()) {
+			(Some("server"), 1) => Ok(SectionKind::Server),
+			(Some("logging"), 1) => Ok(SectionKind::Logging),
+			(Some("host"), n) if n > 1 => {
+				let mut names = vec![];
+				for name in words[1..].iter() {
+					let name = name.trim_end_matches('.').to_lowercase();
+					let bare = name.trim_start_matches("*.");
+					if bare.is_empty() || !bare.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_') {
+						return Err(format!("`{}` is not a host name", name));
+					}
+					names.push(name);
+				}
+				Ok(SectionKind::Host(names))
+			},
+			(Some("location"), 2) if words[1].starts_with('/') => {
+				let prefix = match words[1].trim_end_matches('/') {
+					"" => "/",
+					prefix => prefix
+				};
+				Ok(SectionKind::Location(prefix.to_string(), site_section))
+			},
+			_ => Err(format!("unknown section `{}`, expected [server], [logging], [host <name> ...] or [location /<path>]", header))
+		}
+	}
+}
+
+impl Section {
+	fn get_name(&self) -> String {
+		match self.kind {
+			SectionKind::Server => "[server]".to_string(),
+			SectionKind::Logging => "[logging]".to_string(),
+			SectionKind::Host(ref names) => format!("[host {}]", names.join(" ")),
+			SectionKind::Location(ref prefix, _) => format!("[location {}]", prefix),
+			SectionKind::Invalid => String::new()
+		}
+	}
+}
+
+impl ServerConf {
+	fn new() -> ServerConf {
+		ServerConf {
+			address: "localhost".to_string(),
+			port: 8080,
+			io_engine: "thread".to_string(),
+			max_alive_thread: 8,
+			pool_queue_size: 64,
+			pool_overflow: OverflowPolicy::Block,
+			retry_after: Duration::from_secs(5),
+			tcp_read_timeout: None,
+			keep_alive_timeout: Some(Duration::from_millis(5000)),
+			tcp_write_timeout: Some(Duration::from_secs(30)),
+			keep_alive_max_requests: 100,
+			max_header_size: 8192,
+			max_header_count: 100,
+			max_body_size: 10485760,
+			default_host: String::new(),
+			reject_unknown_hosts: false,
+			watch_config: false,
+			drain_timeout: Duration::from_secs(10)
+		}
+	}
+
+	// Ok(false) for a key that is not a [server] one.
+	fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+		match key {
+			"server_address" => self.address = parse_nonempty(value)?,
+			"server_port" => self.port = parse_port(value)?,
+			"io_engine" => self.io_engine = match value {
+				"thread" | "epoll" => value.to_string(),
+				_ => return Err(format!("expected `thread` or `epoll`, not `{}`", value))
+			},
+			"max_alive_thread" => self.max_alive_thread = parse_positive(value)?,
+			"pool_queue_size" => self.pool_queue_size = parse_number(value)?,
+			"pool_overflow" => self.pool_overflow = match OverflowPolicy::from_name(value) {
+				Some(policy) => policy,
+				None => return Err(format!("expected `block` or `reject`, not `{}`", value))
+			},
+			"retry_after" => self.retry_after = parse_duration(value, Duration::from_secs(1))?,
+			"tcp_read_timeout" => self.tcp_read_timeout = parse_timeout(value)?,
+			"keep_alive_timeout" => self.keep_alive_timeout = parse_timeout(value)?,
+			"tcp_write_timeout" => self.tcp_write_timeout = parse_timeout(value)?,
+			"keep_alive_max_requests" => self.keep_alive_max_requests = parse_positive(value)?,
+			"max_header_size" => self.max_header_size = parse_positive(value)?,
+			"max_header_count" => self.max_header_count = parse_positive(value)?,
+			"max_body_size" => self.max_body_size = parse_number(value)?,
+			"default_host" => self.default_host = value.trim_end_matches('.').to_lowercase(),
+			"reject_unknown_hosts" => self.reject_unknown_hosts = parse_bool(value)?,
+			"watch_config" => self.watch_config = parse_bool(value)?,
+			"drain_timeout" => self.drain_timeout = parse_duration(value, Duration::from_secs(1))?,
+			_ => return Ok(false)
+		}
+		Ok(true)
+	}
+
+	// Keys and values as written in the file.
+	fn get_values(&self) -> Vec<(&'static str, String)> {
+		vec![
+			("server_address", self.address.clone()),
+			("server_port", self.port.to_string()),
+			("io_engine", self.io_engine.clone()),
+			("max_alive_thread", self.max_alive_thread.to_string()),
+			("pool_queue_size", self.pool_queue_size.to_string()),
+			("pool_overflow", self.pool_overflow.get_name().to_string()),
+			("retry_after", format_duration(self.retry_after)),
+			("tcp_read_timeout", format_timeout(self.tcp_read_timeout)),
+			("keep_alive_timeout", format_timeout(self.keep_alive_timeout)),
+			("tcp_write_timeout", format_timeout(self.tcp_write_timeout)),
+			("keep_alive_max_requests", self.keep_alive_max_requests.to_string()),
+			("max_header_size", self.max_header_size.to_string()),
+			("max_header_count", self.max_header_count.to_string()),
+			("max_body_size", self.max_body_size.to_string()),
+			("default_host", self.default_host.clone()),
+			("reject_unknown_hosts", self.reject_unknown_hosts.to_string()),
+			("watch_config", self.watch_config.to_string()),
+			("drain_timeout", format_duration(self.drain_timeout))
+		]
+	}
+}
+
+impl LoggingConf {
+	fn new() -> LoggingConf {
+		LoggingConf {
+			level: Level::Info,
+			access_log: true,
+			file: String::new()
+		}
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+		match key {
+			"level" => self.level = match Level::from_name(value) {
+				Some(level) => level,
+				None => return Err(format!("expected `error`, `warn`, `info` or `debug`, not `{}`", value))
+			},
+			"access_log" => self.access_log = parse_bool(value)?,
+			"file" => self.file = value.to_string(),
+			_ => return Ok(false)
+		}
+		Ok(true)
+	}
+
+	fn get_values(&self) -> Vec<(&'static str, String)> {
+		vec![
+			("level", self.level.get_name().to_string()),
+			("access_log", self.access_log.to_string()),
+			("file", self.file.clone())
+		]
+	}
+}
+
+impl SiteConf {
+	fn new() -> SiteConf {
+		SiteConf {
+			home_dir: "/www".to_string(),
+			home_dir_error: "/error".to_string(),
+			index_files: vec!["index.html".to_string()],
+			alternative_index_basename: "index".to_string(),
+			forbidden_dirs: vec![],
+			autoindex_dirs: vec![],
+			upload_dirs: vec![],
+			upload_users: vec![],
+			webdav: false,
+			restrict_symlinks: true,
+			compression: true,
+			compression_min_size: 1024,
+			compression_types: parse_list("text/*;application/javascript;application/json;application/xml;image/svg+xml"),
+			precompressed: true,
+			alias_file: "/config/alias.conf".to_string(),
+			locations: vec![]
+		}
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+		match key {
+			"home_dir" => self.home_dir = parse_nonempty(value)?,
+			"home_dir_error" => self.home_dir_error = parse_nonempty(value)?,
+			"default_index_file" => self.index_files = parse_list(value),
+			"alternative_index_basename" => self.alternative_index_basename = value.to_string(),
+			"forbidden_dir" => self.forbidden_dirs = parse_list(value),
+			"autoindex_dir" => self.autoindex_dirs = parse_list(value),
+			"upload_dir" => self.upload_dirs = parse_list(value),
+			"upload_users" => {
+				let users = parse_list(value);
+				if let Some(user) = users.iter().find(|user| !user.contains(':') || user.starts_with(':')) {
+					return Err(format!("expected `user:password`, not `{}`", user));
+				}
+				self.upload_users = users;
+			},
+			"webdav" => self.webdav = parse_bool(value)?,
+			"restrict_symlinks" => self.restrict_symlinks = parse_bool(value)?,
+			"compression" => self.compression = parse_bool(value)?,
+			"compression_min_size" => self.compression_min_size = parse_number(value)?,
+			"compression_types" => self.compression_types = parse_list(&value.to_lowercase()),
+			"precompressed" => self.precompressed = parse_bool(value)?,
+			"alias_file" => self.alias_file = value.to_string(),
+			_ => return Ok(false)
+		}
+		Ok(true)
+	}
+
+	// Passwords of `upload_users` are left out.
+	fn get_values(&self) -> Vec<(&'static str, String)> {
+		let upload_users: Vec<String> = self.upload_users.iter()
+			.map(|user| format!("{}:***", user.split(':').next().unwrap_or("")))
+			.collect();
+		vec![
+			("home_dir", self.home_dir.clone()),
+			("home_dir_error", self.home_dir_error.clone()),
+			("default_index_file", self.index_files.join(";")),
+			("alternative_index_basename", self.alternative_index_basename.clone()),
+			("forbidden_dir", self.forbidden_dirs.join(";")),
+			("autoindex_dir", self.autoindex_dirs.join(";")),
+			("upload_dir", self.upload_dirs.join(";")),
+			("upload_users", upload_users.join(";")),
+			("webdav", self.webdav.to_string()),
+			("restrict_symlinks", self.restrict_symlinks.to_string()),
+			("compression", self.compression.to_string()),
+			("compression_min_size", self.compression_min_size.to_string()),
+			("compression_types", self.compression_types.join(";")),
+			("precompressed", self.precompressed.to_string()),
+			("alias_file", self.alias_file.clone())
+		]
+	}
+
+	// Location with the longest prefix holding `path`, on `/` boundaries.
+	pub fn get_location(&self, path: &str) -> Option<&LocationConf> {
+		self.locations.iter()
+			.filter(|location| location.prefix == "/" || path == location.prefix
+				|| (path.starts_with(&location.prefix) && path[location.prefix.len()..].starts_with('/')))
+			.max_by_key(|location| location.prefix.len())
+	}
+}
+
+impl LocationConf {
+	fn new(prefix: &str) -> LocationConf {
+		LocationConf {
+			prefix: prefix.to_string(),
+			deny: false,
+			autoindex: None,
+			compression: None,
+			index_files: None,
+			alternative_index_basename: None
+		}
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+		match key {
+			"deny" => self.deny = parse_bool(value)?,
+			"autoindex" => self.autoindex = Some(parse_bool(value)?),
+			"compression" => self.compression = Some(parse_bool(value)?),
+			"default_index_file" => self.index_files = Some(parse_list(value)),
+			"alternative_index_basename" => self.alternative_index_basename = Some(value.to_string()),
+			_ => return Ok(false)
+		}
+		Ok(true)
+	}
+
+	// Only the keys the location sets.
+	fn get_values(&self) -> Vec<(&'static str, String)> {
+		let mut values = vec![("deny", self.deny.to_string())];
+		if let Some(autoindex) = self.autoindex {
+			values.push(("autoindex", autoindex.to_string()));
+		}
+		if let Some(compression) = self.compression {
+			values.push(("compression", compression.to_string()));
+		}
+		if let Some(ref index_files) = self.index_files {
+			values.push(("default_index_file", index_files.join(";")));
+		}
+		if let Some(ref basename) = self.alternative_index_basename {
+			values.push(("alternative_index_basename", basename.clone()));
+		}
+		values
+	}
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+	match value {
+		"true" => Ok(true),
+		"false" => Ok(false),
+		_ => Err(format!("expected `true` or `false`, not `{}`", value))
+	}
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+	value.parse().map_err(|_| format!("expected a number, not `{}`", value))
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+	match parse_number(value)? {
+		0 => Err("must be at least 1".to_string()),
+		n => Ok(n)
+	}
+}
+
+fn parse_port(value: &str) -> Result<u16, String> {
+	match value.parse::<u16>() {
+		Ok(port) if port > 0 => Ok(port),
+		_ => Err(format!("expected a port from 1 to 65535, not `{}`", value))
+	}
+}
+
+fn parse_nonempty(value: &str) -> Result<String, String> {
+	if value.is_empty() {
+		Err("can't be empty".to_string())
+	}
+	else {
+		Ok(value.to_string())
+	}
+}
+
+// `500ms`, `5s`, `2m` or `1h`; a bare number counts in `unit`.
+fn parse_duration(value: &str, unit: Duration) -> Result<Duration, String> {
+	let digits_end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+	let amount: u32 = match value[..digits_end].parse() {
+		Ok(amount) => amount,
+		Err(_) => return Err(format!("expected a duration like `500ms`, `5s`, `2m` or `1h`, not `{}`", value))
+	};
+	let unit = match value[digits_end..].trim() {
+		"" => unit,
+		"ms" => Duration::from_millis(1),
+		"s" => Duration::from_secs(1),
+		"m" => Duration::from_secs(60),
+		"h" => Duration::from_secs(3600),
+		_ => return Err(format!("expected a duration like `500ms`, `5s`, `2m` or `1h`, not `{}`", value))
+	};
+	Ok(unit * amount)
+}
+
+// Timeouts in milliseconds when bare, 0 means none.
+fn parse_timeout(value: &str) -> Result<Option<Duration>, String> {
+	let duration = parse_duration(value, Duration::from_millis(1))?;
+	Ok(if duration == Duration::from_secs(0) { None } else { Some(duration) })
+}
+
+// `;`-separated, blank items left out.
+fn parse_list(value: &str) -> Vec<String> {
+	value.split(';')
+		.map(|item| item.trim())
+		.filter(|item| !item.is_empty())
+		.map(|item| item.to_string())
+		.collect()
+}
+
+// Pattern of an `include <file>` line, None for other lines.
+fn get_include(line: &str) -> Option<&str> {
+	if !line.starts_with("include") {
+		return None;
+	}
+	let rest = &line["include".len()..];
+	match rest.trim_start() {
+		pattern if rest.starts_with(char::is_whitespace) && !pattern.starts_with('=') => Some(pattern),
+		_ => None
+	}
+}
+
+// Files of an include pattern, relative to `dir` unless absolute. `*` and
+// `?` may be used in the file name: the matches are read in name order and
+// may be none, a plain name must exist.
+fn find_includes(pattern: &str, dir: &Path) -> Result<Vec<PathBuf>, String> {
+	let path = dir.join(pattern);
+	let name = match path.file_name().and_then(|name| name.to_str()) {
+		Some(name) => name.to_string(),
+		None => return Err(format!("`include {}` names no file", pattern))
+	};
+	let parent = path.parent().unwrap_or(dir);
+	if parent.to_string_lossy().contains(['*', '?']) {
+		return Err(format!("`include {}`: wildcards are only allowed in the file name", pattern));
+	}
+	if !name.contains(['*', '?']) {
+		return Ok(vec![path.clone()]);
+	}
+
+	let entries = match parent.read_dir() {
+		Ok(entries) => entries,
+		Err(e) => return Err(format!("can't read `{}` because: {}", parent.display(), e))
+	};
+	let mut files: Vec<PathBuf> = entries
+		.filter_map(|entry| entry.ok())
+		.filter(|entry| entry.file_name().to_str().is_some_and(|file_name| {
+			// Hidden files, like editor backups, only when asked for.
+			(!file_name.starts_with('.') || name.starts_with('.'))
+				&& is_glob_match(name.as_bytes(), file_name.as_bytes())
+		}))
+		.map(|entry| entry.path())
+		.filter(|file| file.is_file())
+		.collect();
+	files.sort();
+	Ok(files)
+}
+
+// `*` for any run of characters, `?` for one.
+fn is_glob_match(pattern: &[u8], name: &[u8]) -> bool {
+	match pattern.first() {
+		None => name.is_empty(),
+		Some(&b'*') => (0..=name.len()).any(|i| is_glob_match(&pattern[1..], &name[i..])),
+		Some(&b'?') => !name.is_empty() && is_glob_match(&pattern[1..], &name[1..]),
+		Some(&c) => name.first() == Some(&c) && is_glob_match(&pattern[1..], &name[1..])
+	}
+}
+
+// Written back as `parse_duration` reads it.
+fn format_duration(duration: Duration) -> String {
+	let millis = duration.as_millis();
+	if millis.is_multiple_of(1000) {
+		format!("{}s", millis / 1000)
+	}
+	else {
+		format!("{}ms", millis)
+	}
+}
+
+fn format_timeout(timeout: Option<Duration>) -> String {
+	timeout.map_or("0".to_string(), format_duration)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	// A directory of config files, removed when dropped.
+	struct ConfigDir(PathBuf);
+
+	impl ConfigDir {
+		fn new(name: &str, files: &[(&str, &str)]) -> ConfigDir {
+			let dir = env::temp_dir().join(format!("aden-config-{}-{}", name, process::id()));
+			fs::create_dir_all(&dir).unwrap();
+			for &(file, text) in files.iter() {
+				fs::write(dir.join(file), text).unwrap();
+			}
+			ConfigDir(dir)
+		}
+
+		fn load(&self, overrides: &[Override]) -> Result<Configuration, String> {
+			Configuration::from_file(&self.0.join("config.conf"), overrides).map_err(|e| e.to_string())
+		}
+	}
+
+	impl Drop for ConfigDir {
+		fn drop(&mut self) {
+			fs::remove_dir_all(&self.0);
+		}
+	}
+
+	#[test]
+	fn defaults_are_the_documented_ones() {
+		let dir = ConfigDir::new("defaults", &[("config.conf", "")]);
+		let config = dir.load(&[]).unwrap();
+
+		let server = &config.server;
+		assert_eq!((server.address.as_str(), server.port, server.io_engine.as_str()), ("localhost", 8080, "thread"));
+		assert_eq!((server.max_alive_thread, server.pool_queue_size), (8, 64));
+		assert_eq!((server.pool_overflow, server.retry_after), (OverflowPolicy::Block, Duration::from_secs(5)));
+		assert_eq!(server.tcp_read_timeout, None);
+		assert_eq!(server.keep_alive_timeout, Some(Duration::from_secs(5)));
+		assert_eq!(server.tcp_write_timeout, Some(Duration::from_secs(30)));
+		assert_eq!(server.keep_alive_max_requests, 100);
+		assert_eq!((server.max_header_size, server.max_header_count, server.max_body_size), (8192, 100, 10485760));
+		assert_eq!((server.default_host.as_str(), server.reject_unknown_hosts), ("", false));
+		assert_eq!((server.watch_config, server.drain_timeout), (false, Duration::from_secs(10)));
+
+		let logging = &config.logging;
+		assert_eq!((logging.level, logging.access_log, logging.file.as_str()), (Level::Info, true, ""));
+
+		let site = &config.site;
+		assert_eq!((site.home_dir.as_str(), site.home_dir_error.as_str()), ("/www", "/error"));
+		assert_eq!((site.index_files.clone(), site.alternative_index_basename.as_str()), (vec!["index.html".to_string()], "index"));
+		assert!(site.forbidden_dirs.is_empty() && site.autoindex_dirs.is_empty());
+		assert!(site.upload_dirs.is_empty() && site.upload_users.is_empty() && !site.webdav);
+		assert!(site.restrict_symlinks && site.compression && site.precompressed);
+		assert_eq!(site.compression_min_size, 1024);
+		assert_eq!(site.compression_types.join(";"), "text/*;application/javascript;application/json;application/xml;image/svg+xml");
+		assert_eq!(site.alias_file, "/config/alias.conf");
+		assert!(config.hosts.is_empty() && site.locations.is_empty() && config.warnings.is_empty());
+
+		// Every key of the dump is a default one, written back the way it is read.
+		let dump = config.dump();
+		let values: Vec<&str> = dump.lines().filter(|line| line.contains(" = ")).collect();
+		assert_eq!(values.len(), 18 + 15 + 3);
+		assert!(values.iter().all(|line| line.ends_with("  # default")), "{}", dump);
+		assert!(dump.contains("\nretry_after = 5s  # default\n"));
+		assert!(dump.contains("\ntcp_read_timeout = 0  # default\n"));
+		assert!(dump.contains("\nkeep_alive_timeout = 5s  # default\n"));
+	}
+
+	#[test]
+	fn bare_durations_use_the_unit_of_their_key() {
+		let text = "retry_after = 7\ndrain_timeout = 1500ms\ntcp_read_timeout = 250\n\
+			keep_alive_timeout = 0\ntcp_write_timeout = 2m\n";
+		let dir = ConfigDir::new("durations", &[("config.conf", text)]);
+		let server = dir.load(&[]).unwrap().server;
+		assert_eq!(server.retry_after, Duration::from_secs(7));
+		assert_eq!(server.drain_timeout, Duration::from_millis(1500));
+		assert_eq!(server.tcp_read_timeout, Some(Duration::from_millis(250)));
+		assert_eq!(server.keep_alive_timeout, None);
+		assert_eq!(server.tcp_write_timeout, Some(Duration::from_secs(120)));
+	}
+
+	#[test]
+	fn the_shipped_config_loads() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config").join("config.conf");
+		let config = Configuration::from_file(&path, &[]).unwrap();
+		assert!(config.warnings.is_empty());
+		assert_eq!(config.server.tcp_read_timeout, Some(Duration::from_secs(3)));
+		assert_eq!(config.site.index_files, vec!["index.html", "index.htm"]);
+	}
+
+	#[test]
+	fn reports_every_error_with_its_file_and_line() {
+		let text = "server_port = 0\n\
+			server_port = 70000\n\
+			io_engine = fibers\n\
+			max_alive_thread = 0\n\
+			pool_overflow = drop\n\
+			retry_after = 5 days\n\
+			tcp_read_timeout = soon\n\
+			max_body_size = -1\n\
+			watch_config = yes\n\
+			bogus = 1\n\
+			just a line\n\
+			server_address =\n\
+			[logging]\n\
+			level = loud\n\
+			access_log = 1\n\
+			port = 1\n\
+			[host example.com]\n\
+			server_port = 81\n\
+			upload_users = bob\n\
+			webdav = maybe\n\
+			[location /a]\n\
+			deny = nope\n\
+			home_dir = /x\n\
+			[nowhere]\n\
+			ignored = x\n\
+			[host bad/name]\n\
+			[location /a/]\n\
+			[server]\n\
+			include extra.conf\n\
+			include missing.conf\n\
+			default_host = nowhere.com\n";
+		let dir = ConfigDir::new("errors", &[("config.conf", text), ("extra.conf", "\nkeep_alive_max_requests = 0\n")]);
+		let errors = dir.load(&[]).unwrap_err();
+
+		let file = dir.0.join("config.conf").display().to_string();
+		let duration = "expected a duration like `500ms`, `5s`, `2m` or `1h`";
+		let expected = vec![
+			format!("{}:1: `server_port`: expected a port from 1 to 65535, not `0`", file),
+			format!("{}:2: `server_port`: expected a port from 1 to 65535, not `70000`", file),
+			format!("{}:3: `io_engine`: expected `thread` or `epoll`, not `fibers`", file),
+			format!("{}:4: `max_alive_thread`: must be at least 1", file),
+			format!("{}:5: `pool_overflow`: expected `block` or `reject`, not `drop`", file),
+			format!("{}:6: `retry_after`: {}, not `5 days`", file, duration),
+			format!("{}:7: `tcp_read_timeout`: {}, not `soon`", file, duration),
+			format!("{}:8: `max_body_size`: expected a number, not `-1`", file),
+			format!("{}:9: `watch_config`: expected `true` or `false`, not `yes`", file),
+			format!("{}:10: unknown key `bogus` in [server]", file),
+			format!("{}:11: expected `key = value` or `[section]`, not `just a line`", file),
+			format!("{}:12: `server_address`: can't be empty", file),
+			format!("{}:14: `level`: expected `error`, `warn`, `info` or `debug`, not `loud`", file),
+			format!("{}:15: `access_log`: expected `true` or `false`, not `1`", file),
+			format!("{}:16: unknown key `port` in [logging]", file),
+			format!("{}:18: `server_port` can only be set in [server]", file),
+			format!("{}:19: `upload_users`: expected `user:password`, not `bob`", file),
+			format!("{}:20: `webdav`: expected `true` or `false`, not `maybe`", file),
+			format!("{}:22: `deny`: expected `true` or `false`, not `nope`", file),
+			format!("{}:23: unknown key `home_dir` in [location /a]", file),
+			format!("{}:24: unknown section `[nowhere]`, expected [server], [logging], [host <name> ...] or [location /<path>]", file),
+			format!("{}:26: `bad/name` is not a host name", file),
+			format!("{}:27: location `/a` already has a section in this site", file),
+			// Lines of an included file are its own.
+			format!("{}:2: `keep_alive_max_requests`: must be at least 1", dir.0.join("extra.conf").display()),
+			format!("{}:30: can't find `{}`", file, dir.0.join("missing.conf").display()),
+			format!("{}:31: default_host `nowhere.com` has no [host] section", file),
+		];
+		let errors: Vec<&str> = errors.lines().collect();
+		assert_eq!(errors, expected);
+	}
+
+	#[test]
+	fn warns_about_keys_set_again() {
+		let text = "server_port = 81\n[server]\nserver_port = 82\n[logging]\nlevel = warn\n\
+			[logging]\nlevel = debug\n[host a.com]\nhome_dir = /a\nhome_dir = /b\n";
+		let dir = ConfigDir::new("again", &[("config.conf", text)]);
+		let config = dir.load(&[]).unwrap();
+		let file = dir.0.join("config.conf").display().to_string();
+		assert_eq!(config.warnings, vec![
+			format!("{f}:3: `server_port` is set again in [server], {f}:1 is overridden", f = file),
+			format!("{f}:7: `level` is set again in [logging], {f}:5 is overridden", f = file),
+			format!("{f}:10: `home_dir` is set again in [host a.com], {f}:9 is overridden", f = file),
+		]);
+
+		// The last value wins.
+		assert_eq!((config.server.port, config.logging.level), (82, Level::Debug));
+		assert_eq!(config.hosts[0].site.home_dir, "/b");
+		let dump = config.dump();
+		assert!(dump.contains(&format!("\nserver_port = 82  # {}:3\n", file)), "{}", dump);
+		assert!(dump.contains(&format!("# Warning: {}:3: `server_port` is set again", file)), "{}", dump);
+	}
+
+	#[test]
+	fn reports_bad_overrides_with_their_source() {
+		let dir = ConfigDir::new("overrides", &[("config.conf", "")]);
+		let overrides = [
+			Override { logging: false, key: "server_port".to_string(), value: "x".to_string(), source: "--port".to_string() },
+			Override { logging: true, key: "colour".to_string(), value: "red".to_string(), source: "environment `ADEN_LOGGING_COLOUR`".to_string() },
+		];
+		assert_eq!(dir.load(&overrides).unwrap_err(),
+			"--port: expected a port from 1 to 65535, not `x`\nenvironment `ADEN_LOGGING_COLOUR`: unknown key `colour`");
+	}
+
+	fn env(vars: &[(&str, &str)]) -> Vec<Override> {
+		Configuration::read_env_overrides(vars.iter().map(|&(name, value)| (name.to_string(), value.to_string())))
+	}
+
+	#[test]
+	fn reads_settings_from_the_environment() {
+		let overrides = env(&[
+			("ADEN_SERVER_PORT", "82"),
+			("ADEN_LOGGING_LEVEL", "debug"),
+			("ADEN_HOME_DIR", "/site"),
+			// Known with a bad value: kept for its error.
+			("ADEN_MAX_ALIVE_THREAD", "many"),
+			// Not settings of ours.
+			("ADEN_RELEASE", "1.2"),
+			("ADEN_LOGGING_COLOUR", "red"),
+			("ADEN_LEVEL", "debug"),
+			("PATH", "/bin"),
+		]);
+		let overrides: Vec<(bool, &str, &str)> = overrides.iter()
+			.map(|o| (o.logging, o.key.as_str(), o.value.as_str()))
+			.collect();
+		assert_eq!(overrides, vec![
+			(false, "home_dir", "/site"),
+			(true, "level", "debug"),
+			(false, "max_alive_thread", "many"),
+			(false, "server_port", "82"),
+		]);
+	}
+
+	#[test]
+	fn command_line_beats_environment_beats_files() {
+		let dir = ConfigDir::new("precedence", &[
+			("config.conf", "server_port = 81\nhome_dir = /file\ninclude site.conf\n[logging]\nlevel = warn\n"),
+			("site.conf", "home_dir = /included\n"),
+		]);
+		let file = dir.0.join("config.conf").display().to_string();
+
+		let config = dir.load(&[]).unwrap();
+		assert_eq!((config.server.port, config.site.home_dir.as_str()), (81, "/included"));
+
+		let mut overrides = env(&[("ADEN_SERVER_PORT", "82"), ("ADEN_LOGGING_LEVEL", "error")]);
+		let config = dir.load(&overrides).unwrap();
+		assert_eq!((config.server.port, config.logging.level), (82, Level::Error));
+
+		overrides.push(Override { logging: false, key: "server_port".to_string(), value: "83".to_string(), source: "--port".to_string() });
+		let config = dir.load(&overrides).unwrap();
+		assert_eq!((config.server.port, config.logging.level), (83, Level::Error));
+
+		let dump = config.dump();
+		assert!(dump.contains("\nserver_port = 83  # --port\n"), "{}", dump);
+		assert!(dump.contains("\nlevel = error  # environment `ADEN_LOGGING_LEVEL`\n"), "{}", dump);
+		assert!(dump.contains(&format!("\nhome_dir = /included  # {}:1\n", dir.0.join("site.conf").display())), "{}", dump);
+		assert!(dump.contains("\nserver_address = localhost  # default\n"), "{}", dump);
+		assert!(dump.contains(&format!("# Read {}\n", file)), "{}", dump);
+	}
+
+	#[test]
+	fn matches_include_patterns() {
+		assert!(is_glob_match(b"*.conf", b"a.conf"));
+		assert!(is_glob_match(b"*.conf", b".conf"));
+		assert!(is_glob_match(b"site-?.conf", b"site-1.conf"));
+		assert!(is_glob_match(b"*-*.conf", b"a-b-c.conf"));
+		assert!(is_glob_match(b"*", b""));
+		assert!(is_glob_match(b"a.conf", b"a.conf"));
+		assert!(!is_glob_match(b"*.conf", b"a.conf~"));
+		assert!(!is_glob_match(b"site-?.conf", b"site-10.conf"));
+		assert!(!is_glob_match(b"?", b""));
+		assert!(!is_glob_match(b"a.conf", b"A.conf"));
+	}
+
+	#[test]
+	fn includes_every_match_in_name_order() {
+		let dir = ConfigDir::new("include", &[
+			("config.conf", "include conf.d/*.conf\ninclude conf.d/*.none\n[logging]\ninclude log?.conf\n"),
+			("log1.conf", "level = debug\n"),
+		]);
+		fs::create_dir(dir.0.join("conf.d")).unwrap();
+		for &(file, text) in [
+			("b.conf", "[host b.com]\nhome_dir = /b\n"),
+			("a.conf", "[host a.com]\nhome_dir = /a\n"),
+			// Hidden, not matching and directories are left out.
+			(".c.conf", "[host c.com]\n"),
+			("d.conf.bak", "[host d.com]\n"),
+		].iter() {
+			fs::write(dir.0.join("conf.d").join(file), text).unwrap();
+		}
+		fs::create_dir(dir.0.join("conf.d").join("e.conf")).unwrap();
+
+		let config = dir.load(&[]).unwrap();
+		let hosts: Vec<&str> = config.hosts.iter().map(|host| host.names[0].as_str()).collect();
+		assert_eq!(hosts, vec!["a.com", "b.com"]);
+		assert_eq!(config.logging.level, Level::Debug);
+		let files: Vec<PathBuf> = config.files.iter().map(|file| file.strip_prefix(&dir.0).unwrap().to_path_buf()).collect();
+		assert_eq!(files, vec![
+			PathBuf::from("config.conf"),
+			Path::new("conf.d").join("a.conf"),
+			Path::new("conf.d").join("b.conf"),
+			PathBuf::from("log1.conf"),
+		]);
+	}
 
-		default.to_string()
+	#[test]
+	fn refuses_bad_includes() {
+		let dir = ConfigDir::new("bad-include", &[
+			("config.conf", "include */x.conf\ninclude loop.conf\n"),
+			("loop.conf", "include config.conf\n"),
+		]);
+		let file = dir.0.join("config.conf").display().to_string();
+		assert_eq!(dir.load(&[]).unwrap_err(), format!(
+			"{f}:1: `include */x.conf`: wildcards are only allowed in the file name\n\
+			{l}:1: `{f}` is already being read: include loop",
+			f = file, l = dir.0.join("loop.conf").display()));
 	}
 }
@@ -0,0 +1,984 @@
+/*
+ *	DEFLATE (RFC 1951) encoder for response compression, with the zlib
+ *	(RFC 1950) and gzip (RFC 1952) formats around it.
+ *	Input is compressed in blocks as it comes, so bodies can be streamed.
+ */
+use std::mem;
+
+const WINDOW_SIZE: usize = 32 * 1024;
+// Input bytes compressed per block.
+const BLOCK_SIZE: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_SIZE: usize = 1 << 15;
+// Candidates tried for each match, more is smaller output but slower.
+const MAX_CHAIN: usize = 64;
+const NIL: u32 = u32::MAX;
+
+const END_OF_BLOCK: usize = 256;
+const LITLEN_CODES: usize = 286;
+const DIST_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order the code length code lengths are written in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for b in data {
+        c = CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    // Largest run before the sums can overflow a u32.
+    for piece in data.chunks(5552) {
+        for byte in piece {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// Writes bits from the least significant one, as DEFLATE wants.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: vec![],
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Pad with zero bits to the next byte.
+    fn align(&mut self) {
+        if self.count > 0 {
+            let pad = 8 - self.count;
+            self.write(0, pad);
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.align();
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        mem::take(&mut self.out)
+    }
+}
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    ((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize)
+        & (HASH_SIZE - 1)
+}
+
+// Chain `pos` in the hash table, if 3 bytes are left from it.
+fn insert_hash(data: &[u8], pos: usize, head: &mut [u32], prev: &mut [u32]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash3(data, pos);
+        prev[pos] = head[h];
+        head[h] = pos as u32;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    // Length, distance.
+    Match(u16, u16),
+}
+
+// Code index and extra bits of a match length (3..=258).
+fn length_code(len: u16) -> (usize, u32, u32) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base <= len).unwrap_or(0);
+    (
+        code,
+        LENGTH_EXTRA[code] as u32,
+        (len - LENGTH_BASE[code]) as u32,
+    )
+}
+
+// Code index and extra bits of a match distance (1..=32768).
+fn dist_code(dist: u16) -> (usize, u32, u32) {
+    let code = DIST_BASE.iter().rposition(|base| *base <= dist).unwrap_or(0);
+    (code, DIST_EXTRA[code] as u32, (dist - DIST_BASE[code]) as u32)
+}
+
+fn fixed_litlen_lengths() -> Vec<u8> {
+    (0..288)
+        .map(|sym| match sym {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect()
+}
+
+// Huffman code lengths for `freqs`, none longer than `max_len`.
+fn build_lengths(freqs: &[u32], max_len: usize) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut used: Vec<usize> = (0..freqs.len()).filter(|s| freqs[*s] > 0).collect();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+    }
+    if used.len() < 2 {
+        return lengths;
+    }
+
+    // Plain Huffman tree; leaves are 0..used.len(), then inner nodes.
+    let leaves = used.len();
+    let mut weights: Vec<u64> = used.iter().map(|s| freqs[*s] as u64).collect();
+    let mut parent: Vec<usize> = vec![0; leaves * 2 - 1];
+    let mut alive: Vec<usize> = (0..leaves).collect();
+    while alive.len() > 1 {
+        alive.sort_by(|a, b| weights[*b].cmp(&weights[*a]));
+        let (x, y) = (alive.pop().unwrap(), alive.pop().unwrap());
+        let node = weights.len();
+        weights.push(weights[x] + weights[y]);
+        parent[x] = node;
+        parent[y] = node;
+        alive.push(node);
+    }
+    let root = weights.len() - 1;
+
+    let mut depth_count = vec![0usize; leaves + 1];
+    for leaf in 0..leaves {
+        let (mut node, mut depth) = (leaf, 0);
+        while node != root {
+            node = parent[node];
+            depth += 1;
+        }
+        depth_count[depth] += 1;
+    }
+
+    // Move leaves up until the tree fits in `max_len` (as in JPEG, annex K.3).
+    let mut bits = depth_count.len() - 1;
+    while bits > max_len {
+        while depth_count[bits] > 0 {
+            let mut j = bits - 2;
+            while depth_count[j] == 0 {
+                j -= 1;
+            }
+            depth_count[bits] -= 2;
+            depth_count[bits - 1] += 1;
+            depth_count[j + 1] += 2;
+            depth_count[j] -= 1;
+        }
+        bits -= 1;
+    }
+
+    // Shortest codes for the most frequent symbols.
+    used.sort_by(|a, b| freqs[*b].cmp(&freqs[*a]));
+    let mut symbols = used.into_iter();
+    for (len, count) in depth_count.iter().enumerate().take(max_len + 1) {
+        for _ in 0..*count {
+            if let Some(sym) = symbols.next() {
+                lengths[sym] = len as u8;
+            }
+        }
+    }
+    lengths
+}
+
+// Canonical codes of `lengths`, bit-reversed to be written LSB first.
+fn build_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut length_count = [0u32; 16];
+    for len in lengths {
+        length_count[*len as usize] += 1;
+    }
+    length_count[0] = 0;
+
+    let mut next_code = [0u32; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + length_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|len| {
+            let len = *len as usize;
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len];
+            next_code[len] += 1;
+            code.reverse_bits() >> (32 - len)
+        })
+        .collect()
+}
+
+// Run-length coding of the code lengths (symbols 16, 17, 18) as
+// (symbol, extra bits, extra value).
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(usize, u32, u32)> {
+    let mut encoded: Vec<(usize, u32, u32)> = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|len| **len == value).count();
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                encoded.push((18, 7, (n - 11) as u32));
+                run -= n;
+            }
+            if run >= 3 {
+                encoded.push((17, 3, (run - 3) as u32));
+                run = 0;
+            }
+        } else {
+            encoded.push((value as usize, 0, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                encoded.push((16, 2, (n - 3) as u32));
+                run -= n;
+            }
+        }
+
+        for _ in 0..run {
+            encoded.push((value as usize, 0, 0));
+        }
+    }
+    encoded
+}
+
+pub struct Deflater {
+    // Last WINDOW_SIZE bytes already compressed, then pending input.
+    data: Vec<u8>,
+    history_len: usize,
+    bits: BitWriter,
+}
+
+impl Deflater {
+    pub fn new() -> Deflater {
+        Deflater {
+            data: vec![],
+            history_len: 0,
+            bits: BitWriter::new(),
+        }
+    }
+
+    // Compress what `input` completes of a block, return the output so far.
+    pub fn write(&mut self, input: &[u8]) -> Vec<u8> {
+        self.data.extend_from_slice(input);
+        while self.data.len() - self.history_len >= BLOCK_SIZE {
+            let block_end = self.history_len + BLOCK_SIZE;
+            self.compress_block(block_end, false);
+        }
+        self.bits.take()
+    }
+
+    // Compress the rest as the final block.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let block_end = self.data.len();
+        self.compress_block(block_end, true);
+        self.bits.align();
+        self.bits.take()
+    }
+
+    fn compress_block(&mut self, block_end: usize, last: bool) {
+        let symbols = self.find_matches(block_end);
+        let raw = self.data[self.history_len..block_end].to_vec();
+        self.write_block(&symbols, &raw, last);
+
+        if block_end > WINDOW_SIZE {
+            self.data.drain(..block_end - WINDOW_SIZE);
+        }
+        self.history_len = block_end.min(WINDOW_SIZE);
+    }
+
+    // LZ77 on the pending bytes up to `end`, with hash chains over the window.
+    fn find_matches(&self, end: usize) -> Vec<Symbol> {
+        let data = &self.data[..end];
+        let mut head = vec![NIL; HASH_SIZE];
+        let mut prev = vec![NIL; end];
+        for pos in 0..self.history_len {
+            insert_hash(data, pos, &mut head, &mut prev);
+        }
+
+        let mut symbols: Vec<Symbol> = vec![];
+        let mut pos = self.history_len;
+        while pos < end {
+            let max_len = MAX_MATCH.min(end - pos);
+            let (mut best_len, mut best_dist) = (0, 0);
+
+            if max_len >= MIN_MATCH {
+                let mut candidate = head[hash3(data, pos)];
+                let mut chain = 0;
+                while candidate != NIL && chain < MAX_CHAIN {
+                    let cand = candidate as usize;
+                    if pos - cand > WINDOW_SIZE {
+                        break;
+                    }
+                    if data[cand + best_len] == data[pos + best_len] {
+                        let len = data[cand..cand + max_len]
+                            .iter()
+                            .zip(&data[pos..pos + max_len])
+                            .take_while(|&(a, b)| a == b)
+                            .count();
+                        if len > best_len {
+                            best_len = len;
+                            best_dist = pos - cand;
+                            if len == max_len {
+                                break;
+                            }
+                        }
+                    }
+                    candidate = prev[cand];
+                    chain += 1;
+                }
+            }
+
+            if best_len >= MIN_MATCH {
+                symbols.push(Symbol::Match(best_len as u16, best_dist as u16));
+                for p in pos..pos + best_len {
+                    insert_hash(data, p, &mut head, &mut prev);
+                }
+                pos += best_len;
+            } else {
+                symbols.push(Symbol::Literal(data[pos]));
+                insert_hash(data, pos, &mut head, &mut prev);
+                pos += 1;
+            }
+        }
+        symbols
+    }
+
+    // Write one block as stored, fixed or dynamic Huffman, whichever is smallest.
+    fn write_block(&mut self, symbols: &[Symbol], raw: &[u8], last: bool) {
+        let mut litlen_freqs = vec![0u32; LITLEN_CODES];
+        let mut dist_freqs = vec![0u32; DIST_CODES];
+        let mut extra_bits: u64 = 0;
+        litlen_freqs[END_OF_BLOCK] = 1;
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Literal(byte) => litlen_freqs[byte as usize] += 1,
+                Symbol::Match(len, dist) => {
+                    let (len_code, len_extra, _) = length_code(len);
+                    let (dist_code, dist_extra, _) = dist_code(dist);
+                    litlen_freqs[257 + len_code] += 1;
+                    dist_freqs[dist_code] += 1;
+                    extra_bits += (len_extra + dist_extra) as u64;
+                }
+            }
+        }
+
+        // Both trees get at least two codes, some decoders refuse less.
+        let mut tree_litlen_freqs = litlen_freqs.clone();
+        let mut tree_dist_freqs = dist_freqs.clone();
+        for freqs in [&mut tree_litlen_freqs, &mut tree_dist_freqs].iter_mut() {
+            for sym in 0..2 {
+                if freqs.iter().filter(|f| **f > 0).count() < 2 && freqs[sym] == 0 {
+                    freqs[sym] = 1;
+                }
+            }
+        }
+
+        let litlen_lengths = build_lengths(&tree_litlen_freqs, 15);
+        let dist_lengths = build_lengths(&tree_dist_freqs, 15);
+        let hlit = 257.max(litlen_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+        let hdist = 1.max(dist_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+
+        let mut all_lengths = litlen_lengths[..hlit].to_vec();
+        all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+        let code_lengths = encode_code_lengths(&all_lengths);
+        let mut cl_freqs = vec![0u32; 19];
+        for &(sym, _, _) in code_lengths.iter() {
+            cl_freqs[sym] += 1;
+        }
+        let cl_lengths = build_lengths(&cl_freqs, 7);
+        let hclen = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|sym| cl_lengths[*sym] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+
+        let data_cost = |litlen: &[u8], dist: &[u8]| -> u64 {
+            let litlen_cost: u64 = litlen_freqs
+                .iter()
+                .zip(litlen)
+                .map(|(f, l)| *f as u64 * *l as u64)
+                .sum();
+            let dist_cost: u64 = dist_freqs
+                .iter()
+                .zip(dist)
+                .map(|(f, l)| *f as u64 * *l as u64)
+                .sum();
+            litlen_cost + dist_cost + extra_bits
+        };
+
+        let header_cost: u64 = 14
+            + 3 * hclen as u64
+            + code_lengths
+                .iter()
+                .map(|&(sym, extra, _)| cl_lengths[sym] as u64 + extra as u64)
+                .sum::<u64>();
+        let dynamic_cost = 3 + header_cost + data_cost(&litlen_lengths, &dist_lengths);
+        let fixed_litlen = fixed_litlen_lengths();
+        let fixed_dist = vec![5u8; DIST_CODES];
+        let fixed_cost = 3 + data_cost(&fixed_litlen, &fixed_dist);
+        let stored_blocks = 1.max(raw.len().div_ceil(65535)) as u64;
+        let stored_cost = stored_blocks * (3 + 7 + 32) + raw.len() as u64 * 8;
+
+        if stored_cost < fixed_cost.min(dynamic_cost) {
+            self.write_stored(raw, last);
+        } else if fixed_cost <= dynamic_cost {
+            self.bits.write(last as u32, 1);
+            self.bits.write(1, 2);
+            self.write_symbols(symbols, &fixed_litlen, &fixed_dist);
+        } else {
+            self.bits.write(last as u32, 1);
+            self.bits.write(2, 2);
+            self.bits.write((hlit - 257) as u32, 5);
+            self.bits.write((hdist - 1) as u32, 5);
+            self.bits.write((hclen - 4) as u32, 4);
+            for sym in CODE_LENGTH_ORDER.iter().take(hclen) {
+                self.bits.write(cl_lengths[*sym] as u32, 3);
+            }
+
+            let cl_codes = build_codes(&cl_lengths);
+            for &(sym, extra, value) in code_lengths.iter() {
+                self.bits.write(cl_codes[sym], cl_lengths[sym] as u32);
+                self.bits.write(value, extra);
+            }
+            self.write_symbols(symbols, &litlen_lengths, &dist_lengths);
+        }
+    }
+
+    fn write_stored(&mut self, raw: &[u8], last: bool) {
+        let mut pieces: Vec<&[u8]> = raw.chunks(65535).collect();
+        if pieces.is_empty() {
+            pieces.push(&[]);
+        }
+
+        let count = pieces.len();
+        for (i, piece) in pieces.into_iter().enumerate() {
+            self.bits.write((last && i + 1 == count) as u32, 1);
+            self.bits.write(0, 2);
+            self.bits.align();
+            let len = piece.len() as u16;
+            self.bits.write_bytes(&len.to_le_bytes());
+            self.bits.write_bytes(&(!len).to_le_bytes());
+            self.bits.write_bytes(piece);
+        }
+    }
+
+    fn write_symbols(&mut self, symbols: &[Symbol], litlen_lengths: &[u8], dist_lengths: &[u8]) {
+        let litlen_codes = build_codes(litlen_lengths);
+        let dist_codes = build_codes(dist_lengths);
+
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Literal(byte) => {
+                    let sym = byte as usize;
+                    self.bits.write(litlen_codes[sym], litlen_lengths[sym] as u32);
+                }
+                Symbol::Match(len, dist) => {
+                    let (code, extra, value) = length_code(len);
+                    let sym = 257 + code;
+                    self.bits.write(litlen_codes[sym], litlen_lengths[sym] as u32);
+                    self.bits.write(value, extra);
+
+                    let (code, extra, value) = dist_code(dist);
+                    self.bits.write(dist_codes[code], dist_lengths[code] as u32);
+                    self.bits.write(value, extra);
+                }
+            }
+        }
+
+        self.bits.write(
+            litlen_codes[END_OF_BLOCK],
+            litlen_lengths[END_OF_BLOCK] as u32,
+        );
+    }
+}
+
+// Content-codings we can produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+}
+
+// DEFLATE data with the gzip or zlib header and checksum.
+pub struct Encoder {
+    coding: ContentCoding,
+    deflater: Deflater,
+    header_sent: bool,
+    checksum: u32,
+    input_size: u32,
+}
+
+impl Encoder {
+    pub fn new(coding: ContentCoding) -> Encoder {
+        Encoder {
+            coding,
+            deflater: Deflater::new(),
+            header_sent: false,
+            checksum: match coding {
+                ContentCoding::Gzip => 0,
+                ContentCoding::Deflate => 1,
+            },
+            input_size: 0,
+        }
+    }
+
+    pub fn write(&mut self, input: &[u8]) -> Vec<u8> {
+        self.checksum = match self.coding {
+            ContentCoding::Gzip => crc32_update(self.checksum, input),
+            ContentCoding::Deflate => adler32_update(self.checksum, input),
+        };
+        self.input_size = self.input_size.wrapping_add(input.len() as u32);
+
+        let mut out = self.take_header();
+        out.extend(self.deflater.write(input));
+        out
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = self.take_header();
+        out.extend(self.deflater.finish());
+        match self.coding {
+            ContentCoding::Gzip => {
+                out.extend_from_slice(&self.checksum.to_le_bytes());
+                out.extend_from_slice(&self.input_size.to_le_bytes());
+            }
+            ContentCoding::Deflate => out.extend_from_slice(&self.checksum.to_be_bytes()),
+        }
+        out
+    }
+
+    fn take_header(&mut self) -> Vec<u8> {
+        if self.header_sent {
+            return vec![];
+        }
+
+        self.header_sent = true;
+        match self.coding {
+            // No name, no mtime, unknown OS.
+            ContentCoding::Gzip => vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff],
+            // 32K window, default level.
+            ContentCoding::Deflate => vec![0x78, 0x9c],
+        }
+    }
+}
+
+// The inflater is also used by the tests of compress.rs.
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // Tables of RFC 1951 3.2.5, written again so a mistake above shows.
+    const LENGTHS: [(u16, u32); 29] = [
+        (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0), (11, 1), (13, 1),
+        (15, 1), (17, 1), (19, 2), (23, 2), (27, 2), (31, 2), (35, 3), (43, 3), (51, 3),
+        (59, 3), (67, 4), (83, 4), (99, 4), (115, 4), (131, 5), (163, 5), (195, 5), (227, 5),
+        (258, 0),
+    ];
+    const DISTANCES: [(u16, u32); 30] = [
+        (1, 0), (2, 0), (3, 0), (4, 0), (5, 1), (7, 1), (9, 2), (13, 2), (17, 3), (25, 3),
+        (33, 4), (49, 4), (65, 5), (97, 5), (129, 6), (193, 6), (257, 7), (385, 7), (513, 8),
+        (769, 8), (1025, 9), (1537, 9), (2049, 10), (3073, 10), (4097, 11), (6145, 11),
+        (8193, 12), (12289, 12), (16385, 13), (24577, 13),
+    ];
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit_pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self.data[self.bit_pos / 8];
+                value |= ((byte >> (self.bit_pos % 8)) as u32 & 1) << i;
+                self.bit_pos += 1;
+            }
+            value
+        }
+
+        fn align(&mut self) {
+            self.bit_pos = self.bit_pos.div_ceil(8) * 8;
+        }
+    }
+
+    // Canonical Huffman decoding table: code count per length, symbols by code.
+    struct Huffman {
+        counts: [i32; 16],
+        symbols: Vec<usize>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Huffman {
+            let mut counts = [0; 16];
+            for len in lengths {
+                counts[*len as usize] += 1;
+            }
+            counts[0] = 0;
+            let mut symbols: Vec<usize> = (0..lengths.len()).filter(|s| lengths[*s] > 0).collect();
+            symbols.sort_by_key(|s| lengths[*s]);
+            Huffman { counts, symbols }
+        }
+
+        fn decode(&self, reader: &mut BitReader) -> usize {
+            let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+            for len in 1..16 {
+                code |= reader.bits(1) as i32;
+                let count = self.counts[len];
+                if code - first < count {
+                    return self.symbols[(index + code - first) as usize];
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            panic!("invalid Huffman code");
+        }
+    }
+
+    // What an inflated stream was made of.
+    #[derive(Debug, Default)]
+    pub struct Inflated {
+        pub data: Vec<u8>,
+        // Block types in order: 0 stored, 1 fixed, 2 dynamic.
+        blocks: Vec<u32>,
+        longest_match: usize,
+        farthest_match: usize,
+        // Bytes read, up to the end of the last block.
+        pub consumed: usize,
+    }
+
+    pub fn inflate(data: &[u8]) -> Inflated {
+        let mut reader = BitReader { data, bit_pos: 0 };
+        let mut out = Inflated::default();
+        loop {
+            let last = reader.bits(1) == 1;
+            let block_type = reader.bits(2);
+            out.blocks.push(block_type);
+            match block_type {
+                0 => {
+                    reader.align();
+                    let len = reader.bits(16);
+                    assert_eq!(reader.bits(16), !len & 0xffff);
+                    for _ in 0..len {
+                        let byte = reader.bits(8) as u8;
+                        out.data.push(byte);
+                    }
+                }
+                1 => {
+                    let litlen = Huffman::new(&fixed_litlen_lengths());
+                    let dist = Huffman::new(&[5; 30]);
+                    inflate_symbols(&mut reader, &litlen, &dist, &mut out);
+                }
+                2 => {
+                    let hlit = reader.bits(5) as usize + 257;
+                    let hdist = reader.bits(5) as usize + 1;
+                    let hclen = reader.bits(4) as usize + 4;
+                    let mut cl_lengths = [0u8; 19];
+                    for sym in [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15]
+                        .iter()
+                        .take(hclen)
+                    {
+                        cl_lengths[*sym] = reader.bits(3) as u8;
+                    }
+                    let cl = Huffman::new(&cl_lengths);
+                    let mut lengths: Vec<u8> = vec![];
+                    while lengths.len() < hlit + hdist {
+                        match cl.decode(&mut reader) {
+                            sym @ 0..=15 => lengths.push(sym as u8),
+                            16 => {
+                                let prev = *lengths.last().unwrap();
+                                let repeat = 3 + reader.bits(2);
+                                lengths.extend((0..repeat).map(|_| prev));
+                            }
+                            17 => lengths.extend((0..3 + reader.bits(3)).map(|_| 0)),
+                            _ => lengths.extend((0..11 + reader.bits(7)).map(|_| 0)),
+                        }
+                    }
+                    assert_eq!(lengths.len(), hlit + hdist);
+                    let litlen = Huffman::new(&lengths[..hlit]);
+                    let dist = Huffman::new(&lengths[hlit..]);
+                    inflate_symbols(&mut reader, &litlen, &dist, &mut out);
+                }
+                _ => panic!("reserved block type"),
+            }
+            if last {
+                out.consumed = reader.bit_pos.div_ceil(8);
+                return out;
+            }
+        }
+    }
+
+    fn inflate_symbols(reader: &mut BitReader, litlen: &Huffman, dist: &Huffman, out: &mut Inflated) {
+        loop {
+            let sym = litlen.decode(reader);
+            if sym < 256 {
+                out.data.push(sym as u8);
+                continue;
+            }
+            if sym == END_OF_BLOCK {
+                return;
+            }
+            let (base, extra) = LENGTHS[sym - 257];
+            let len = base as usize + reader.bits(extra) as usize;
+            let (base, extra) = DISTANCES[dist.decode(reader)];
+            let distance = base as usize + reader.bits(extra) as usize;
+            assert!(distance <= out.data.len() && distance <= WINDOW_SIZE);
+            for _ in 0..len {
+                let byte = out.data[out.data.len() - distance];
+                out.data.push(byte);
+            }
+            out.longest_match = out.longest_match.max(len);
+            out.farthest_match = out.farthest_match.max(distance);
+        }
+    }
+
+    fn deflate(input: &[u8]) -> Vec<u8> {
+        let mut deflater = Deflater::new();
+        let mut out = deflater.write(input);
+        out.extend(deflater.finish());
+        out
+    }
+
+    // Bytes that don't compress, from a xorshift generator.
+    pub fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    // Text-like bytes: words of a small vocabulary, compressible but not runs.
+    pub fn words(len: usize) -> Vec<u8> {
+        let vocabulary = [
+            "the ", "server ", "sends ", "a ", "response ", "with ", "gzip ", "when ", "asked ",
+            "for, ", "and ", "keeps ", "the ", "connection ", "open.\n", "Files ", "are ",
+        ];
+        let picks = noise(len, 7);
+        let mut text = vec![];
+        let mut i = 0;
+        while text.len() < len {
+            text.extend_from_slice(vocabulary[picks[i] as usize % vocabulary.len()].as_bytes());
+            i += 1;
+        }
+        text.truncate(len);
+        text
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(crc32_update(0, b""), 0);
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf43926);
+        assert_eq!(
+            crc32_update(0, b"The quick brown fox jumps over the lazy dog"),
+            0x414fa339
+        );
+        assert_eq!(crc32_update(0, &[0xff; 100000]), 0x68c6cec4);
+        // Computed in pieces as it is while streaming.
+        assert_eq!(crc32_update(crc32_update(0, b"12345"), b"6789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn adler32_known_answers() {
+        assert_eq!(adler32_update(1, b""), 1);
+        assert_eq!(adler32_update(1, b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32_update(1, b"123456789"), 0x091e01de);
+        // Longer than the run the sums are taken modulo after.
+        assert_eq!(adler32_update(1, &[0xff; 100000]), 0x149a302c);
+        assert_eq!(adler32_update(adler32_update(1, b"Wiki"), b"pedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn empty_input() {
+        let inflated = inflate(&deflate(b""));
+        assert!(inflated.data.is_empty());
+
+        // As gzip(1) writes it: a fixed block with only its end.
+        let mut encoder = Encoder::new(ContentCoding::Gzip);
+        assert_eq!(
+            encoder.finish(),
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let mut encoder = Encoder::new(ContentCoding::Deflate);
+        assert_eq!(encoder.finish(), [0x78, 0x9c, 3, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn short_input_uses_a_fixed_block() {
+        let input = b"hello hello hello hello";
+        let inflated = inflate(&deflate(input));
+        assert_eq!(inflated.data, input);
+        assert_eq!(inflated.blocks, [1]);
+        assert!(inflated.longest_match >= 12);
+    }
+
+    #[test]
+    fn text_uses_dynamic_blocks() {
+        let input = words(20000);
+        let compressed = deflate(&input);
+        let inflated = inflate(&compressed);
+        assert_eq!(inflated.data, input);
+        assert_eq!(inflated.blocks, [2]);
+        assert!(compressed.len() < input.len() / 3);
+    }
+
+    #[test]
+    fn noise_uses_stored_blocks() {
+        let input = noise(100000, 1);
+        let compressed = deflate(&input);
+        let inflated = inflate(&compressed);
+        assert_eq!(inflated.data, input);
+        assert!(inflated.blocks.iter().all(|block| *block == 0));
+        // One block of 64 KiB and the rest, stored ones hold 65535 bytes at most.
+        assert_eq!(inflated.blocks.len(), 3);
+        assert!(compressed.len() < input.len() + 20);
+    }
+
+    #[test]
+    fn input_over_64k_spans_blocks() {
+        // Each part fills whole blocks, but the last one. A stored block
+        // holds 65535 bytes at most, the noise takes two.
+        let mut input = words(2 * BLOCK_SIZE);
+        input.extend(noise(BLOCK_SIZE, 3));
+        input.extend(words(50000));
+        let inflated = inflate(&deflate(&input));
+        assert_eq!(inflated.data, input);
+        assert_eq!(inflated.blocks, [2, 2, 0, 0, 2]);
+    }
+
+    #[test]
+    fn long_back_references() {
+        // A run gives matches of the longest length.
+        let mut input = vec![b'a'; 5000];
+        // The same noise again 30000 bytes later, near the end of the window,
+        // and across the first block boundary.
+        let repeated = noise(20000, 5);
+        input.extend_from_slice(&repeated);
+        input.extend(noise(10000, 9));
+        input.extend_from_slice(&repeated);
+        input.extend(noise(40000, 11));
+        let inflated = inflate(&deflate(&input));
+        assert_eq!(inflated.data, input);
+        assert_eq!(inflated.longest_match, MAX_MATCH);
+        assert!(inflated.farthest_match >= 30000);
+    }
+
+    #[test]
+    fn streaming_writes_give_the_same_stream() {
+        let input = words(200000);
+        let mut deflater = Deflater::new();
+        let mut streamed = vec![];
+        for piece in input.chunks(1000) {
+            streamed.extend(deflater.write(piece));
+        }
+        streamed.extend(deflater.finish());
+        assert_eq!(streamed, deflate(&input));
+    }
+
+    #[test]
+    fn gzip_and_zlib_wrappers() {
+        let input = words(100000);
+        for coding in [ContentCoding::Gzip, ContentCoding::Deflate].iter() {
+            let mut encoder = Encoder::new(*coding);
+            let mut out = vec![];
+            for piece in input.chunks(7000) {
+                out.extend(encoder.write(piece));
+            }
+            out.extend(encoder.finish());
+
+            let header_len = if *coding == ContentCoding::Gzip { 10 } else { 2 };
+            let inflated = inflate(&out[header_len..]);
+            assert_eq!(inflated.data, input);
+            let trailer = &out[header_len + inflated.consumed..];
+            match *coding {
+                ContentCoding::Gzip => {
+                    assert_eq!(&out[..3], [0x1f, 0x8b, 8]);
+                    assert_eq!(trailer[..4], crc32_update(0, &input).to_le_bytes());
+                    assert_eq!(trailer[4..], (input.len() as u32).to_le_bytes());
+                }
+                ContentCoding::Deflate => {
+                    assert_eq!((out[0] as u16 * 256 + out[1] as u16) % 31, 0);
+                    assert_eq!(trailer, adler32_update(1, &input).to_be_bytes());
+                }
+            }
+        }
+    }
+}
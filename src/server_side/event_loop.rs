@@ -0,0 +1,413 @@
+/*
+ *	Event-driven I/O engine: one thread, non-blocking sockets and epoll.
+ *	Requests are handled by the same code as the threaded engine.
+ *	Files are read with blocking calls on the loop thread, one
+ *	STREAM_BUFFER_SIZE chunk at a time: a slow disk stalls every connection.
+ */
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::net;
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use client_side::request;
+use client_side::request::RequestParser;
+use server_side::epoll::{self, Epoll};
+use server_side::live::Snapshot;
+use server_side::monitor::{Monitor, Tracked};
+use server_side::response::Response;
+use server_side::status::HttpStatus;
+use server_side::{log, utils, ConnectionLimits, Server};
+
+const MAX_EVENTS: usize = 256;
+// How often idle connections are checked for timeouts.
+const TICK_MILLIS: i32 = 1000;
+// Bytes of a file read per step, so one big download can't hold the loop.
+const STREAM_BUFFER_SIZE: u64 = 64 * 1024;
+// Chunks one connection may send before the others get their turn.
+const MAX_CHUNKS_PER_TURN: usize = 16;
+// Out of file descriptors: time the listener is left alone before accepting again.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(500);
+// errno of accept() when the process (EMFILE) or the system (ENFILE) has no
+// file descriptor left.
+const EMFILE: i32 = 24;
+const ENFILE: i32 = 23;
+
+struct Connection {
+    stream: TcpStream,
+    ip: String,
+    tracked: Tracked,
+    // Settings at accept time, kept until the connection closes.
+    snapshot: Arc<Snapshot>,
+    limits: ConnectionLimits,
+    // Received but not parsed yet.
+    buffer: Vec<u8>,
+    parser: RequestParser,
+    // Serialized response bytes waiting for the socket to accept them.
+    output: Vec<u8>,
+    output_pos: usize,
+    // Response being sent, `body_done` once its last bytes are in `output`.
+    response: Option<Response>,
+    body_done: bool,
+    req_path: String,
+    timer: utils::Timer,
+    keep_alive: bool,
+    requests_served: usize,
+    last_active: Instant,
+    // Last time the client took response bytes, for tcp_write_timeout.
+    last_sent: Instant,
+    peer_closed: bool,
+    interest: u32,
+}
+
+enum Progress {
+    Wait,
+    Close,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, ip: String, snapshot: Arc<Snapshot>, tracked: Tracked) -> Connection {
+        let limits = ConnectionLimits::from_config(&snapshot.config);
+        Connection {
+            stream,
+            ip,
+            tracked,
+            snapshot,
+            limits,
+            buffer: vec![],
+            parser: limits.new_parser(),
+            output: vec![],
+            output_pos: 0,
+            response: None,
+            body_done: false,
+            req_path: String::new(),
+            timer: utils::Timer::new(),
+            keep_alive: true,
+            requests_served: 0,
+            last_active: Instant::now(),
+            last_sent: Instant::now(),
+            peer_closed: false,
+            interest: epoll::EPOLLIN | epoll::EPOLLRDHUP,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.response.is_none() && self.output_pos == self.output.len()
+    }
+
+    // Drain the socket into `buffer`.
+    fn read_available(&mut self) -> io::Result<()> {
+        let mut read_buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut read_buffer) {
+                Ok(0) => {
+                    self.peer_closed = true;
+                    return Ok(());
+                }
+                Ok(len) => {
+                    self.buffer.extend_from_slice(&read_buffer[..len]);
+                    self.last_active = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Write pending output, returns false if the socket is full.
+    fn flush_output(&mut self) -> io::Result<bool> {
+        while self.output_pos < self.output.len() {
+            match self.stream.write(&self.output[self.output_pos..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(len) => {
+                    self.output_pos += len;
+                    self.last_active = Instant::now();
+                    self.last_sent = self.last_active;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.output.clear();
+        self.output_pos = 0;
+        Ok(true)
+    }
+
+    fn start_response(&mut self, mut res: Response, keep_alive: bool) -> io::Result<()> {
+        res.set_read_buffer_size(STREAM_BUFFER_SIZE);
+        let header = res.build_header()?;
+        self.output.extend_from_slice(header.as_bytes());
+        self.response = Some(res);
+        self.keep_alive = keep_alive;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn start_error(&mut self, status: HttpStatus) -> io::Result<()> {
+        self.req_path = "<null>".to_string();
+        let res = Server::error_response(&self.snapshot.config, status);
+        self.start_response(res, false)
+    }
+
+    // Move the connection as far as possible without blocking.
+    fn progress(&mut self) -> io::Result<Progress> {
+        let snapshot = Arc::clone(&self.snapshot);
+        let mut chunks = 0;
+        loop {
+            if !self.flush_output()? {
+                return Ok(Progress::Wait);
+            }
+
+            if !self.body_done {
+                if let Some(ref mut res) = self.response {
+                    // Still writable: epoll reports it again on the next wait.
+                    if chunks == MAX_CHUNKS_PER_TURN {
+                        return Ok(Progress::Wait);
+                    }
+                    chunks += 1;
+                    let (content, remaining_bytes) = res.build_content();
+                    self.output.extend_from_slice(content);
+                    self.body_done = remaining_bytes == 0;
+                    continue;
+                }
+            }
+
+            if let Some(res) = self.response.take() {
+                self.body_done = false;
+                self.tracked
+                    .end_request(res.get_status_code(), self.timer.elapsed().unwrap());
+                log::access(format_args!(
+                    "{} - {} - {} ({} ms)",
+                    self.ip,
+                    res.get_status_code(),
+                    self.req_path,
+                    self.timer.elapsed().unwrap() as f32
+                ));
+
+                if !self.keep_alive || res.must_close() {
+                    return Ok(Progress::Close);
+                }
+                continue;
+            }
+
+            match self.parser.parse(&mut self.buffer) {
+                Ok(Some(req)) => {
+                    self.timer = utils::Timer::new();
+                    self.requests_served += 1;
+                    self.req_path = req.req_path.to_owned();
+                    self.tracked.start_request(&req.req_path);
+                    let (res, keep_alive) = Server::respond(
+                        &req,
+                        self.requests_served,
+                        &self.limits,
+                        &snapshot.mimetype,
+                        &snapshot.hosts,
+                        self.tracked.is_stopping(),
+                    );
+                    self.start_response(res, keep_alive)?;
+                }
+                Ok(None) if self.peer_closed => return Ok(Progress::Close),
+                Ok(None) if self.parser.take_continue() => {
+                    self.output.extend_from_slice(request::CONTINUE_RESPONSE);
+                }
+                Ok(None) => {
+                    self.tracked
+                        .set_pending(self.parser.is_receiving(&self.buffer));
+                    return Ok(Progress::Wait);
+                }
+                Err(status) => {
+                    self.timer = utils::Timer::new();
+                    self.start_error(status)?;
+                }
+            }
+        }
+    }
+
+    fn wanted_interest(&self) -> u32 {
+        let sending = self.output_pos < self.output.len() || self.response.is_some();
+        if sending && self.peer_closed {
+            // The socket stays readable at end of input, watching it would
+            // wake the loop on every wait until the response is out.
+            epoll::EPOLLOUT
+        } else if sending {
+            epoll::EPOLLIN | epoll::EPOLLRDHUP | epoll::EPOLLOUT
+        } else {
+            epoll::EPOLLIN | epoll::EPOLLRDHUP
+        }
+    }
+
+    fn is_timed_out(&self, now: Instant) -> bool {
+        // A client that stopped reading its response.
+        if !self.is_idle() {
+            return match self.limits.tcp_write_timeout {
+                Some(timeout) => now.duration_since(self.last_sent) >= timeout,
+                None => false,
+            };
+        }
+
+        let timeout = if self.requests_served == 0 {
+            self.limits.tcp_read_timeout
+        } else {
+            self.limits.keep_alive_timeout
+        };
+
+        match timeout {
+            Some(timeout) => now.duration_since(self.last_active) >= timeout,
+            None => false,
+        }
+    }
+}
+
+impl Server {
+    pub fn start_with_epoll(self) {
+        if let Err(e) = self.run_event_loop() {
+            log::error(format_args!("The event loop stopped because: {}", e));
+        }
+    }
+
+    fn run_event_loop(&self) -> io::Result<()> {
+        let epoll = Epoll::new()?;
+        let listener_fd = self.server.as_raw_fd();
+
+        self.server.set_nonblocking(true)?;
+        epoll.add(listener_fd, epoll::EPOLLIN)?;
+
+        let mut connections: HashMap<RawFd, Connection> = HashMap::new();
+        let mut events = vec![epoll::Event { events: 0, data: 0 }; MAX_EVENTS];
+        let mut accepting = true;
+        // Set while the listener is out of epoll for lack of file descriptors.
+        let mut paused_until: Option<Instant> = None;
+
+        loop {
+            if accepting && self.monitor.is_stopping() {
+                epoll.delete(listener_fd);
+                accepting = false;
+            }
+            if !accepting && connections.is_empty() {
+                return Ok(());
+            }
+            let mut timeout = TICK_MILLIS;
+            if let Some(until) = paused_until {
+                let now = Instant::now();
+                if now >= until {
+                    paused_until = None;
+                    if accepting {
+                        epoll.add(listener_fd, epoll::EPOLLIN)?;
+                    }
+                } else {
+                    timeout = timeout.min((until - now).as_millis() as i32 + 1);
+                }
+            }
+            let ready = epoll.wait(&mut events, timeout)?;
+
+            for event in events.iter().take(ready) {
+                let fd = event.data as RawFd;
+
+                if fd == listener_fd {
+                    if accepting
+                        && !self.monitor.is_stopping()
+                        && !self.accept_all(&epoll, &mut connections)
+                    {
+                        epoll.delete(listener_fd);
+                        paused_until = Some(Instant::now() + ACCEPT_BACKOFF);
+                    }
+                    continue;
+                }
+
+                let close = match connections.get_mut(&fd) {
+                    Some(conn) => {
+                        let readable = event.events
+                            & (epoll::EPOLLIN | epoll::EPOLLRDHUP | epoll::EPOLLHUP | epoll::EPOLLERR);
+                        let result = if readable != 0 {
+                            conn.read_available()
+                        } else {
+                            Ok(())
+                        };
+
+                        match result.and_then(|_| conn.progress()) {
+                            Ok(Progress::Wait) => {
+                                let interest = conn.wanted_interest();
+                                if interest != conn.interest {
+                                    conn.interest = interest;
+                                    epoll.modify(fd, interest);
+                                }
+                                false
+                            }
+                            Ok(Progress::Close) | Err(_) => true,
+                        }
+                    }
+                    None => false,
+                };
+
+                if close {
+                    Server::close_connection(&epoll, &mut connections, fd);
+                }
+            }
+
+            let now = Instant::now();
+            let timed_out: Vec<RawFd> = connections
+                .iter()
+                .filter(|&(_, conn)| conn.is_timed_out(now))
+                .map(|(fd, _)| *fd)
+                .collect();
+            for fd in timed_out {
+                Server::close_connection(&epoll, &mut connections, fd);
+            }
+        }
+    }
+
+    // Accept every waiting connection. False when out of file descriptors:
+    // the pending connection stays in the backlog and the listener is
+    // readable again at once, it has to be left alone for a while.
+    fn accept_all(&self, epoll: &Epoll, connections: &mut HashMap<RawFd, Connection>) -> bool {
+        loop {
+            match self.server.accept() {
+                Ok((socket, sock_addr)) => {
+                    if self.is_refused(&socket, sock_addr) || socket.set_nonblocking(true).is_err()
+                    {
+                        continue;
+                    }
+                    let fd = socket.as_raw_fd();
+                    let tracked = Monitor::track(&self.monitor, &socket, sock_addr);
+                    let conn = Connection::new(
+                        socket,
+                        sock_addr.ip().to_string(),
+                        self.live.get(),
+                        tracked,
+                    );
+                    if epoll.add(fd, conn.interest).is_ok() {
+                        connections.insert(fd, conn);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(ref e)
+                    if e.raw_os_error() == Some(EMFILE) || e.raw_os_error() == Some(ENFILE) =>
+                {
+                    log::error(format_args!(
+                        "Connect error by: {}, not accepting for {} ms.",
+                        e,
+                        ACCEPT_BACKOFF.as_millis()
+                    ));
+                    return false;
+                }
+                Err(e) => {
+                    log::error(format_args!("Connect error by: {}", e));
+                    return true;
+                }
+            }
+        }
+    }
+
+    fn close_connection(epoll: &Epoll, connections: &mut HashMap<RawFd, Connection>, fd: RawFd) {
+        if let Some(conn) = connections.remove(&fd) {
+            epoll.delete(fd);
+            conn.stream.shutdown(net::Shutdown::Both);
+        }
+    }
+}
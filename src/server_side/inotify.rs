@@ -0,0 +1,124 @@
+/*
+ *	Thin wrapper around Linux inotify(7), to notice config files changing.
+ *	Directories are watched rather than files: editors often save by
+ *	writing a new file and renaming it over the old one.
+ */
+use std::ffi::CString;
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+const IN_MODIFY: u32 = 0x002;
+const IN_CLOSE_WRITE: u32 = 0x008;
+const IN_MOVED_TO: u32 = 0x080;
+const IN_CREATE: u32 = 0x100;
+const IN_DELETE: u32 = 0x200;
+const IN_NONBLOCK: c_int = 0o4000;
+const IN_CLOEXEC: c_int = 0o2000000;
+
+// wd, mask, cookie and len, then `len` bytes of name.
+const EVENT_HEADER_SIZE: usize = 16;
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize;
+    fn close(fd: c_int) -> c_int;
+}
+
+#[derive(Debug)]
+pub struct Inotify {
+    fd: RawFd,
+    // Watch descriptor of a directory and the names in it that matter.
+    watches: Vec<(c_int, Vec<Vec<u8>>)>,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Inotify> {
+        let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            fd,
+            watches: vec![],
+        })
+    }
+
+    // Report changes to `file`, by watching its directory.
+    pub fn watch_file(&mut self, file: &Path) -> io::Result<()> {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = file
+            .file_name()
+            .map_or(vec![], |name| name.as_bytes().to_vec());
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mask = IN_MODIFY | IN_CLOSE_WRITE | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+        let wd = unsafe { inotify_add_watch(self.fd, dir.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The same directory gives the same descriptor back.
+        match self.watches.iter_mut().find(|watch| watch.0 == wd) {
+            Some(watch) if watch.1.contains(&name) => {}
+            Some(watch) => watch.1.push(name),
+            None => self.watches.push((wd, vec![name])),
+        }
+        Ok(())
+    }
+
+    // Whether a watched file changed since the last call, without blocking.
+    pub fn has_changed(&mut self) -> bool {
+        let mut changed = false;
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let len = unsafe { read(self.fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) };
+            if len <= 0 {
+                return changed;
+            }
+
+            let mut pos = 0;
+            while pos + EVENT_HEADER_SIZE <= len as usize {
+                let wd = c_int::from_ne_bytes([
+                    buffer[pos],
+                    buffer[pos + 1],
+                    buffer[pos + 2],
+                    buffer[pos + 3],
+                ]);
+                let name_len = u32::from_ne_bytes([
+                    buffer[pos + 12],
+                    buffer[pos + 13],
+                    buffer[pos + 14],
+                    buffer[pos + 15],
+                ]) as usize;
+                let name_start = pos + EVENT_HEADER_SIZE;
+                let name_end = (name_start + name_len).min(len as usize);
+                // The name is padded with NULs.
+                let name = buffer[name_start..name_end].split(|&b| b == 0).next();
+
+                if let (Some(watch), Some(name)) =
+                    (self.watches.iter().find(|watch| watch.0 == wd), name)
+                {
+                    changed |= watch.1.iter().any(|watched| watched[..] == *name);
+                }
+                pos = name_end;
+            }
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ *	Settings the server runs with: config, MIME types and sites, loaded
+ *	together and swapped whole on reload. A connection keeps the snapshot
+ *	it started with until it closes, a config with errors is refused and
+ *	the running one kept.
+ */
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use server_side::config::{Configuration, Override};
+#[cfg(target_os = "linux")]
+use server_side::inotify::Inotify;
+use server_side::log;
+use server_side::mimetype::Mimetype;
+use server_side::signal;
+use server_side::utils;
+use server_side::vhost::VirtualHosts;
+
+// How often the watcher looks for SIGHUP and changed files.
+const WATCH_TICK: Duration = Duration::from_millis(250);
+
+// Where the settings are read from, again on each reload.
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    // None: built-in settings and MIME types, no config files.
+    pub path: Option<PathBuf>,
+    pub overrides: Vec<Override>,
+}
+
+#[derive(Debug)]
+pub struct Snapshot {
+    pub config: Configuration,
+    pub mimetype: Mimetype,
+    pub hosts: VirtualHosts,
+}
+
+impl Snapshot {
+    // Everything read from config files. Sites found in `previous` keep
+    // their WebDAV locks.
+    pub fn load(source: &ConfigSource, previous: Option<&Snapshot>) -> Result<Snapshot, Error> {
+        let (config, mimetype) = match source.path {
+            Some(ref path) => (
+                Configuration::from_file(path, &source.overrides)?,
+                Mimetype::new()?,
+            ),
+            None => (
+                Configuration::builtin(&source.overrides)?,
+                Mimetype::builtin(),
+            ),
+        };
+        let hosts = VirtualHosts::new(&config, previous.map(|snapshot| &snapshot.hosts))?;
+        Ok(Snapshot {
+            config,
+            mimetype,
+            hosts,
+        })
+    }
+
+    // Files a reload of `source` reads, for the watcher.
+    fn get_files(&self, source: &ConfigSource) -> Vec<PathBuf> {
+        if source.path.is_none() {
+            return vec![];
+        }
+        let root_path = utils::get_root_path();
+        let mut files = self.config.files.clone();
+        files.push(utils::to_root_path("/config/mimetype.mt", &root_path));
+        files.push(utils::to_root_path(
+            "/config/custom_mimetype.mt",
+            &root_path,
+        ));
+        let sites = Some(&self.config.site)
+            .into_iter()
+            .chain(self.config.hosts.iter().map(|host| &host.site));
+        for site in sites {
+            files.push(utils::to_root_path(&site.alias_file, &root_path));
+        }
+        files
+    }
+}
+
+#[derive(Debug)]
+pub struct LiveConfig {
+    source: ConfigSource,
+    current: RwLock<Arc<Snapshot>>,
+    // One reload at a time.
+    reloading: Mutex<()>,
+}
+
+impl LiveConfig {
+    pub fn new(source: ConfigSource, snapshot: Snapshot) -> LiveConfig {
+        LiveConfig {
+            source,
+            current: RwLock::new(Arc::new(snapshot)),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Snapshot> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    // Read the config files again and serve new connections with them.
+    // Listening settings need a restart, they are only reported.
+    pub fn reload(&self) -> Result<(), Error> {
+        let _reloading = self.reloading.lock().unwrap_or_else(|e| e.into_inner());
+        let old = self.get();
+        let new = Snapshot::load(&self.source, Some(&old))?;
+        log::configure(&new.config.logging)?;
+
+        let (old_server, new_server) = (&old.config.server, &new.config.server);
+        let restart_keys = [
+            ("server_address", old_server.address != new_server.address),
+            ("server_port", old_server.port != new_server.port),
+            ("io_engine", old_server.io_engine != new_server.io_engine),
+            (
+                "max_alive_thread",
+                old_server.max_alive_thread != new_server.max_alive_thread,
+            ),
+            (
+                "pool_queue_size",
+                old_server.pool_queue_size != new_server.pool_queue_size,
+            ),
+        ];
+        for &(key, changed) in restart_keys.iter() {
+            if changed {
+                log::warn(format_args!(
+                    "`{}` changed, it takes effect on restart.",
+                    key
+                ));
+            }
+        }
+
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(new);
+        log::info(format_args!(
+            "Configuration reloaded from `{}`.",
+            old.config.path.display()
+        ));
+        Ok(())
+    }
+
+    // Reload on SIGHUP and, with `watch_config`, when a config file changes.
+    pub fn start_watcher(live: Arc<LiveConfig>) {
+        if let Err(e) = signal::listen(signal::SIGHUP) {
+            log::warn(format_args!("Can't reload on SIGHUP because: {}", e));
+        }
+
+        thread::spawn(move || {
+            let mut watcher = FileWatcher::new();
+            loop {
+                thread::sleep(WATCH_TICK);
+                let changed = watcher.has_changed(&live.get(), &live.source);
+                if !signal::take(signal::SIGHUP) && !changed {
+                    continue;
+                }
+
+                if let Err(e) = live.reload() {
+                    log::error(format_args!(
+                        "Reload failed, still running the previous configuration:\n{}",
+                        e
+                    ));
+                }
+            }
+        });
+    }
+}
+
+// inotify on the files of the running snapshot, while `watch_config` is on.
+struct FileWatcher {
+    #[cfg(target_os = "linux")]
+    inotify: Option<Inotify>,
+    // Snapshot the watches were set for.
+    #[cfg(target_os = "linux")]
+    watched: Option<Arc<Snapshot>>,
+    // Changes seen on the last tick: a save often comes as several events,
+    // the reload waits for a quiet tick.
+    pending: bool,
+}
+
+impl FileWatcher {
+    fn new() -> FileWatcher {
+        FileWatcher {
+            #[cfg(target_os = "linux")]
+            inotify: None,
+            #[cfg(target_os = "linux")]
+            watched: None,
+            pending: false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn has_changed(&mut self, snapshot: &Arc<Snapshot>, source: &ConfigSource) -> bool {
+        if !snapshot.config.server.watch_config {
+            self.inotify = None;
+            self.watched = None;
+            self.pending = false;
+            return false;
+        }
+
+        // A reload may name new files, watching one twice is harmless.
+        if !self
+            .watched
+            .as_ref()
+            .is_some_and(|watched| Arc::ptr_eq(watched, snapshot))
+        {
+            self.watched = Some(Arc::clone(snapshot));
+            if self.inotify.is_none() {
+                match Inotify::new() {
+                    Ok(inotify) => self.inotify = Some(inotify),
+                    Err(e) => log::warn(format_args!("Can't watch config files because: {}", e)),
+                }
+            }
+            if let Some(ref mut inotify) = self.inotify {
+                for file in snapshot.get_files(source) {
+                    if let Err(e) = inotify.watch_file(&file) {
+                        log::debug(format_args!(
+                            "Can't watch `{}` because: {}",
+                            file.display(),
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+
+        let inotify = match self.inotify {
+            Some(ref mut inotify) => inotify,
+            None => return false,
+        };
+        if inotify.has_changed() {
+            self.pending = true;
+            false
+        } else {
+            let changed = self.pending;
+            self.pending = false;
+            changed
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn has_changed(&mut self, snapshot: &Arc<Snapshot>, source: &ConfigSource) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn a_bad_mimetype_file_keeps_the_running_snapshot() {
+        let root = env::temp_dir().join(format!("aden-live-{}", process::id()));
+        let config_dir = root.join("config");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join("config.conf"),
+            "home_dir = /\nhome_dir_error = /\nalias_file =\n",
+        )
+        .unwrap();
+        fs::write(config_dir.join("custom_mimetype.mt"), "# none\n").unwrap();
+        fs::write(
+            config_dir.join("mimetype.mt"),
+            ".txt\ttext/plain\n.md\ttext/markdown\n",
+        )
+        .unwrap();
+        // The only test that reads config files from the server root.
+        utils::set_root_path(&root);
+
+        let source = ConfigSource {
+            path: Some(config_dir.join("config.conf")),
+            overrides: vec![],
+        };
+        let live = LiveConfig::new(source.clone(), Snapshot::load(&source, None).unwrap());
+        assert_eq!(
+            live.get().mimetype.get_mimetype_default("a.md"),
+            "text/markdown"
+        );
+
+        fs::write(
+            config_dir.join("mimetype.mt"),
+            ".txt\ttext/plain\n.md text/x-markdown\n",
+        )
+        .unwrap();
+        let e = live.reload().unwrap_err().to_string();
+        assert!(e.contains("mimetype.mt:2: `.md text/x-markdown`"), "{}", e);
+        assert_eq!(
+            live.get().mimetype.get_mimetype_default("a.md"),
+            "text/markdown"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+/*
+ *	Server messages and the access log, filtered by `[logging] level` and
+ *	written to stdout or to `[logging] file`.
+ */
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use server_side::config::LoggingConf;
+use server_side::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+const LEVELS: [Level; 4] = [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        LEVELS
+            .iter()
+            .find(|level| level.get_name() == name.to_lowercase())
+            .cloned()
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    fn get_prefix(self) -> &'static str {
+        match self {
+            Level::Error => "E: ",
+            Level::Warn => "W: ",
+            Level::Info => "",
+            Level::Debug => "D: ",
+        }
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+static ACCESS_LOG: AtomicBool = AtomicBool::new(true);
+// None: stdout.
+static FILE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+// Apply `[logging]`: the file is opened for appending, relative to the
+// server root like every path of config.
+pub fn configure(logging: &LoggingConf) -> io::Result<()> {
+    let file = if logging.file.is_empty() {
+        None
+    } else {
+        let path = utils::to_root_path(&logging.file, &utils::get_root_path());
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Some(LineWriter::new(file))
+    };
+
+    set_level(logging.level);
+    ACCESS_LOG.store(logging.access_log, Ordering::Relaxed);
+    *FILE.lock().unwrap_or_else(|e| e.into_inner()) = file;
+    Ok(())
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn get_level() -> Level {
+    LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+pub fn is_enabled(level: Level) -> bool {
+    level <= get_level()
+}
+
+pub fn error(message: fmt::Arguments) {
+    write(Level::Error, message);
+}
+
+pub fn warn(message: fmt::Arguments) {
+    write(Level::Warn, message);
+}
+
+pub fn info(message: fmt::Arguments) {
+    write(Level::Info, message);
+}
+
+pub fn debug(message: fmt::Arguments) {
+    write(Level::Debug, message);
+}
+
+// One served request, unless `access_log = false`.
+pub fn access(message: fmt::Arguments) {
+    if ACCESS_LOG.load(Ordering::Relaxed) {
+        write(Level::Info, message);
+    }
+}
+
+pub fn flush() {
+    if let Some(ref mut file) = *FILE.lock().unwrap_or_else(|e| e.into_inner()) {
+        let _ = file.flush();
+    }
+    let _ = io::stdout().flush();
+}
+
+fn write(level: Level, message: fmt::Arguments) {
+    if !is_enabled(level) {
+        return;
+    }
+
+    match *FILE.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(ref mut file) => {
+            let _ = writeln!(file, "{}{}", level.get_prefix(), message);
+        }
+        None => println!("{}{}", level.get_prefix(), message),
+    }
+}
@@ -1,10 +1,10 @@
 
-use std::path;
 use std::io;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::fs::File;
+use std::path::Path;
 
 use server_side::utils;
 
@@ -26,60 +26,70 @@ impl Mimetype {
 		let root_path = utils::get_root_path();
 
 		// Process custom mimetype first!
-		// For safety, Use std::path::PathBuf to create path to compatible to cross platform.
-		let custom_mimetype_pathbuf = path::PathBuf::from(utils::to_root_path("/config/custom_mimetype.mt", &root_path));
-		let custom_mimetype_path: &str = custom_mimetype_pathbuf.to_str().unwrap();
-		let custom_mimetype_file_handle = match File::open(custom_mimetype_path)  {
-			Ok(ok) => ok,
-			Err(e) => return Err(Error::new(ErrorKind::Other, format!("can't find `{:?}`", custom_mimetype_path)))
-		};
-		let custom_mimetype_bufreader = BufReader::new(&custom_mimetype_file_handle);
+		let custom_mimetype_path = utils::to_root_path("/config/custom_mimetype.mt", &root_path);
+		Mimetype::read_file(&custom_mimetype_path, &mut mimetypes)?;
 
-		for line_wrapped in custom_mimetype_bufreader.lines() {
-			let line_raw = line_wrapped.unwrap();
-			// line_raw.trim();
-			// line_raw.trim_matches('\r');
-			// line_raw.trim_matches('\n');
-			// line_raw.trim_matches('\t');
+		// Then, server mimetype
+		let mimetype_path = utils::to_root_path("/config/mimetype.mt", &root_path);
+		Mimetype::read_file(&mimetype_path, &mut mimetypes)?;
 
-			let line = line_raw.trim();
-			if line.len() > 0 && line.as_bytes()[0] != '#' as u8 {
-				let line_splitted = line.split('\t').collect::<Vec<&str>>();
-				let key = line_splitted[0];
-				let val = line_splitted[1];
-				mimetypes.push(MimetypeData::new(key.to_string(), val.to_string()));
-			}
-		}
+		Ok(Mimetype{ mimetype_vec: mimetypes, default_mimetype: "text/plain".to_string() })
+	}
 
-		// Then, server mimetype
-		let mimetype_pathbuf = path::PathBuf::from(utils::to_root_path("/config/mimetype.mt", &root_path));
-		let mimetype_path: &str = mimetype_pathbuf.to_str().unwrap();
-		let mimetype_file_handle = match File::open(mimetype_path) {
+	// The server's config/mimetype.mt, built in for running without config files.
+	pub fn builtin() -> Mimetype {
+		let mut mimetypes: Vec<MimetypeData> = vec![];
+		Mimetype::read_lines("(built-in)", include_str!("../../config/mimetype.mt").lines(), &mut mimetypes)
+			.expect("the built-in mimetype.mt is valid");
+		Mimetype{ mimetype_vec: mimetypes, default_mimetype: "text/plain".to_string() }
+	}
+
+	fn read_file(path: &Path, mimetypes: &mut Vec<MimetypeData>) -> Result<(), io::Error> {
+		let file_handle = match File::open(path) {
 			Ok(ok) => ok,
-			Err(e) => return Err(Error::new(ErrorKind::Other, format!("can't find `{:?}`", mimetype_path)))
+			Err(e) => return Err(Error::other(format!("can't find `{}`", path.display())))
 		};
-		let mimetype_bufreader = BufReader::new(&mimetype_file_handle);
 
-		for line_wrapped in mimetype_bufreader.lines() {
-			let line_raw = line_wrapped.unwrap();
-			// line_raw.trim();
-			// line_raw.trim_matches('\r');
-			// line_raw.trim_matches('\n');
-			// line_raw.trim_matches('\t');
+		let mut lines = vec![];
+		for line in BufReader::new(&file_handle).lines() {
+			lines.push(line?);
+		}
+		let file = path.display().to_string();
+		Mimetype::read_lines(&file, lines.iter().map(|line| line.as_str()), mimetypes)
+	}
 
+	// `<extension>\t<mimetype>` a line. All the errors are in the message,
+	// one a line as `file:line: error`.
+	fn read_lines<'a, I: Iterator<Item = &'a str>>(file: &str, lines: I,
+		mimetypes: &mut Vec<MimetypeData>) -> Result<(), io::Error> {
+		let mut errors: Vec<String> = vec![];
+		for (i, line_raw) in lines.enumerate() {
 			let line = line_raw.trim();
-			if line.len() > 0 && line.as_bytes()[0] != '#' as u8 {
-				let line_splitted = line.split('\t').collect::<Vec<&str>>();
-				let key = line_splitted[0];
-				let val = line_splitted[1];
-				mimetypes.push(MimetypeData::new(key.to_string(), val.to_string()));
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let fields: Vec<&str> = line.split('\t').map(|field| field.trim())
+				.filter(|field| !field.is_empty())
+				.collect();
+			match fields[..] {
+				[extension, mimetype] if extension.starts_with('.') && mimetype.contains('/') => {
+					mimetypes.push(MimetypeData::new(extension.to_string(), mimetype.to_string()));
+				},
+				_ => errors.push(format!("{}:{}: `{}` (expected `.<extension><tab><type>/<subtype>`)",
+					file, i + 1, line))
 			}
 		}
 
-		Ok(Mimetype{ mimetype_vec: mimetypes, default_mimetype: "text/plain".to_string() })
+		if errors.is_empty() {
+			Ok(())
+		}
+		else {
+			Err(Error::other(errors.join("\n")))
+		}
 	}
 
-	pub fn get_mimetype(&self, filename: &String) -> Result<String, io::Error> {
+	pub fn get_mimetype(&self, filename: &str) -> Result<String, io::Error> {
 		let ext: String;
 		if filename.contains(".") {
 			let filename_ext = filename.split(".").last().unwrap().to_string();
@@ -92,17 +102,17 @@ impl Mimetype {
 			}
 		}
 
-		Err(io::Error::new(io::ErrorKind::Other, "mimetype not found."))
+		Err(io::Error::other("mimetype not found."))
 	}
 
-	pub fn get_mimetype_or(&self, filename: &String, default: &str) -> String {
+	pub fn get_mimetype_or(&self, filename: &str, default: &str) -> String {
 		match self.get_mimetype(filename) {
 			Ok(mt) => mt,
 			Err(_) => default.to_owned()
 		}
 	}
 
-	pub fn get_mimetype_default(&self, filename: &String) -> String {
+	pub fn get_mimetype_default(&self, filename: &str) -> String {
 		match self.get_mimetype(filename) {
 			Ok(mt) => mt,
 
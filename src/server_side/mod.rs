@@ -1,62 +1,133 @@
-/**
- *
- */
+pub mod autoindex;
+pub mod compress;
+pub mod conditional;
 pub mod config;
+pub mod deflate;
+#[cfg(target_os = "linux")]
+pub mod epoll;
+#[cfg(target_os = "linux")]
+mod event_loop;
+#[cfg(target_os = "linux")]
+pub mod inotify;
+pub mod live;
+pub mod log;
 pub mod mimetype;
+pub mod monitor;
+pub mod pool;
+pub mod range;
+pub mod regex;
+pub mod resolver;
 pub mod response;
+pub mod signal;
 pub mod status;
+pub mod stopper;
+pub mod upload;
 pub mod utils;
+pub mod vhost;
+pub mod webdav;
+pub mod xml;
 
-use std::fs::metadata;
+use std::fs::{metadata, File};
 use std::io;
 use std::io::prelude::*;
 use std::io::{BufWriter, Write};
 use std::io::{Error, ErrorKind};
 use std::net;
-use std::net::{IpAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::net::{TcpListener, TcpStream};
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
-use client_side::request::Request;
-use server_side::{config::Configuration, mimetype::Mimetype, response::Response};
+use client_side::alias::Rewritten;
+use client_side::request;
+use client_side::request::{Request, RequestParser};
+use server_side::status::HttpStatus;
+use server_side::{
+    autoindex::DirListing,
+    compress::Compression,
+    conditional::Validators,
+    config::{Configuration, SiteConf},
+    live::{LiveConfig, Snapshot},
+    mimetype::Mimetype,
+    monitor::{Monitor, Tracked},
+    pool::{OverflowPolicy, WorkerPool},
+    range::{MultipartRanges, RangeSet},
+    resolver::PathResolver,
+    response::Response,
+    stopper::Stopper,
+    upload::{Uploads, UPLOAD_METHODS},
+    vhost::{Site, VirtualHosts},
+    webdav::{DavContext, DavState, DAV_METHODS},
+};
+
+// Methods the server knows of, others get 501.
+const KNOWN_METHODS: [&str; 16] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH", "MKCOL",
+    "PROPFIND", "PROPPATCH", "COPY", "MOVE", "LOCK", "UNLOCK",
+];
+// Methods files and directories of home_dir answer to, others get 405.
+const RESOURCE_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
 
 // use string to be easy to combine and no need to convert from int
 #[derive(Debug)]
 pub struct Server {
     pub address: String,
-    pub port: String,
+    pub port: u16,
     server: TcpListener,
-    mimetype: Mimetype,
+    live: Arc<LiveConfig>,
+    monitor: Arc<Monitor>,
 }
 
-impl Server {
-    pub fn new(server_address: &str, server_port: &str) -> Result<Server, Error> {
-        let ip_addrs: Vec<IpAddr> = match (server_address, 0)
-            .to_socket_addrs()
-            .map(|iter| iter.map(|socket_address| socket_address.ip()).collect())
-        {
-            Ok(ip_vec) => ip_vec,
-            Err(e) => return Err(e),
-        };
-        let ipv4 = ip_addrs[ip_addrs.len() - 1].to_string();
+// Timeouts and keep-alive limits of a client connection, from config.
+#[derive(Debug, Clone, Copy)]
+struct ConnectionLimits {
+    tcp_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    tcp_write_timeout: Option<Duration>,
+    keep_alive_max_requests: usize,
+    max_header_size: usize,
+    max_header_count: usize,
+    max_body_size: usize,
+}
 
-        if let Err(e) = server_port.parse::<i32>() {
-            return Err(Error::new(ErrorKind::Other, e.to_string()));
+impl ConnectionLimits {
+    fn from_config(config: &Configuration) -> ConnectionLimits {
+        ConnectionLimits {
+            tcp_read_timeout: config.server.tcp_read_timeout,
+            keep_alive_timeout: config.server.keep_alive_timeout,
+            tcp_write_timeout: config.server.tcp_write_timeout,
+            keep_alive_max_requests: config.server.keep_alive_max_requests,
+            max_header_size: config.server.max_header_size,
+            max_header_count: config.server.max_header_count,
+            max_body_size: config.server.max_body_size,
         }
+    }
 
-        match TcpListener::bind(ipv4.to_owned() + ":" + server_port) {
+    fn new_parser(&self) -> RequestParser {
+        let mut parser = RequestParser::with_limits(self.max_header_size, self.max_header_count);
+        parser.set_max_body_size(self.max_body_size);
+        parser
+    }
+}
+
+impl Server {
+    pub fn new(live: &Arc<LiveConfig>) -> Result<Server, Error> {
+        let snapshot = live.get();
+        let config = &snapshot.config;
+        let server_address = config.server.address.as_str();
+        let ip_addrs: Vec<IpAddr> = (server_address, 0)
+            .to_socket_addrs()
+            .map(|iter| iter.map(|socket_address| socket_address.ip()).collect())?;
+        let ipv4 = ip_addrs[ip_addrs.len() - 1];
+
+        match TcpListener::bind((ipv4, config.server.port)) {
             Ok(tcplistener) => Ok(Server {
                 address: server_address.to_string(),
-                port: server_port.to_string(),
+                port: config.server.port,
                 server: tcplistener,
-                mimetype: match Mimetype::new() {
-                    Ok(mt) => mt,
-                    Err(e) => {
-                        // println!("server_side::Server::new -> init mimetype failed: {:?}", e.to_string());
-                        return Err(e);
-                    }
-                },
+                live: Arc::clone(live),
+                monitor: Arc::new(Monitor::new()),
             }),
             Err(e) => Err(e),
         }
@@ -66,286 +137,693 @@ impl Server {
     // 	self.server.set_nonblocking(mode)
     // }
 
-    pub fn start_with_thread(self, config: &Configuration) {
-        let max_alive_thread: u32 = match config.get_value_or("max_alive_thread", "8").parse() {
-            Ok(ok) => ok,
-            Err(_) => 10,
+    pub fn get_monitor(&self) -> Arc<Monitor> {
+        Arc::clone(&self.monitor)
+    }
+
+    // To stop the server from another thread once it runs.
+    pub fn get_stopper(&self) -> io::Result<Stopper> {
+        Ok(Stopper::new(
+            self.server.try_clone()?,
+            Arc::clone(&self.monitor),
+        ))
+    }
+
+    // Run with the I/O engine chosen by `io_engine` in config.
+    pub fn start(self) {
+        let io_engine = self.live.get().config.server.io_engine.clone();
+        match io_engine.as_str() {
+            #[cfg(target_os = "linux")]
+            "epoll" => self.start_with_epoll(),
+            "thread" => self.start_with_thread(),
+            other => {
+                log::warn(format_args!(
+                    "io_engine `{}` is not available, using `thread`.",
+                    other
+                ));
+                self.start_with_thread()
+            }
+        }
+    }
+
+    // Each connection is served with the settings current when a worker
+    // picks it up.
+    pub fn start_with_thread(self) {
+        let (max_alive_thread, pool_queue_size) = {
+            let snapshot = self.live.get();
+            (
+                snapshot.config.server.max_alive_thread,
+                snapshot.config.server.pool_queue_size,
+            )
         };
 
+        let live = Arc::clone(&self.live);
+        let monitor = Arc::clone(&self.monitor);
+        let pool = WorkerPool::new(
+            max_alive_thread,
+            pool_queue_size,
+            move |mut socket, sock_addr, tracked| {
+                Server::handle_client(&mut socket, sock_addr, tracked, &live.get(), &monitor);
+            },
+        );
+
         loop {
             match self.server.accept() {
+                Ok(_) | Err(_) if self.monitor.is_stopping() => break,
                 Ok((socket, sock_addr)) => {
-                    let mimetype = self.mimetype.to_owned();
-                    let cloned_config = config.to_owned();
-                    thread::spawn(move || {
-                        let mut sock = match socket.try_clone() {
-                            Ok(sock) => sock,
-                            Err(e) => {
-                                println!("Cloning error by: {}", e.to_string());
-                                return;
-                            }
-                        };
-                        // Server::handle_client(&mut sock);
-                        Server::handle_client(
-                            &mut sock,
-                            sock_addr.ip().to_string(),
-                            mimetype,
-                            cloned_config,
-                        );
-                    });
+                    if self.is_refused(&socket, sock_addr) {
+                        continue;
+                    }
+                    let tracked = Monitor::track(&self.monitor, &socket, sock_addr);
+                    let snapshot = self.live.get();
+                    let pool_overflow = snapshot.config.server.pool_overflow;
+                    if let Err(mut socket) = pool.dispatch(socket, sock_addr, tracked, pool_overflow) {
+                        Server::send_busy(&mut socket, &snapshot.config);
+                        self.monitor.count_busy();
+                        log::access(format_args!("{} - 503 - <null> (Server busy)", sock_addr.ip()));
+                    }
                 }
                 Err(e) => {
-                    println!("Connect error by: {}", e.to_string());
+                    log::error(format_args!("Connect error by: {}", e));
                 }
             }
         }
     }
 
+    // Close a connection from a banned address.
+    fn is_refused(&self, socket: &TcpStream, sock_addr: SocketAddr) -> bool {
+        if !self.monitor.is_banned(sock_addr.ip()) {
+            return false;
+        }
+        socket.shutdown(net::Shutdown::Both);
+        self.monitor.count_refused();
+        log::debug(format_args!(
+            "{} - refused, the address is banned",
+            sock_addr.ip()
+        ));
+        true
+    }
+
     fn handle_client(
         client: &mut TcpStream,
-        ip: String,
-        mimetype: Mimetype,
-        config: Configuration,
+        peer: SocketAddr,
+        tracked: Tracked,
+        snapshot: &Snapshot,
+        monitor: &Arc<Monitor>,
     ) {
-        // TODO: use client_side module, handle request, send response
-        // set read time-out for client
-        let tcp_read_timeout = Duration::from_millis(1000);
-        let home_dir = config.get_value_or("home_dir", "/www");
-        let home_dir_err = config.get_value_or("home_dir_err", "/error");
-        let default_index_file = config.get_value_or("default_index_file", "index.html");
-        let alternative_index_basename = config.get_value_or("alternative_index_basename", "index");
-        let forbidden_dirs_raw = config.get_value_or("forbidden_dir", "");
-        let forbidden_dirs = forbidden_dirs_raw.split(";").collect::<Vec<&str>>();
-        let timer = utils::Timer::new();
-        let tcp_read_timeout: Option<Duration>;
-        let tcpstream_nonblocking = match config
-            .get_value_or("tcpstream_nonblocking", "false")
-            .as_str()
-        {
-            "true" => true,
-            _ => false,
-        };
-
-        // client.set_nonblocking(tcpstream_nonblocking);
+        let ip = peer.ip().to_string();
+        let config = &snapshot.config;
+        let limits = ConnectionLimits::from_config(config);
+
+        // Bytes received but not consumed yet: pipelined requests stay here
+        // until the previous response has been sent.
+        let mut buffer: Vec<u8> = vec![];
+        let mut parser = limits.new_parser();
+        let mut read_buffer = [0; 4096];
+        let mut requests_served: usize = 0;
+        client.set_write_timeout(limits.tcp_write_timeout);
 
-        match config.get_value_or("tcp_read_timeout", "0").parse::<u64>() {
-            Ok(millis) => {
-                if millis != 0 {
-                    tcp_read_timeout = Some(Duration::from_millis(millis));
-                } else {
-                    tcp_read_timeout = None;
-                }
-            }
-            Err(_) => tcp_read_timeout = None,
-        };
-
-        client.set_read_timeout(tcp_read_timeout);
-        // println!("Receiving data ...");
-
-        let mut req_raw_header: Vec<u8> = vec![];
-        let mut buff_u8_1 = [0; 1];
-        let mut buff_u8 = [0; 1000];
-        let break_loop = false;
-
-        match client.read(&mut buff_u8_1[..]) {
-            Ok(_) => {
-                client.set_nonblocking(tcpstream_nonblocking);
-                req_raw_header.push(buff_u8_1[0]);
-            }
-            Err(e) => {
-                // println!("{} - 401 - <null> `{}`", ip, e.to_string());
-                println!(
-                    "{} - 401 - <null> (Timeout) {}ms",
-                    ip,
-                    timer.elapsed().unwrap() as f32
-                );
-                client.shutdown(net::Shutdown::Both);
-                return;
+        loop {
+            let timer = utils::Timer::new();
+            if requests_served == 0 {
+                client.set_read_timeout(limits.tcp_read_timeout);
+            } else {
+                client.set_read_timeout(limits.keep_alive_timeout);
             }
-        }
 
-        loop {
-            let req_content_len = match client.read(&mut buff_u8[..]) {
-                Ok(len) => len,
-                Err(e) => {
-                    // client.write_all("HTTP/1.1 408 Request Timeout\r\nServer: Aden 0.1.0\r\n\r\nClient request timed out.\r\n".as_bytes());
-                    if req_raw_header.len() == 0 {
-                        println!(
-                            "{} - 408 - <null> {}ms",
-                            ip,
-                            timer.elapsed().unwrap() as f32
-                        );
-                        client.shutdown(net::Shutdown::Both);
-                        return;
-                    } else {
-                        break;
+            let req = match parser.parse(&mut buffer) {
+                Ok(Some(req)) => req,
+                Ok(None) => {
+                    if parser.take_continue() {
+                        client.write_all(request::CONTINUE_RESPONSE);
+                    }
+                    tracked.set_pending(parser.is_receiving(&buffer));
+                    match client.read(&mut read_buffer) {
+                        Ok(0) | Err(_) if buffer.is_empty() => {
+                            if requests_served == 0 {
+                                log::access(format_args!(
+                                    "{} - 408 - <null> {}ms",
+                                    ip,
+                                    timer.elapsed().unwrap() as f32
+                                ));
+                            }
+                            break;
+                        }
+                        Ok(0) | Err(_) => {
+                            // Half a request and nothing more is coming.
+                            Server::send_error(client, config, status::REQUEST_TIMEOUT);
+                            break;
+                        }
+                        Ok(len) => {
+                            buffer.extend_from_slice(&read_buffer[..len]);
+                            tracked.set_pending(parser.is_receiving(&buffer));
+                        }
                     }
+                    continue;
+                }
+                Err(status) => {
+                    Server::send_error(client, config, status);
+                    tracked.end_request(status.get_code(), timer.elapsed().unwrap());
+                    log::access(format_args!(
+                        "{} - {} - <null> ({}) {}ms",
+                        ip,
+                        status.get_code(),
+                        status.get_message(),
+                        timer.elapsed().unwrap() as f32
+                    ));
+                    break;
                 }
             };
 
-            req_raw_header.extend(buff_u8.to_vec());
-
-            if req_content_len != 1000 {
+            requests_served += 1;
+            tracked.start_request(&req.req_path);
+            let (mut res, keep_alive) = Server::respond(
+                &req,
+                requests_served,
+                &limits,
+                &snapshot.mimetype,
+                &snapshot.hosts,
+                monitor.is_stopping(),
+            );
+            let sent = Server::send_response(client, &mut res);
+            tracked.end_request(res.get_status_code(), timer.elapsed().unwrap());
+
+            log::access(format_args!(
+                "{} - {} - {} ({} ms)",
+                ip,
+                res.get_status_code(),
+                req.req_path,
+                timer.elapsed().unwrap() as f32
+            ));
+
+            if !keep_alive || sent.is_err() || res.must_close() {
                 break;
             }
+        }
 
-            let rrhlen = req_raw_header.len();
-            if rrhlen > 4 {
-                if req_raw_header[rrhlen - 1] == '\n' as u8
-                    && req_raw_header[rrhlen - 2] == '\r' as u8
-                    && req_raw_header[rrhlen - 3] == '\n' as u8
-                    && req_raw_header[rrhlen - 4] == '\r' as u8
-                {
-                    break;
-                }
-            }
+        client.shutdown(net::Shutdown::Both);
+    }
+
+    // Handle one request and mark the response for keeping the connection or not,
+    // never while `stopping`. Shared by every I/O engine.
+    fn respond(
+        req: &Request,
+        requests_served: usize,
+        limits: &ConnectionLimits,
+        mimetype: &Mimetype,
+        hosts: &VirtualHosts,
+        stopping: bool,
+    ) -> (Response, bool) {
+        let mut res = match hosts.select(req) {
+            Ok(site) => Server::handle_request(req, mimetype, site),
+            Err(status) => Server::get_error_page(&hosts.get_main().config, status),
+        };
+        if req.method == "HEAD" {
+            res.set_header_only(true);
+        }
+        if req.http_ver == "HTTP/1.0" {
+            res.set_chunked(false);
         }
 
-        let mut req: Request = match Request::new(&req_raw_header) {
-            Ok(r) => r,
-            Err(e) => {
-                println!(
-                    "{} - 401 - <null> (Bad request) {}ms",
-                    ip,
-                    timer.elapsed().unwrap() as f32
+        let keep_alive = req.is_keep_alive()
+            && !stopping
+            && requests_served < limits.keep_alive_max_requests
+            && !res.must_close();
+        if keep_alive {
+            res.add_header("Connection", "keep-alive");
+            if let Some(timeout) = limits.keep_alive_timeout {
+                let keep_alive_param = format!(
+                    "timeout={}, max={}",
+                    timeout.as_secs(),
+                    limits.keep_alive_max_requests - requests_served
                 );
-                return;
+                res.add_header("Keep-Alive", keep_alive_param.as_str());
             }
-        };
+        } else {
+            res.add_header("Connection", "close");
+        }
 
-        // unalias req.req_path before
-        // check security error:
-        match req.get_header("Content-Length".to_string()) {
-            Ok(ct) => {
-                let content_len: usize = ct.parse().unwrap();
-                let mut req_raw_content: Vec<u8> = vec![0; content_len];
-                client.read_exact(&mut req_raw_content);
-                req.content = String::from_utf8_lossy(&req_raw_content.as_slice()).into_owned();
-            }
-            Err(_) => {}
-        };
+        (res, keep_alive)
+    }
 
-        let root_path = utils::get_root_path();
-        let req_path_split_query_string: Vec<&str> = req.req_path.split("?").collect();
+    fn handle_request(req: &Request, mimetype: &Mimetype, site: &Site) -> Response {
+        let config = &site.config;
+        let aliases = &site.aliases;
+        let dav = &site.dav;
+        let home_dir_err = &config.home_dir_error;
+        let mut compression = Compression::from_config(config);
+
+        let mut resolver = PathResolver::new(&utils::get_root_path(), &config.home_dir);
+        resolver.set_restrict_symlinks(config.restrict_symlinks);
+        resolver.set_aliases(aliases.get_prefixes());
+        resolver.set_forbidden_dirs(&config.forbidden_dirs);
+        let uploads = Uploads::from_config(config, &resolver);
+        // Directories without an index file listed, with everything below them.
+        let autoindex_dirs: Vec<PathBuf> = config
+            .autoindex_dirs
+            .iter()
+            .map(|dir| resolver.root_file(dir))
+            .collect();
+        let req_path_split_query_string: Vec<&str> = req.req_path.split('?').collect();
         let real_req_path = req_path_split_query_string[0];
-        let query_string: &str;
-        if req_path_split_query_string.len() > 1 {
+        let query_string: &str = if req_path_split_query_string.len() > 1 {
             // For future use.
-            query_string = req_path_split_query_string[1];
+            req_path_split_query_string[1]
         } else {
-            query_string = "";
-        }
+            ""
+        };
 
-        let unalias_path = home_dir.to_owned() + real_req_path;
         let mut res: Response = Response::new();
-        let mut forbidden = false;
-        for dir in forbidden_dirs {
-            if unalias_path.starts_with(dir) {
-                forbidden = true;
+        if !KNOWN_METHODS.contains(&req.method.as_str()) {
+            Server::set_error_page(&mut res, &resolver, home_dir_err, status::NOT_IMPLEMENTED);
+            return res;
+        }
+
+        // `OPTIONS *`: what the server itself can do.
+        if req.req_path == "*" {
+            let allowed = Server::get_allowed_methods(&uploads, config.webdav, None);
+            Server::set_options(&mut res, &allowed);
+            return res;
+        }
+
+        // alias.conf rewrites come first, they may send the client elsewhere.
+        let rewritten = resolver::normalize_path(real_req_path)
+            .and_then(|path| aliases.rewrite(&path, query_string));
+        let resolved = match rewritten {
+            Ok(Rewritten::Path(path, query)) => {
+                resolver.resolve_path(&path).map(|file_path| (path, file_path, query))
             }
+            Ok(Rewritten::Redirect(status, location)) => {
+                Server::set_redirect(&mut res, status, &location);
+                return res;
+            }
+            Err(status) => Err(status),
+        };
+        let (norm_req_path, req_path, query_string) = match resolved {
+            Ok(resolved) => resolved,
+            Err(status) => {
+                Server::set_error_page(&mut res, &resolver, home_dir_err, status);
+                return res;
+            }
+        };
+
+        let location = config.get_location(&norm_req_path);
+        if let Some(compress) = location.and_then(|location| location.compression) {
+            compression.set_enabled(compress);
         }
+        let forbidden = Server::is_denied(config, &resolver, &norm_req_path, &req_path);
 
-        let mut req_path = utils::to_root_path(unalias_path.as_str(), &root_path);
         let req_path_isdir = match metadata(&req_path) {
             Ok(mtdat) => mtdat.is_dir(),
             Err(_) => false,
         };
 
+        let allowed = Server::get_allowed_methods(&uploads, config.webdav, Some(&req_path));
+        let is_get = req.method == "GET" || req.method == "HEAD";
+        let creates = req.method == "PUT" || req.method == "MKCOL" || req.method == "LOCK";
+
         if forbidden {
-            req_path = utils::to_root_path((home_dir_err + "/403.html").as_str(), &root_path);
-            res.add_content_from_file(req_path.to_string());
-            res.set_response_text(Some("1.1"), Some(403), Some("Forbidden"));
-            res.add_header("Server", "Aden 0.1");
-            res.add_header("Content-Type", "text/html");
-        }
-        // else if req_path.ends_with(r"\") {
-        else if req_path_isdir {
-            let new_req_path = if req_path.ends_with('\\') {
-                req_path.to_owned() + default_index_file.as_str()
-            } else {
-                req_path.to_owned() + "\\" + default_index_file.as_str()
+            Server::set_error_page(&mut res, &resolver, home_dir_err, status::FORBIDDEN);
+        } else if !is_get && !creates && metadata(&req_path).is_err() {
+            Server::set_error_page(&mut res, &resolver, home_dir_err, status::NOT_FOUND);
+        } else if req.method == "OPTIONS" {
+            Server::set_options(&mut res, &allowed);
+        } else if !allowed.contains(&req.method.as_str()) {
+            let status = status::METHOD_NOT_ALLOWED;
+            Server::set_error_page(&mut res, &resolver, home_dir_err, status);
+            res.add_header("Allow", allowed.join(", ").as_str());
+        } else if UPLOAD_METHODS.contains(&req.method.as_str())
+            || DAV_METHODS.contains(&req.method.as_str())
+        {
+            let ctx = DavContext {
+                config,
+                resolver: &resolver,
+                uploads: &uploads,
+                mimetype,
+            };
+            Server::handle_upload(req, &mut res, &ctx, dav, home_dir_err, &norm_req_path, &req_path);
+        } else if req_path_isdir {
+            let index_files: Vec<&str> = location
+                .and_then(|location| location.index_files.as_ref())
+                .unwrap_or(&config.index_files)
+                .iter()
+                .map(|name| name.as_str())
+                .collect();
+            let alternative_index_basename = location
+                .and_then(|location| location.alternative_index_basename.as_ref())
+                .unwrap_or(&config.alternative_index_basename);
+            let index = resolver.find_index(&req_path, &index_files, alternative_index_basename);
+            let autoindex = match location.and_then(|location| location.autoindex) {
+                Some(autoindex) => autoindex,
+                None => autoindex_dirs.iter().any(|dir| req_path.starts_with(dir)),
             };
 
-            match res.add_content_from_file(new_req_path.to_owned()) {
-                Ok(_) => {
-                    if !req_path.to_owned().ends_with('\\') && !req.req_path.ends_with("/") {
-                        let new_location = req.req_path.to_owned() + "/";
-                        println!("new_location: {}", new_location);
-                        res.set_response_text(Some("1.1"), Some(301), Some("Moved Permanently"));
-                        res.add_header("Location", new_location.as_str());
-                    } else {
-                        res.set_response_text(Some("1.1"), Some(200), Some("OK"));
-                    }
-
-                    res.add_header("Server", "Aden 0.1");
-                    res.add_header(
-                        "Content-Type",
-                        mimetype.get_mimetype_or(&req_path, "text/html").as_str(),
-                    );
-                    req_path = new_req_path;
+            if !norm_req_path.ends_with('/') {
+                // A directory is always at its path with a trailing slash,
+                // the bare prefix of an alias too. Without an index nor a
+                // listing the client gets its 404 from there.
+                let mut new_location = real_req_path.to_owned() + "/";
+                if req_path_split_query_string.len() > 1 {
+                    new_location = new_location + "?" + req_path_split_query_string[1];
                 }
-                Err(e) => {
-                    req_path =
-                        utils::to_root_path((home_dir_err + "/404.html").as_str(), &root_path);
-                    res.add_content_from_file(req_path.to_string());
-                    res.set_response_text(Some("1.1"), Some(404), Some("Not Found"));
-                    res.add_header("Server", "Aden 0.1");
-                    res.add_header("Content-Type", "text/html");
+                log::debug(format_args!("new_location: {}", new_location));
+                Server::set_redirect(&mut res, status::MOVED_PERMANENTLY, &new_location);
+            } else if let Some(index) = index {
+                let content_type = mimetype.get_mimetype_or(&index.to_string_lossy(), "text/html");
+                if let Err(status) =
+                    Server::serve_file(req, &mut res, &index, &content_type, &compression)
+                {
+                    Server::set_error_page(&mut res, &resolver, home_dir_err, status);
+                }
+            } else if autoindex {
+                let listing =
+                    DirListing::read(&norm_req_path, &req_path, &resolver, mimetype);
+                match listing {
+                    Ok(listing) => listing.set_response(req, &mut res, &query_string, &compression),
+                    Err(_) => {
+                        let status = status::FORBIDDEN;
+                        Server::set_error_page(&mut res, &resolver, home_dir_err, status);
+                    }
                 }
+            } else {
+                Server::set_error_page(&mut res, &resolver, home_dir_err, status::NOT_FOUND);
             }
         } else {
-            // must check alias path and convert before send response
-            match res.add_content_from_file(req_path.to_owned()) {
-                Ok(_) => {
-                    res.set_response_text(Some("1.1"), Some(200), Some("OK"));
-                    res.add_header("Server", "Aden 0.1");
+            let content_type = mimetype.get_mimetype_or(&req_path.to_string_lossy(), "text/html");
+            if let Err(status) =
+                Server::serve_file(req, &mut res, &req_path, &content_type, &compression)
+            {
+                Server::set_error_page(&mut res, &resolver, home_dir_err, status);
+            }
+        }
+
+        res
+    }
+
+    // In a forbidden_dir or a `deny` location: nothing is served or written there.
+    fn is_denied(config: &SiteConf, resolver: &PathResolver, href: &str, file_path: &Path) -> bool {
+        resolver.is_forbidden(file_path)
+            || config
+                .get_location(href)
+                .is_some_and(|location| location.deny)
+    }
+
+    // Methods the file or directory at `path` answers to, or any resource
+    // of the server without a path. WebDAV goes where uploads do.
+    fn get_allowed_methods(
+        uploads: &Uploads,
+        webdav: bool,
+        path: Option<&Path>,
+    ) -> Vec<&'static str> {
+        let mut allowed = RESOURCE_METHODS.to_vec();
+        let writable = match path {
+            Some(path) => uploads.is_enabled_for(path),
+            None => uploads.is_enabled(),
+        };
+        if writable {
+            allowed.extend_from_slice(&UPLOAD_METHODS);
+            if webdav {
+                allowed.extend_from_slice(&DAV_METHODS);
+            }
+        }
+        allowed
+    }
+
+    // PUT, DELETE, MKCOL and WebDAV, once the path is known to take them.
+    // `href` is the normalized request path.
+    fn handle_upload(
+        req: &Request,
+        res: &mut Response,
+        ctx: &DavContext,
+        dav: &DavState,
+        home_dir_err: &str,
+        href: &str,
+        path: &Path,
+    ) {
+        let result = if !ctx.uploads.authorize(req) {
+            Err(status::UNAUTHORIZED)
+        } else if DAV_METHODS.contains(&req.method.as_str()) {
+            ctx.resolver
+                .check_write_path(path)
+                .and_then(|_| dav.handle(ctx, req, href, path, res))
+                .map(|_| None)
+        } else {
+            ctx.resolver
+                .check_write_path(path)
+                .and_then(|_| dav.check_write(req, href, path))
+                .and_then(|_| match req.method.as_str() {
+                    "PUT" => ctx.uploads.put(req, path),
+                    "DELETE" => ctx.uploads.delete(path).inspect(|_| dav.forget(href)),
+                    _ => ctx.uploads.mkcol(req, path),
+                })
+                .map(Some)
+        };
+
+        match result {
+            // WebDAV filled the response itself.
+            Ok(None) => {}
+            Ok(Some(status)) => {
+                res.set_response_text(
+                    Some("1.1"),
+                    Some(status.get_code()),
+                    Some(status.get_message()),
+                );
+                res.add_header("Server", "Aden 0.1");
+                res.add_content_from_string(String::new());
+            }
+            Err(status) => {
+                Server::set_error_page(res, ctx.resolver, home_dir_err, status);
+                if status == status::UNAUTHORIZED {
                     res.add_header(
-                        "Content-Type",
-                        mimetype.get_mimetype_or(&req_path, "text/html").as_str(),
+                        "WWW-Authenticate",
+                        "Basic realm=\"Aden\", charset=\"UTF-8\"",
                     );
                 }
-                Err(e) => {
-                    // println!("E: Can't response because: {}", e.to_string());
-                    req_path =
-                        utils::to_root_path((home_dir_err + "/404.html").as_str(), &root_path);
-                    res.add_content_from_file(req_path.to_string());
-                    res.set_response_text(Some("1.1"), Some(404), Some("Not Found"));
-                    res.add_header("Server", "Aden 0.1");
-                    res.add_header("Content-Type", "text/html");
-                }
             }
         }
+    }
 
+    // Send the client to `location`, with a 3xx `status`.
+    fn set_redirect(res: &mut Response, status: HttpStatus, location: &str) {
+        res.set_response_text(
+            Some("1.1"),
+            Some(status.get_code()),
+            Some(status.get_message()),
+        );
+        res.add_header("Server", "Aden 0.1");
+        res.add_header("Location", location);
+        res.add_content_from_string(String::new());
+    }
+
+    // Answer to OPTIONS: an empty 200 listing the `allowed` methods.
+    fn set_options(res: &mut Response, allowed: &[&str]) {
+        res.set_response_text(Some("1.1"), Some(200), Some("OK"));
+        res.add_header("Server", "Aden 0.1");
+        res.add_header("Allow", allowed.join(", ").as_str());
+        if allowed.contains(&"PROPFIND") {
+            res.add_header("DAV", "1, 2");
+            res.add_header("MS-Author-Via", "DAV");
+        }
+        res.add_content_from_string(String::new());
+    }
+
+    // Send a file whole, or only the parts asked with `Range` (206, or 416
+    // when none of them is inside the file). Conditional requests may end
+    // with a 304 instead, the error status to show is returned. Whole files
+    // are compressed if the client accepts it, or taken precompressed.
+    fn serve_file(
+        req: &Request,
+        res: &mut Response,
+        file_path: &Path,
+        content_type: &str,
+        compression: &Compression,
+    ) -> Result<(), HttpStatus> {
+        // A `.br` or `.gz` sibling stands for the file when the client takes it.
+        let precompressed = compression.find_precompressed(req, file_path);
+        let (file_path, encoding) = match precompressed {
+            Some((ref sibling, coding)) => (sibling.as_path(), Some(coding)),
+            None => (file_path, None),
+        };
+
+        let file = File::open(file_path).map_err(|_| status::NOT_FOUND)?;
+        let file_meta = file.metadata().map_err(|_| status::NOT_FOUND)?;
+        if file_meta.is_dir() {
+            return Err(status::NOT_FOUND);
+        }
+        let file_len = file_meta.len();
+
+        let validators = Validators::from_metadata(&file_meta, encoding);
+        let condition = validators.evaluate(req);
+        if let Some(status::PRECONDITION_FAILED) = condition {
+            return Err(status::PRECONDITION_FAILED);
+        }
+
+        // If-Range: the parts only if the client still has this version.
+        let ranges = match req.get_header("Range".to_string()) {
+            Ok(ref value) if req.method == "GET" => match req.get_header("If-Range".to_string()) {
+                Ok(ref if_range) if !validators.if_range_matches(if_range) => RangeSet::Full,
+                _ => range::parse_range(value, file_len),
+            },
+            _ => RangeSet::Full,
+        };
+        // Compressed here only when sent whole, checked before the headers
+        // are set in case it is a 406.
+        let coding = match (encoding, &ranges) {
+            (None, &RangeSet::Full) if condition.is_none() => {
+                compression.choose(req, content_type, file_len)?
+            }
+            _ => None,
+        };
+
+        res.add_header("Server", "Aden 0.1");
+        res.add_header("ETag", validators.get_etag());
+        if let Some(last_modified) = validators.get_last_modified() {
+            res.add_header("Last-Modified", last_modified.as_str());
+        }
+        if let Some(coding) = encoding {
+            res.add_header("Content-Encoding", coding);
+        }
+        if encoding.is_some() || compression.is_compressible(content_type) {
+            res.add_header("Vary", "Accept-Encoding");
+        }
+
+        if let Some(status) = condition {
+            res.set_response_text(
+                Some("1.1"),
+                Some(status.get_code()),
+                Some(status.get_message()),
+            );
+            return Ok(());
+        }
+
+        res.add_header("Accept-Ranges", "bytes");
+
+        let status = match ranges {
+            RangeSet::Full => {
+                res.add_content_from_file(file_path)
+                    .map_err(|_| status::NOT_FOUND)?;
+                res.add_header("Content-Type", content_type);
+                if let Some(coding) = coding {
+                    res.set_content_encoding(coding);
+                    res.add_header("ETag", validators.get_weak_etag().as_str());
+                }
+                status::REQUEST_OK
+            }
+            RangeSet::Unsatisfiable => {
+                res.add_header("Content-Range", format!("bytes */{}", file_len).as_str());
+                res.add_content_from_string(String::new());
+                status::RANGE_NOT_SATISFIABLE
+            }
+            RangeSet::Ranges(ref ranges) if ranges.len() == 1 => {
+                let part = ranges[0];
+                let mut file = file;
+                file.seek(io::SeekFrom::Start(part.start))
+                    .map_err(|_| status::NOT_FOUND)?;
+                let reader = io::BufReader::new(file.take(part.len()));
+
+                res.add_header("Content-Type", content_type);
+                res.add_header("Content-Range", part.get_content_range(file_len).as_str());
+                res.add_content_from_reader(Box::new(reader), Some(part.len()));
+                status::PARTIAL_CONTENT
+            }
+            RangeSet::Ranges(ref ranges) => {
+                let body = MultipartRanges::new(file, ranges, content_type, file_len);
+                let length = body.get_content_length();
+
+                res.add_header("Content-Type", body.get_content_type().as_str());
+                res.add_content_from_reader(Box::new(body), Some(length));
+                status::PARTIAL_CONTENT
+            }
+        };
+
+        res.set_response_text(
+            Some("1.1"),
+            Some(status.get_code()),
+            Some(status.get_message()),
+        );
+        Ok(())
+    }
+
+    // Error page of a site.
+    fn get_error_page(site: &SiteConf, status: HttpStatus) -> Response {
+        let resolver = PathResolver::new(&utils::get_root_path(), &site.home_dir);
+        let mut res: Response = Response::new();
+        Server::set_error_page(&mut res, &resolver, &site.home_dir_error, status);
+        res
+    }
+
+    // Error response used before a request could be handled at all.
+    fn error_response(config: &Configuration, status: HttpStatus) -> Response {
+        let mut res = Server::get_error_page(&config.site, status);
+        res.add_header("Connection", "close");
+        res
+    }
+
+    fn send_error(client: &mut TcpStream, config: &Configuration, status: HttpStatus) {
+        let mut res = Server::error_response(config, status);
+        Server::send_response(client, &mut res);
+    }
+
+    fn send_response(client: &mut TcpStream, res: &mut Response) -> io::Result<()> {
         let res_built_hd = match res.build_header() {
             Ok(r) => r,
             Err(e) => {
-                println!("E: Can't build response by: {}", e.to_string());
-                return;
+                log::error(format_args!("Can't build response by: {}", e));
+                return Err(e);
             }
         };
 
-        client.set_nonblocking(false);
-        client.write_all(res_built_hd.as_bytes());
+        client.write_all(res_built_hd.as_bytes())?;
 
         // Fix this res.build_content if file size is too big, crash system.
         let mut client_bufwriter = BufWriter::new(client);
         loop {
             let (content, remaining_bytes) = res.build_content();
-            client_bufwriter.write(content);
-            client_bufwriter.flush();
+            client_bufwriter.write_all(content)?;
             if remaining_bytes == 0 {
                 break;
             }
         }
 
-        println!(
-            "{} - {} - {} ({} ms)",
-            ip,
-            res.get_status_code(),
-            req.req_path,
-            timer.elapsed().unwrap() as f32
-        );
+        client_bufwriter.flush()
     }
 
-    pub fn shutdown(self) {}
+    // Answer a connection the pool has no room for.
+    fn send_busy(client: &mut TcpStream, config: &Configuration) {
+        let mut res = Server::error_response(config, status::SERVICE_UNAVAILABLE);
+        let retry_after = config.server.retry_after.as_secs().to_string();
+        res.add_header("Retry-After", &retry_after);
+
+        // Consume what the client already sent, closing with unread data
+        // resets the connection and the client may never see the 503.
+        let mut discard = [0; 4096];
+        client.set_read_timeout(Some(Duration::from_millis(50)));
+        let _ = client.read(&mut discard);
+
+        Server::send_response(client, &mut res);
+        client.shutdown(net::Shutdown::Both);
+    }
+
+    // Fill `res` with `<home_dir_error>/<code>.html`, or a short text if the page is missing.
+    fn set_error_page(
+        res: &mut Response,
+        resolver: &PathResolver,
+        home_dir_err: &str,
+        status: HttpStatus,
+    ) {
+        let error_page =
+            resolver.root_file(&format!("{}/{}.html", home_dir_err, status.get_code()));
+        res.set_response_text(
+            Some("1.1"),
+            Some(status.get_code()),
+            Some(status.get_message()),
+        );
+        res.add_header("Server", "Aden 0.1");
+        if res.add_content_from_file(&error_page).is_ok() {
+            res.add_header("Content-Type", "text/html");
+        } else {
+            res.add_content_from_string(status.to_string());
+            res.add_header("Content-Type", "text/plain");
+        }
+    }
 }
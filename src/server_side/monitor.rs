@@ -0,0 +1,269 @@
+/*
+ *	What the server is doing, for the console and a stop: open connections,
+ *	counters and banned addresses. Connections are closed from here through
+ *	a clone of their socket, whichever engine serves them.
+ */
+use std::cell::Cell;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub id: usize,
+    pub peer: SocketAddr,
+    pub since: Instant,
+    pub requests: usize,
+    // Request being answered, None between requests.
+    pub path: Option<String>,
+    // Part of the next request came in, or the connection waits for a
+    // worker: it is not idle either.
+    pub pending: bool,
+}
+
+// Counters since start.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub accepted: usize,
+    pub requests: usize,
+    // Responses by class, 1xx to 5xx.
+    pub statuses: [usize; 5],
+    // Answered 503 with every worker busy.
+    pub busy: usize,
+    // Closed right away, from a banned address.
+    pub refused: usize,
+    pub kicked: usize,
+    pub response_time: Duration,
+}
+
+#[derive(Debug)]
+pub struct Monitor {
+    started: Instant,
+    next_id: AtomicUsize,
+    // With a clone of the socket, to close it.
+    connections: Mutex<Vec<(ConnectionInfo, Option<TcpStream>)>>,
+    banned: RwLock<Vec<IpAddr>>,
+    accepted: AtomicUsize,
+    requests: AtomicUsize,
+    statuses: [AtomicUsize; 5],
+    busy: AtomicUsize,
+    refused: AtomicUsize,
+    kicked: AtomicUsize,
+    response_micros: AtomicU64,
+    // No new connections, no keep-alive.
+    stopping: AtomicBool,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            started: Instant::now(),
+            next_id: AtomicUsize::new(1),
+            connections: Mutex::new(vec![]),
+            banned: RwLock::new(vec![]),
+            accepted: AtomicUsize::new(0),
+            requests: AtomicUsize::new(0),
+            statuses: [const { AtomicUsize::new(0) }; 5],
+            busy: AtomicUsize::new(0),
+            refused: AtomicUsize::new(0),
+            kicked: AtomicUsize::new(0),
+            response_micros: AtomicU64::new(0),
+            stopping: AtomicBool::new(false),
+        }
+    }
+
+    // Register an accepted connection, until the returned guard is dropped.
+    pub fn track(monitor: &Arc<Monitor>, stream: &TcpStream, peer: SocketAddr) -> Tracked {
+        let id = monitor.next_id.fetch_add(1, Ordering::SeqCst);
+        let info = ConnectionInfo {
+            id,
+            peer,
+            since: Instant::now(),
+            requests: 0,
+            path: None,
+            pending: false,
+        };
+        monitor
+            .lock_connections()
+            .push((info, stream.try_clone().ok()));
+        monitor.accepted.fetch_add(1, Ordering::SeqCst);
+        Tracked {
+            monitor: Arc::clone(monitor),
+            id,
+            pending: Cell::new(false),
+        }
+    }
+
+    pub fn get_uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn get_connections(&self) -> Vec<ConnectionInfo> {
+        self.lock_connections()
+            .iter()
+            .map(|conn| conn.0.clone())
+            .collect()
+    }
+
+    pub fn get_stats(&self) -> Stats {
+        let mut statuses = [0; 5];
+        for (count, counter) in statuses.iter_mut().zip(self.statuses.iter()) {
+            *count = counter.load(Ordering::SeqCst);
+        }
+        Stats {
+            accepted: self.accepted.load(Ordering::SeqCst),
+            requests: self.requests.load(Ordering::SeqCst),
+            statuses,
+            busy: self.busy.load(Ordering::SeqCst),
+            refused: self.refused.load(Ordering::SeqCst),
+            kicked: self.kicked.load(Ordering::SeqCst),
+            response_time: Duration::from_micros(self.response_micros.load(Ordering::SeqCst)),
+        }
+    }
+
+    pub fn count_busy(&self) {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn count_refused(&self) {
+        self.refused.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Close the connections of `ip`, returns how many.
+    pub fn kick(&self, ip: IpAddr) -> usize {
+        let kicked = self.close_where(|info| info.peer.ip() == ip);
+        self.kicked.fetch_add(kicked, Ordering::SeqCst);
+        kicked
+    }
+
+    // Close connections between requests, with nothing of the next one
+    // received, returns how many.
+    pub fn close_idle(&self) -> usize {
+        self.close_where(|info| info.path.is_none() && !info.pending)
+    }
+
+    pub fn close_all(&self) -> usize {
+        self.close_where(|_| true)
+    }
+
+    fn close_where<F: Fn(&ConnectionInfo) -> bool>(&self, f: F) -> usize {
+        let connections = self.lock_connections();
+        let mut closed = 0;
+        for conn in connections.iter().filter(|conn| f(&conn.0)) {
+            if let Some(ref stream) = conn.1 {
+                stream.shutdown(Shutdown::Both);
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    pub fn set_stopping(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.read_banned().contains(&ip)
+    }
+
+    pub fn get_banned(&self) -> Vec<IpAddr> {
+        self.read_banned().clone()
+    }
+
+    // Refuse new connections from `ip`, false if it already was.
+    pub fn ban(&self, ip: IpAddr) -> bool {
+        let mut banned = self.banned.write().unwrap_or_else(|e| e.into_inner());
+        if banned.contains(&ip) {
+            return false;
+        }
+        banned.push(ip);
+        true
+    }
+
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let mut banned = self.banned.write().unwrap_or_else(|e| e.into_inner());
+        let count = banned.len();
+        banned.retain(|&banned_ip| banned_ip != ip);
+        banned.len() != count
+    }
+
+    fn lock_connections(
+        &self,
+    ) -> std::sync::MutexGuard<'_, Vec<(ConnectionInfo, Option<TcpStream>)>> {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn read_banned(&self) -> std::sync::RwLockReadGuard<'_, Vec<IpAddr>> {
+        self.banned.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, id: usize, f: &dyn Fn(&mut ConnectionInfo)) {
+        if let Some(conn) = self
+            .lock_connections()
+            .iter_mut()
+            .find(|conn| conn.0.id == id)
+        {
+            f(&mut conn.0);
+        }
+    }
+}
+
+// An open connection, unregistered when dropped.
+#[derive(Debug)]
+pub struct Tracked {
+    monitor: Arc<Monitor>,
+    id: usize,
+    // As last told to the monitor.
+    pending: Cell<bool>,
+}
+
+impl Tracked {
+    pub fn is_stopping(&self) -> bool {
+        self.monitor.is_stopping()
+    }
+
+    // See `ConnectionInfo::pending`.
+    pub fn set_pending(&self, pending: bool) {
+        if self.pending.replace(pending) != pending {
+            self.monitor.update(self.id, &|info| info.pending = pending);
+        }
+    }
+
+    pub fn start_request(&self, path: &str) {
+        self.pending.set(false);
+        self.monitor.update(self.id, &|info| {
+            info.requests += 1;
+            info.path = Some(path.to_string());
+            info.pending = false;
+        });
+    }
+
+    // A response was sent, `millis` after the request came.
+    pub fn end_request(&self, status_code: u16, millis: f64) {
+        let monitor = &self.monitor;
+        monitor.update(self.id, &|info| info.path = None);
+        monitor.requests.fetch_add(1, Ordering::SeqCst);
+        if let Some(counter) = monitor
+            .statuses
+            .get((status_code as usize / 100).wrapping_sub(1))
+        {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+        monitor
+            .response_micros
+            .fetch_add((millis * 1000.0) as u64, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.monitor
+            .lock_connections()
+            .retain(|conn| conn.0.id != self.id);
+    }
+}
@@ -0,0 +1,183 @@
+/*
+ *	Fixed-size pool of worker threads serving accepted connections.
+ */
+use std::net::{SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use server_side::log;
+use server_side::monitor::Tracked;
+
+// Tracked from accept time, so a stop waits for the queued ones too.
+type Job = (TcpStream, SocketAddr, Tracked);
+
+// What the accept loop does when every worker is busy and the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    // Stop accepting until a worker is free.
+    Block,
+    // Answer 503 right away and close.
+    Reject,
+}
+
+impl OverflowPolicy {
+    pub fn from_name(s: &str) -> Option<OverflowPolicy> {
+        match s.to_lowercase().as_str() {
+            "block" => Some(OverflowPolicy::Block),
+            "reject" | "503" => Some(OverflowPolicy::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            OverflowPolicy::Block => "block",
+            OverflowPolicy::Reject => "reject",
+        }
+    }
+}
+
+pub struct WorkerPool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    // `size` workers share one queue holding at most `queue_size` waiting connections.
+    pub fn new<F>(size: usize, queue_size: usize, handler: F) -> WorkerPool
+    where
+        F: Fn(TcpStream, SocketAddr, Tracked) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        let workers = (0..size.max(1))
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                thread::Builder::new()
+                    .name(format!("aden-worker-{}", id))
+                    .spawn(move || WorkerPool::work(&receiver, &*handler))
+                    .expect("can't spawn worker thread")
+            })
+            .collect();
+
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    fn work<F>(receiver: &Mutex<Receiver<Job>>, handler: &F)
+    where
+        F: Fn(TcpStream, SocketAddr, Tracked),
+    {
+        loop {
+            // Hold the lock only while waiting, not while serving.
+            let job = match receiver.lock() {
+                Ok(rx) => rx.recv(),
+                Err(_) => return,
+            };
+
+            let (socket, sock_addr, tracked) = match job {
+                Ok(job) => job,
+                // Sender dropped: the pool is shutting down.
+                Err(_) => return,
+            };
+
+            // A bug hit by one connection closes it, the worker stays: lost
+            // workers would leave the server answering 503 for good.
+            let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                handler(socket, sock_addr, tracked)
+            }));
+            if served.is_err() {
+                log::error(format_args!(
+                    "A worker panicked serving {}, the connection is closed.",
+                    sock_addr.ip()
+                ));
+            }
+        }
+    }
+
+    // Queue a connection according to `policy`, marked pending until a
+    // worker takes it. On rejection the socket is handed back so the caller
+    // can answer it.
+    pub fn dispatch(
+        &self,
+        socket: TcpStream,
+        sock_addr: SocketAddr,
+        tracked: Tracked,
+        policy: OverflowPolicy,
+    ) -> Result<(), TcpStream> {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return Err(socket),
+        };
+
+        tracked.set_pending(true);
+        match policy {
+            OverflowPolicy::Block => sender.send((socket, sock_addr, tracked)).map_err(|e| (e.0).0),
+            OverflowPolicy::Reject => match sender.try_send((socket, sock_addr, tracked)) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full((socket, _, _))) => Err(socket),
+                Err(TrySendError::Disconnected((socket, _, _))) => Err(socket),
+            },
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    // Let the workers finish the queued connections, then join them.
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server_side::monitor::Monitor;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn workers_survive_a_panicking_handler() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let monitor = Arc::new(Monitor::new());
+        let (done, served) = channel();
+        let done = Mutex::new(done);
+        let calls = AtomicUsize::new(0);
+
+        // One worker: it must still be there after the first three panics.
+        let pool = WorkerPool::new(1, 8, move |_, _, _| {
+            if calls.fetch_add(1, Ordering::SeqCst) < 3 {
+                panic!("bug");
+            }
+            done.lock().unwrap().send(()).unwrap();
+        });
+
+        let mut clients = vec![];
+        for _ in 0..4 {
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (socket, sock_addr) = listener.accept().unwrap();
+            let tracked = Monitor::track(&monitor, &socket, sock_addr);
+            assert!(pool
+                .dispatch(socket, sock_addr, tracked, OverflowPolicy::Block)
+                .is_ok());
+        }
+
+        assert!(served.recv_timeout(Duration::from_secs(5)).is_ok());
+        drop(pool);
+        // The connections of the panicked ones were let go too.
+        assert!(monitor.get_connections().is_empty());
+    }
+}
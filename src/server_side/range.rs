@@ -0,0 +1,360 @@
+/*
+ *	Byte-range requests (`Range: bytes=...`) on files.
+ */
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// More ranges than this in one request is not a real client, send the whole file.
+const MAX_RANGES: usize = 32;
+
+// Inclusive byte positions, like in `Content-Range`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn get_content_range(&self, file_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, file_len)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeSet {
+    // No usable Range header: send the whole file.
+    Full,
+    // Every range starts after the end of the file: 416.
+    Unsatisfiable,
+    Ranges(Vec<ByteRange>),
+}
+
+// Parse the value of a `Range` header for a file of `file_len` bytes.
+// A header we can't understand is ignored, as RFC 7233 asks.
+pub fn parse_range(value: &str, file_len: u64) -> RangeSet {
+    let value = value.trim();
+    let eq = match value.find('=') {
+        Some(pos) => pos,
+        None => return RangeSet::Full,
+    };
+
+    if !value[..eq].trim().eq_ignore_ascii_case("bytes") {
+        return RangeSet::Full;
+    }
+
+    let specs: Vec<&str> = value[eq + 1..]
+        .split(',')
+        .map(|spec| spec.trim())
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeSet::Full;
+    }
+
+    let mut ranges: Vec<ByteRange> = vec![];
+    for spec in specs {
+        let dash = match spec.find('-') {
+            Some(pos) => pos,
+            None => return RangeSet::Full,
+        };
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+        if first.is_empty() {
+            // Suffix range: the last `n` bytes.
+            let suffix_len = match parse_position(last) {
+                Some(n) => n,
+                None => return RangeSet::Full,
+            };
+            if suffix_len > 0 && file_len > 0 {
+                ranges.push(ByteRange {
+                    start: file_len - suffix_len.min(file_len),
+                    end: file_len - 1,
+                });
+            }
+            continue;
+        }
+
+        let start = match parse_position(first) {
+            Some(n) => n,
+            None => return RangeSet::Full,
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match parse_position(last) {
+                Some(n) if n >= start => n,
+                _ => return RangeSet::Full,
+            }
+        };
+
+        if start < file_len {
+            ranges.push(ByteRange {
+                start,
+                end: end.min(file_len - 1),
+            });
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeSet::Unsatisfiable;
+    }
+
+    RangeSet::Ranges(coalesce(ranges))
+}
+
+fn parse_position(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// Merge overlapping or touching ranges so no byte is sent twice.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    if ranges.len() < 2 {
+        return ranges;
+    }
+
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = vec![];
+    for r in ranges {
+        if let Some(last) = merged.last_mut() {
+            if r.start <= last.end.saturating_add(1) {
+                last.end = last.end.max(r.end);
+                continue;
+            }
+        }
+        merged.push(r);
+    }
+    merged
+}
+
+enum Part {
+    Text(Vec<u8>),
+    File(ByteRange),
+}
+
+// Body of a `multipart/byteranges` response, read straight from the file.
+pub struct MultipartRanges {
+    file: File,
+    parts: Vec<Part>,
+    index: usize,
+    offset: u64,
+    boundary: String,
+    content_length: u64,
+}
+
+impl MultipartRanges {
+    pub fn new(
+        file: File,
+        ranges: &[ByteRange],
+        content_type: &str,
+        file_len: u64,
+    ) -> MultipartRanges {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64 ^ d.as_secs())
+            .unwrap_or(0);
+        let boundary = format!("aden_{:016x}", nanos);
+
+        let mut parts: Vec<Part> = vec![];
+        for r in ranges {
+            let part_header = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                r.get_content_range(file_len)
+            );
+            parts.push(Part::Text(part_header.into_bytes()));
+            parts.push(Part::File(*r));
+        }
+        parts.push(Part::Text(format!("\r\n--{}--\r\n", boundary).into_bytes()));
+
+        let content_length = parts
+            .iter()
+            .map(|part| match *part {
+                Part::Text(ref bytes) => bytes.len() as u64,
+                Part::File(ref r) => r.len(),
+            })
+            .sum();
+
+        MultipartRanges {
+            file,
+            parts,
+            index: 0,
+            offset: 0,
+            boundary,
+            content_length,
+        }
+    }
+
+    pub fn get_content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    pub fn get_content_length(&self) -> u64 {
+        self.content_length
+    }
+}
+
+impl Read for MultipartRanges {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.index < self.parts.len() {
+            match self.parts[self.index] {
+                Part::Text(ref bytes) => {
+                    let rest = &bytes[self.offset as usize..];
+                    if !rest.is_empty() {
+                        let len = rest.len().min(buf.len());
+                        buf[..len].copy_from_slice(&rest[..len]);
+                        self.offset += len as u64;
+                        return Ok(len);
+                    }
+                }
+                Part::File(ref r) => {
+                    let rest = r.len() - self.offset;
+                    if rest > 0 {
+                        self.file.seek(SeekFrom::Start(r.start + self.offset))?;
+                        let len = (&mut self.file).take(rest).read(buf)?;
+                        if len == 0 {
+                            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                        }
+                        self.offset += len as u64;
+                        return Ok(len);
+                    }
+                }
+            }
+
+            self.index += 1;
+            self.offset = 0;
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn ranges(value: &str, file_len: u64) -> Vec<(u64, u64)> {
+        match parse_range(value, file_len) {
+            RangeSet::Ranges(ranges) => ranges.iter().map(|r| (r.start, r.end)).collect(),
+            other => panic!("{}: {:?}", value, other),
+        }
+    }
+
+    #[test]
+    fn parses_the_range_forms() {
+        assert_eq!(ranges("bytes=0-499", 1000), vec![(0, 499)]);
+        // Past the end of the file, cut at the last byte.
+        assert_eq!(ranges("bytes=900-1999", 1000), vec![(900, 999)]);
+        // Open: to the end.
+        assert_eq!(ranges("bytes=500-", 1000), vec![(500, 999)]);
+        // Suffix: the last n bytes, all of them when n is longer.
+        assert_eq!(ranges("bytes=-300", 1000), vec![(700, 999)]);
+        assert_eq!(ranges("bytes=-3000", 1000), vec![(0, 999)]);
+        assert_eq!(ranges(" Bytes = 0-0 , -1 ", 1000), vec![(0, 0), (999, 999)]);
+    }
+
+    #[test]
+    fn merges_overlapping_and_touching_ranges() {
+        assert_eq!(
+            ranges("bytes=500-700,0-99,650-800", 1000),
+            vec![(0, 99), (500, 800)]
+        );
+        assert_eq!(ranges("bytes=0-99,100-199,-800", 1000), vec![(0, 999)]);
+        assert_eq!(ranges("bytes=0-9,20-29", 1000), vec![(0, 9), (20, 29)]);
+    }
+
+    #[test]
+    fn headers_it_cant_use_send_the_whole_file() {
+        for value in [
+            "0-99",
+            "items=0-99",
+            "bytes=",
+            "bytes=a-b",
+            "bytes=99-0",
+            "bytes=0-99,x",
+            "bytes=--1",
+            "bytes=+1-2",
+            "bytes=99999999999999999999-",
+        ]
+        .iter()
+        {
+            assert_eq!(parse_range(value, 1000), RangeSet::Full, "{}", value);
+        }
+    }
+
+    #[test]
+    fn caps_the_number_of_ranges() {
+        let specs: Vec<String> = (0..MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+            .collect();
+        let value = format!("bytes={}", specs.join(","));
+        assert_eq!(ranges(&value, 1000).len(), MAX_RANGES);
+        assert_eq!(
+            parse_range(&format!("{},999-", value), 1000),
+            RangeSet::Full
+        );
+    }
+
+    #[test]
+    fn ranges_after_the_end_are_416() {
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeSet::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=1000-1100,2000-", 1000),
+            RangeSet::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeSet::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-,-5", 0), RangeSet::Unsatisfiable);
+        // One satisfiable range is enough.
+        assert_eq!(ranges("bytes=1000-,5-6", 1000), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn frames_each_range_as_a_part() {
+        let path = env::temp_dir().join(format!("aden-range-{}", process::id()));
+        fs::write(&path, "0123456789abcdefghij").unwrap();
+        let ranges = [
+            ByteRange { start: 0, end: 2 },
+            ByteRange { start: 15, end: 19 },
+        ];
+        let mut body = MultipartRanges::new(File::open(&path).unwrap(), &ranges, "text/plain", 20);
+
+        let content_type = body.get_content_type();
+        let boundary = content_type
+            .trim_start_matches("multipart/byteranges; boundary=")
+            .to_string();
+        assert!(boundary.len() > 10 && boundary != content_type);
+
+        // Small reads cross from part headers to file bytes and back.
+        let mut content = vec![];
+        let mut buf = [0; 7];
+        loop {
+            let len = body.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            content.extend_from_slice(&buf[..len]);
+        }
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-2/20\r\n\r\n012\
+             \r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 15-19/20\r\n\r\nfghij\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(String::from_utf8(content).unwrap(), expected);
+        assert_eq!(body.get_content_length(), expected.len() as u64);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
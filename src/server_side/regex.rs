@@ -0,0 +1,660 @@
+/*
+ *	Small regular expressions for alias.conf rewrites: literals, `.`, classes
+ *	(`[a-z]`, `[^/]`, `\d`, `\w`, `\s`), anchors, groups, `|` and the `*`, `+`,
+ *	`?`, `{n,m}` quantifiers (lazy with a trailing `?`). No backreferences.
+ *
+ *	Patterns compile to a small program run by a backtracking machine that
+ *	never tries the same instruction at the same position twice, so matching
+ *	takes at most program length × text length steps, whatever the pattern.
+ */
+
+// Patterns compiling to more instructions than this are refused.
+const MAX_PROGRAM_SIZE: usize = 10000;
+// Highest count accepted in `{n,m}`.
+const MAX_REPEAT: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    negated: bool,
+    // Inclusive char ranges.
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(low, high)| low <= c && c <= high) != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ast {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    // Group, with its capture number if it captures.
+    Group(Box<Ast>, Option<usize>),
+    Concat(Vec<Ast>),
+    Alternate(Vec<Ast>),
+    Repeat {
+        ast: Box<Ast>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    // Try the first target, then the second.
+    Split(usize, usize),
+    Jump(usize),
+    // Record the position in capture slot n.
+    Save(usize),
+    Start,
+    End,
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    // Capture groups, the whole match being group 0.
+    groups: usize,
+}
+
+// Byte ranges of the groups of a match in the text, None for a group that
+// took no part in it.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 1,
+        };
+        let ast = parser.parse_alternate()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched `)` at {}", parser.pos));
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&ast, &mut program)?;
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+
+        Ok(Regex {
+            program,
+            groups: parser.groups,
+        })
+    }
+
+    pub fn get_group_count(&self) -> usize {
+        self.groups
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.captures(text).is_some()
+    }
+
+    // Leftmost match in `text`.
+    pub fn captures(&self, text: &str) -> Option<Captures> {
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let positions = chars.len() + 1;
+        // (instruction, position) pairs already tried, they can't match twice.
+        let mut visited = vec![0u64; (self.program.len() * positions).div_ceil(64)];
+
+        for start in 0..positions {
+            if let Some(slots) = self.run(&chars, start, &mut visited) {
+                let offset = |pos: usize| chars.get(pos).map_or(text.len(), |c| c.0);
+                return Some(
+                    (0..self.groups)
+                        .map(|group| match (slots[group * 2], slots[group * 2 + 1]) {
+                            (Some(begin), Some(end)) => Some((offset(begin), offset(end))),
+                            _ => None,
+                        })
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+
+    fn run(
+        &self,
+        chars: &[(usize, char)],
+        start: usize,
+        visited: &mut [u64],
+    ) -> Option<Vec<Option<usize>>> {
+        enum Job {
+            Try(usize, usize),
+            Restore(usize, Option<usize>),
+        }
+
+        let positions = chars.len() + 1;
+        let mut slots: Vec<Option<usize>> = vec![None; self.groups * 2];
+        let mut jobs = vec![Job::Try(0, start)];
+
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Try(pc, pos) => (pc, pos),
+                Job::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+
+            loop {
+                let state = pc * positions + pos;
+                if visited[state / 64] & (1 << (state % 64)) != 0 {
+                    break;
+                }
+                visited[state / 64] |= 1 << (state % 64);
+
+                let c = chars.get(pos).map(|c| c.1);
+                match self.program[pc] {
+                    Inst::Char(expected) if c == Some(expected) => {
+                        pc += 1;
+                        pos += 1;
+                    }
+                    Inst::Any if c.is_some() => {
+                        pc += 1;
+                        pos += 1;
+                    }
+                    Inst::Class(ref class) if c.is_some_and(|c| class.matches(c)) => {
+                        pc += 1;
+                        pos += 1;
+                    }
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Try(second, pos));
+                        pc = first;
+                    }
+                    Inst::Jump(target) => pc = target,
+                    Inst::Save(slot) => {
+                        jobs.push(Job::Restore(slot, slots[slot]));
+                        slots[slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::Start if pos == 0 => pc += 1,
+                    Inst::End if pos == chars.len() => pc += 1,
+                    Inst::Match => return Some(slots),
+                    _ => break,
+                }
+            }
+        }
+        None
+    }
+}
+
+// Text of `replacement` with `$0`..`$9` and `${n}` replaced by the groups
+// of `captures` in `text`, `$$` by `$`.
+pub fn expand(replacement: &str, text: &str, captures: &Captures) -> String {
+    let group = |n: usize| {
+        captures
+            .get(n)
+            .and_then(|range| range.map(|(begin, end)| &text[begin..end]))
+            .unwrap_or("")
+    };
+
+    let mut expanded = String::with_capacity(replacement.len());
+    let mut rest = replacement;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(digit) = rest.chars().next().and_then(|c| c.to_digit(10)) {
+            expanded.push_str(group(digit as usize));
+            rest = &rest[1..];
+        } else if let Some(n) = rest
+            .strip_prefix('{')
+            .and_then(|braced| braced.find('}').map(|close| &braced[..close]))
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            expanded.push_str(group(n));
+            rest = &rest[rest.find('}').unwrap_or(0) + 1..];
+        } else {
+            expanded.push('$');
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    // Groups numbered so far, including group 0.
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Ast, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.remove(0)
+        } else {
+            Ast::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast, String> {
+        let mut items = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_quantifier(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.remove(0),
+            _ => Ast::Concat(items),
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, String> {
+        let at = self.pos;
+        match self.next() {
+            Some('(') => {
+                let capture = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(format!("unknown group kind at {}", at));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups - 1)
+                };
+                let inner = self.parse_alternate()?;
+                if !self.eat(')') {
+                    return Err(format!("unclosed `(` at {}", at));
+                }
+                Ok(Ast::Group(Box::new(inner), capture))
+            }
+            Some('[') => self.parse_class(at),
+            Some('.') => Ok(Ast::Any),
+            Some('^') => Ok(Ast::Start),
+            Some('$') => Ok(Ast::End),
+            Some('\\') => match self.parse_escape()? {
+                Escape::Char(c) => Ok(Ast::Literal(c)),
+                Escape::Class(class) => Ok(Ast::Class(class)),
+            },
+            Some(c @ '*') | Some(c @ '+') | Some(c @ '?') => {
+                Err(format!("`{}` with nothing to repeat at {}", c, at))
+            }
+            Some(c) => Ok(Ast::Literal(c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn parse_quantifier(&mut self, atom: Ast) -> Result<Ast, String> {
+        let at = self.pos;
+        let (min, max) = match self.peek() {
+            Some('{') => match self.parse_counts() {
+                Some(counts) => counts,
+                // Not a count, a `{` to match as is.
+                None => return Ok(atom),
+            },
+            Some(c @ '*') | Some(c @ '+') | Some(c @ '?') => {
+                self.pos += 1;
+                match c {
+                    '*' => (0, None),
+                    '+' => (1, None),
+                    _ => (0, Some(1)),
+                }
+            }
+            _ => return Ok(atom),
+        };
+        if max.is_some_and(|max| max < min) || min > MAX_REPEAT || max > Some(MAX_REPEAT) {
+            return Err(format!("bad repeat count at {}", at));
+        }
+        if matches!(atom, Ast::Start | Ast::End | Ast::Empty) {
+            return Err(format!("nothing to repeat at {}", at));
+        }
+
+        let greedy = !self.eat('?');
+        Ok(Ast::Repeat {
+            ast: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    // `{n}`, `{n,}` or `{n,m}`, the position left after the `}`.
+    fn parse_counts(&mut self) -> Option<(u32, Option<u32>)> {
+        let close = self.pos + self.chars[self.pos..].iter().position(|&c| c == '}')?;
+        let inside: String = self.chars[self.pos + 1..close].iter().collect();
+        let mut parts = inside.splitn(2, ',');
+        let min = parts.next()?.trim().parse().ok()?;
+        let max = match parts.next() {
+            None => Some(min),
+            Some(max) if max.trim().is_empty() => None,
+            Some(max) => Some(max.trim().parse().ok()?),
+        };
+        self.pos = close + 1;
+        Some((min, max))
+    }
+
+    fn parse_class(&mut self, at: usize) -> Result<Ast, String> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut first = true;
+
+        loop {
+            let c = match self.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.parse_escape()? {
+                    Escape::Char(c) => c,
+                    Escape::Class(ref class) if !class.negated => {
+                        ranges.extend_from_slice(&class.ranges);
+                        first = false;
+                        continue;
+                    }
+                    Escape::Class(_) => {
+                        return Err(format!("negated escape in class at {}", at));
+                    }
+                },
+                Some(c) => c,
+                None => return Err(format!("unclosed `[` at {}", at)),
+            };
+            first = false;
+
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).is_some_and(|&next| next != ']');
+            if is_range {
+                self.pos += 1;
+                let high = match self.next() {
+                    Some('\\') => match self.parse_escape()? {
+                        Escape::Char(high) => high,
+                        Escape::Class(_) => return Err(format!("bad range at {}", at)),
+                    },
+                    Some(high) => high,
+                    None => return Err(format!("unclosed `[` at {}", at)),
+                };
+                if high < c {
+                    return Err(format!("bad range at {}", at));
+                }
+                ranges.push((c, high));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+
+        Ok(Ast::Class(Class { negated, ranges }))
+    }
+
+    // After a `\`.
+    fn parse_escape(&mut self) -> Result<Escape, String> {
+        let class = |negated: bool, ranges: &[(char, char)]| {
+            Ok(Escape::Class(Class {
+                negated,
+                ranges: ranges.to_vec(),
+            }))
+        };
+        let digit = [('0', '9')];
+        let word = [('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+        let space = [(' ', ' '), ('\t', '\r')];
+
+        match self.next() {
+            Some('d') => class(false, &digit),
+            Some('D') => class(true, &digit),
+            Some('w') => class(false, &word),
+            Some('W') => class(true, &word),
+            Some('s') => class(false, &space),
+            Some('S') => class(true, &space),
+            Some('n') => Ok(Escape::Char('\n')),
+            Some('t') => Ok(Escape::Char('\t')),
+            Some(c) if c.is_ascii_alphanumeric() => {
+                Err(format!("unknown escape `\\{}` at {}", c, self.pos - 2))
+            }
+            Some(c) => Ok(Escape::Char(c)),
+            None => Err("trailing `\\`".to_string()),
+        }
+    }
+}
+
+enum Escape {
+    Char(char),
+    Class(Class),
+}
+
+fn compile(ast: &Ast, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err("pattern too big".to_string());
+    }
+
+    match *ast {
+        Ast::Empty => {}
+        Ast::Literal(c) => program.push(Inst::Char(c)),
+        Ast::Any => program.push(Inst::Any),
+        Ast::Class(ref class) => program.push(Inst::Class(class.clone())),
+        Ast::Start => program.push(Inst::Start),
+        Ast::End => program.push(Inst::End),
+        Ast::Group(ref inner, capture) => {
+            if let Some(n) = capture {
+                program.push(Inst::Save(n * 2));
+            }
+            compile(inner, program)?;
+            if let Some(n) = capture {
+                program.push(Inst::Save(n * 2 + 1));
+            }
+        }
+        Ast::Concat(ref items) => {
+            for item in items.iter() {
+                compile(item, program)?;
+            }
+        }
+        Ast::Alternate(ref branches) => {
+            // Split to each branch in turn, every branch jumps to the end.
+            let mut jumps = vec![];
+            for (i, branch) in branches.iter().enumerate() {
+                let split = program.len();
+                if i + 1 < branches.len() {
+                    program.push(Inst::Split(split + 1, 0));
+                }
+                compile(branch, program)?;
+                if i + 1 < branches.len() {
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Ast::Repeat {
+            ref ast,
+            min,
+            max,
+            greedy,
+        } => {
+            for _ in 0..min {
+                compile(ast, program)?;
+            }
+
+            let split = |body: usize, out: usize| {
+                if greedy {
+                    Inst::Split(body, out)
+                } else {
+                    Inst::Split(out, body)
+                }
+            };
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Split(0, 0));
+                    compile(ast, program)?;
+                    program.push(Inst::Jump(start));
+                    let out = program.len();
+                    program[start] = split(start + 1, out);
+                }
+                Some(max) => {
+                    // Each optional copy may stop the repetition.
+                    let mut splits = vec![];
+                    for _ in min..max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(ast, program)?;
+                    }
+                    let out = program.len();
+                    for at in splits {
+                        program[at] = split(at + 1, out);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Text of each group of the first match, None without a match.
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let captures = Regex::new(pattern).unwrap().captures(text)?;
+        Some(
+            captures
+                .iter()
+                .map(|range| range.map(|(begin, end)| text[begin..end].to_string()))
+                .collect(),
+        )
+    }
+
+    fn whole(pattern: &str, text: &str) -> Option<String> {
+        groups(pattern, text).and_then(|groups| groups[0].clone())
+    }
+
+    #[test]
+    fn matches_literals_and_anchors() {
+        assert_eq!(whole("abc", "xxabcxx").unwrap(), "abc");
+        assert_eq!(whole("^abc", "abcx").unwrap(), "abc");
+        assert!(whole("^abc", "xabc").is_none());
+        assert_eq!(whole("abc$", "xabc").unwrap(), "abc");
+        assert!(whole("abc$", "abcx").is_none());
+        assert!(Regex::new("^$").unwrap().is_match(""));
+        assert!(!Regex::new("^$").unwrap().is_match("/"));
+        assert_eq!(whole("a.c", "a/c").unwrap(), "a/c");
+        assert_eq!(whole("a\\.c", "abc a.c").unwrap(), "a.c");
+        assert_eq!(whole("caf.", "café").unwrap(), "café");
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert_eq!(whole("[a-c]+", "xxbcaz").unwrap(), "bca");
+        assert_eq!(whole("[^/]+$", "/a/b/file.html").unwrap(), "file.html");
+        assert_eq!(whole("[-a]+", "x-a-").unwrap(), "-a-");
+        assert_eq!(whole("[a-]+", "xa-a").unwrap(), "a-a");
+        assert_eq!(whole("[]a]+", "x]a]").unwrap(), "]a]");
+        assert_eq!(whole("\\d+", "/blog/2024/x").unwrap(), "2024");
+        assert_eq!(whole("\\D+", "12ab34").unwrap(), "ab");
+        assert_eq!(whole("\\w+", "--a_1--").unwrap(), "a_1");
+        assert_eq!(whole("a\\sb", "a\tb").unwrap(), "a\tb");
+        assert_eq!(whole("[\\d.]+", "v1.25x").unwrap(), "1.25");
+    }
+
+    #[test]
+    fn matches_alternation_and_groups() {
+        assert_eq!(whole("^/(css|js)/", "/js/app.js").unwrap(), "/js/");
+        assert!(whole("^/(css|js)/", "/img/a.png").is_none());
+        assert_eq!(whole("a|ab", "ab").unwrap(), "a");
+        assert_eq!(whole("^(?:a|b)+$", "abba").unwrap(), "abba");
+        assert_eq!(whole("x(|y)z", "xz").unwrap(), "xz");
+    }
+
+    #[test]
+    fn repeats_with_counts() {
+        assert_eq!(whole("a{2}", "aaaa").unwrap(), "aa");
+        assert_eq!(whole("a{2,}", "aaaa").unwrap(), "aaaa");
+        assert_eq!(whole("a{1,3}", "aaaa").unwrap(), "aaa");
+        assert_eq!(whole("a{1,3}?", "aaaa").unwrap(), "a");
+        assert!(whole("^a{2,3}$", "a").is_none());
+        assert!(whole("^a{2,3}$", "aaaa").is_none());
+        assert_eq!(whole("^\\d{4}-\\d{2}$", "2024-05").unwrap(), "2024-05");
+        // Not a count: the braces are matched as they are.
+        assert_eq!(whole("a{x}", "a{x}").unwrap(), "a{x}");
+        assert_eq!(whole("a.*b", "a1b2b").unwrap(), "a1b2b");
+        assert_eq!(whole("a.*?b", "a1b2b").unwrap(), "a1b");
+    }
+
+    #[test]
+    fn captures_groups() {
+        let found = groups("^/blog/(\\d+)/(\\w+)?", "/blog/42/").unwrap();
+        assert_eq!(found, vec![Some("/blog/42/".to_string()), Some("42".to_string()), None]);
+
+        let found = groups("^/((a)|(b))+$", "/ab").unwrap();
+        assert_eq!(found[1].as_deref(), Some("b"));
+        assert_eq!(found[3].as_deref(), Some("b"));
+        assert_eq!(Regex::new("(a)(?:b)(c)").unwrap().get_group_count(), 3);
+
+        // Byte offsets, even after multibyte chars.
+        let captures = Regex::new("(é+)x").unwrap().captures("aééx").unwrap();
+        assert_eq!(captures[1], Some((1, 5)));
+    }
+
+    #[test]
+    fn expands_replacements() {
+        let pattern = Regex::new("^/old/(\\w+)/(.*)$").unwrap();
+        let text = "/old/docs/a.html";
+        let captures = pattern.captures(text).unwrap();
+        assert_eq!(expand("/new/$2?s=$1", text, &captures), "/new/a.html?s=docs");
+        assert_eq!(expand("${1}x $0", text, &captures), "docsx /old/docs/a.html");
+        // Missing groups are empty, `$$` is a dollar and a lone `$` stays.
+        assert_eq!(expand("$9|$$1|$|${x}", text, &captures), "|$1|$|${x}");
+    }
+
+    #[test]
+    fn refuses_bad_patterns() {
+        for pattern in [
+            "(a", "a)", "[a", "[z-a]", "*a", "a{3,1}", "a{1001}", "^*", "(?=a)", "\\q", "a\\",
+            "[\\D]",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn stays_linear_on_nested_repeats() {
+        let pattern = Regex::new("^(a+)+$").unwrap();
+        let text = "a".repeat(5000) + "b";
+        assert!(!pattern.is_match(&text));
+    }
+}
@@ -0,0 +1,377 @@
+/*
+ *	Resolve HTTP paths into native filesystem paths.
+ */
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use server_side::status;
+use server_side::status::HttpStatus;
+use server_side::utils;
+
+// Every file lookup of the server goes through here, so no other module
+// has to know how `home_dir` and request paths map onto the disk.
+#[derive(Debug, Clone)]
+pub struct PathResolver {
+    root_path: PathBuf,
+    home_dir: PathBuf,
+    restrict_symlinks: bool,
+    // forbidden_dir entries, resolved.
+    forbidden_dirs: Vec<PathBuf>,
+    // URL prefix (without trailing `/`) and the directory served for it,
+    // longest prefix first.
+    aliases: Vec<(String, PathBuf)>,
+}
+
+impl PathResolver {
+    pub fn new(root_path: &Path, home_dir: &str) -> PathResolver {
+        PathResolver {
+            root_path: root_path.to_path_buf(),
+            home_dir: utils::to_root_path(home_dir, root_path),
+            restrict_symlinks: true,
+            forbidden_dirs: vec![],
+            aliases: vec![],
+        }
+    }
+
+    // Serve URLs starting with a prefix from its directory (written like
+    // home_dir) instead of home_dir.
+    pub fn set_aliases(&mut self, aliases: &[(String, String)]) {
+        self.aliases = aliases
+            .iter()
+            .map(|alias| {
+                let prefix = alias.0.trim_end_matches('/').to_string();
+                (prefix, self.root_file(&alias.1))
+            })
+            .collect();
+        self.aliases.sort_by_key(|alias| std::cmp::Reverse(alias.0.len()));
+    }
+
+    // forbidden_dir is written like a path from root (`/www/forbidden`),
+    // compare on resolved paths so `/www/forbidden-not` is not caught.
+    pub fn set_forbidden_dirs(&mut self, dirs: &[String]) {
+        self.forbidden_dirs = dirs.iter().map(|dir| self.root_file(dir)).collect();
+    }
+
+    // When set, files reached through a symlink pointing outside `home_dir` are refused.
+    pub fn set_restrict_symlinks(&mut self, restrict: bool) {
+        self.restrict_symlinks = restrict;
+    }
+
+    pub fn get_root_path(&self) -> &Path {
+        &self.root_path
+    }
+
+    pub fn get_home_dir(&self) -> &Path {
+        &self.home_dir
+    }
+
+    // Server files such as `/config/config.conf` or `/error/404.html`.
+    pub fn root_file(&self, p: &str) -> PathBuf {
+        utils::to_root_path(p, &self.root_path)
+    }
+
+    // Request path (without query string) to file under `home_dir`, or
+    // under the directory of an alias.
+    // The path must already be normalized, see `resolve_request`.
+    pub fn resolve(&self, req_path: &str) -> PathBuf {
+        for (prefix, dir) in self.aliases.iter() {
+            if let Some(rest) = req_path.strip_prefix(prefix.as_str()) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return utils::to_root_path(rest, dir);
+                }
+            }
+        }
+        utils::to_root_path(req_path, &self.home_dir)
+    }
+
+    // Normalize a raw request path and map it under `home_dir`.
+    // Returns the normalized http path together with the file path.
+    pub fn resolve_request(&self, raw_path: &str) -> Result<(String, PathBuf), HttpStatus> {
+        let req_path = normalize_path(raw_path)?;
+        let file_path = self.resolve_path(&req_path)?;
+
+        Ok((req_path, file_path))
+    }
+
+    // `resolve` with the symlink check, for a path already normalized.
+    pub fn resolve_path(&self, req_path: &str) -> Result<PathBuf, HttpStatus> {
+        let file_path = self.resolve(req_path);
+        self.check_path(&file_path)?;
+        Ok(file_path)
+    }
+
+    // The file is in a forbidden_dir, or is one.
+    pub fn is_forbidden(&self, file_path: &Path) -> bool {
+        self.forbidden_dirs
+            .iter()
+            .any(|dir| file_path.starts_with(dir))
+    }
+
+    // Refuse a file under `home_dir` reached through a symlink leading out of it.
+    pub fn check_path(&self, file_path: &Path) -> Result<(), HttpStatus> {
+        if self.restrict_symlinks && !self.is_inside_home_dir(file_path) {
+            Err(status::FORBIDDEN)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Writes must land under `home_dir` too: check the directory they go in,
+    // the file itself may not exist yet.
+    pub fn check_write_path(&self, file_path: &Path) -> Result<(), HttpStatus> {
+        match file_path.parent() {
+            Some(parent) if self.restrict_symlinks && !self.is_inside_home_dir(parent) => {
+                Err(status::FORBIDDEN)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Index file of the directory `dir`: the first of `index_files` there,
+    // else a file whose name starts with `alternative_basename`. Those named
+    // `<basename>.<ext>` come first, each group in name order.
+    pub fn find_index(
+        &self,
+        dir: &Path,
+        index_files: &[&str],
+        alternative_basename: &str,
+    ) -> Option<PathBuf> {
+        let is_index = |path: &Path| path.is_file() && self.check_path(path).is_ok();
+
+        if let Some(index) = index_files
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| is_index(path))
+        {
+            return Some(index);
+        }
+
+        if alternative_basename.is_empty() {
+            return None;
+        }
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|child| child.ok())
+            .filter_map(|child| child.file_name().into_string().ok())
+            .filter(|name| name.starts_with(alternative_basename) && !name.starts_with('.'))
+            .collect();
+        names.sort_by_key(|name| (!name[alternative_basename.len()..].starts_with('.'), name.clone()));
+
+        names
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| is_index(path))
+    }
+
+    // Follow symlinks and check the real file is still under `home_dir`, or
+    // under the alias directory it was found in.
+    // Missing files pass, they will end up in a 404 anyway.
+    fn is_inside_home_dir(&self, file_path: &Path) -> bool {
+        let real_file_path = match fs::canonicalize(file_path) {
+            Ok(p) => p,
+            Err(_) => return true,
+        };
+
+        let base_dir = self
+            .aliases
+            .iter()
+            .map(|alias| &alias.1)
+            .find(|dir| file_path.starts_with(dir))
+            .unwrap_or(&self.home_dir);
+        match fs::canonicalize(base_dir) {
+            Ok(real_base_dir) => real_file_path.starts_with(real_base_dir),
+            Err(_) => false,
+        }
+    }
+}
+
+// Percent-decode the path, then collapse `.`, `..` and duplicated slashes.
+// The result always starts with `/` and never climbs above it: a `..` that
+// would leave the web root is a 403, bad escapes and NUL bytes are a 400.
+pub fn normalize_path(raw_path: &str) -> Result<String, HttpStatus> {
+    let decoded = match utils::percent_decode(raw_path) {
+        Some(d) => d,
+        None => return Err(status::BAD_REQUEST),
+    };
+
+    if decoded.contains(&0) {
+        return Err(status::BAD_REQUEST);
+    }
+
+    let decoded = match String::from_utf8(decoded) {
+        Ok(d) => d,
+        Err(_) => return Err(status::BAD_REQUEST),
+    };
+
+    collapse_path(&decoded)
+}
+
+// The second half of `normalize_path`, for a path already decoded.
+pub fn collapse_path(decoded: &str) -> Result<String, HttpStatus> {
+    // `\` is a separator on Windows, never let it through as part of a name.
+    if decoded.contains('\\') {
+        return Err(status::BAD_REQUEST);
+    }
+
+    let mut segments: Vec<&str> = vec![];
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(status::FORBIDDEN);
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let is_dir_path =
+        decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    let mut req_path = "/".to_string() + segments.join("/").as_str();
+    if !segments.is_empty() && is_dir_path {
+        req_path.push('/');
+    }
+
+    Ok(req_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // An empty directory for one test, removed first if a previous run left it.
+    fn make_temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("aden-resolver-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn collapses_dots_and_slashes() {
+        assert_eq!(normalize_path("/").unwrap(), "/");
+        assert_eq!(normalize_path("").unwrap(), "/");
+        assert_eq!(normalize_path("/a/./b//c").unwrap(), "/a/b/c");
+        assert_eq!(normalize_path("/a/b/../c/").unwrap(), "/a/c/");
+        assert_eq!(normalize_path("/a/b/..").unwrap(), "/a/");
+        assert_eq!(normalize_path("/a/.").unwrap(), "/a/");
+        assert_eq!(normalize_path("/a/..").unwrap(), "/");
+    }
+
+    #[test]
+    fn refuses_to_climb_above_root() {
+        assert_eq!(normalize_path("/.."), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/../etc/passwd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/a/../../etc/passwd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/a/b/../../.."), Err(status::FORBIDDEN));
+    }
+
+    #[test]
+    fn decodes_escapes_before_collapsing() {
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/%2E%2E/etc/passwd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/.%2e/etc/passwd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/%2e%2e%2fetc%2fpasswd"), Err(status::FORBIDDEN));
+        assert_eq!(normalize_path("/a/%2e%2e/b").unwrap(), "/b");
+        assert_eq!(normalize_path("/a%20b/c%2Fd").unwrap(), "/a b/c/d");
+        assert_eq!(normalize_path("/caf%C3%A9").unwrap(), "/café");
+    }
+
+    #[test]
+    fn refuses_nul_bytes() {
+        assert_eq!(normalize_path("/index.html%00.png"), Err(status::BAD_REQUEST));
+        assert_eq!(normalize_path("/%00"), Err(status::BAD_REQUEST));
+    }
+
+    #[test]
+    fn refuses_malformed_escapes() {
+        assert_eq!(normalize_path("/%"), Err(status::BAD_REQUEST));
+        assert_eq!(normalize_path("/a%2"), Err(status::BAD_REQUEST));
+        assert_eq!(normalize_path("/%zz"), Err(status::BAD_REQUEST));
+        assert_eq!(normalize_path("/%+1"), Err(status::BAD_REQUEST));
+        // Not UTF-8 once decoded.
+        assert_eq!(normalize_path("/%C3%28"), Err(status::BAD_REQUEST));
+    }
+
+    #[test]
+    fn refuses_backslashes() {
+        assert_eq!(normalize_path("/a\\..\\..\\b"), Err(status::BAD_REQUEST));
+        assert_eq!(normalize_path("/a%5c..%5c..%5cb"), Err(status::BAD_REQUEST));
+    }
+
+    #[test]
+    fn resolves_under_home_dir() {
+        let resolver = PathResolver::new(Path::new("/srv/aden"), "/www");
+        let (href, path) = resolver.resolve_request("/docs/../a%20b.html").unwrap();
+        assert_eq!(href, "/a b.html");
+        assert_eq!(path, Path::new("/srv/aden/www/a b.html"));
+        assert_eq!(resolver.resolve("/"), Path::new("/srv/aden/www"));
+        assert_eq!(resolver.root_file("/error/404.html"), Path::new("/srv/aden/error/404.html"));
+        assert_eq!(resolver.resolve_request("/../config/config.conf"), Err(status::FORBIDDEN));
+    }
+
+    #[test]
+    fn resolves_aliases_on_segment_boundaries() {
+        let mut resolver = PathResolver::new(Path::new("/srv/aden"), "/www");
+        resolver.set_aliases(&[
+            ("/docs/".to_string(), "/share/docs".to_string()),
+            ("/docs/api".to_string(), "/share/api".to_string()),
+        ]);
+        assert_eq!(resolver.resolve("/docs"), Path::new("/srv/aden/share/docs"));
+        assert_eq!(resolver.resolve("/docs/x.html"), Path::new("/srv/aden/share/docs/x.html"));
+        assert_eq!(resolver.resolve("/docs/api/v1"), Path::new("/srv/aden/share/api/v1"));
+        assert_eq!(resolver.resolve("/docsx"), Path::new("/srv/aden/www/docsx"));
+    }
+
+    #[test]
+    fn matches_forbidden_dirs_by_component() {
+        let mut resolver = PathResolver::new(Path::new("/srv/aden"), "/www");
+        resolver.set_forbidden_dirs(&["/www/private".to_string(), "/www/tmp/".to_string()]);
+        assert!(resolver.is_forbidden(&resolver.resolve("/private")));
+        assert!(resolver.is_forbidden(&resolver.resolve("/private/")));
+        assert!(resolver.is_forbidden(&resolver.resolve("/private/a/b.html")));
+        assert!(resolver.is_forbidden(&resolver.resolve("/tmp/x")));
+        assert!(!resolver.is_forbidden(&resolver.resolve("/private-not/a.html")));
+        assert!(!resolver.is_forbidden(&resolver.resolve("/privat")));
+        assert!(!resolver.is_forbidden(&resolver.resolve("/public/private")));
+
+        let (_, path) = resolver.resolve_request("/public/..//private/%2e/a").unwrap();
+        assert!(resolver.is_forbidden(&path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_leading_out_of_home_dir() {
+        use std::os::unix::fs::symlink;
+
+        let root = make_temp_dir("symlinks");
+        fs::create_dir_all(root.join("www/sub")).unwrap();
+        fs::create_dir_all(root.join("secret")).unwrap();
+        fs::write(root.join("secret/key"), "key").unwrap();
+        fs::write(root.join("www/sub/page.html"), "page").unwrap();
+        symlink(root.join("secret"), root.join("www/out")).unwrap();
+        symlink(root.join("secret/key"), root.join("www/key")).unwrap();
+        symlink(root.join("www/sub/page.html"), root.join("www/in.html")).unwrap();
+
+        let mut resolver = PathResolver::new(&root, "/www");
+        assert_eq!(resolver.resolve_request("/out/key"), Err(status::FORBIDDEN));
+        assert_eq!(resolver.resolve_request("/key"), Err(status::FORBIDDEN));
+        assert_eq!(
+            resolver.check_write_path(&resolver.resolve("/out/new")),
+            Err(status::FORBIDDEN)
+        );
+        assert!(resolver.resolve_request("/in.html").is_ok());
+        assert!(resolver.resolve_request("/sub/page.html").is_ok());
+        // Missing files pass, they end up in a 404.
+        assert!(resolver.resolve_request("/missing.html").is_ok());
+        assert!(resolver.check_write_path(&resolver.resolve("/sub/new")).is_ok());
+
+        resolver.set_restrict_symlinks(false);
+        assert!(resolver.resolve_request("/out/key").is_ok());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,10 +1,14 @@
-/**
- *
- */
-
 use std::fs::{File, Metadata, metadata};
-use std::io::{Read, BufReader};
+use std::io::{Read, BufReader, Cursor};
+use std::mem;
 use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use server_side::compress::CompressReader;
+use server_side::deflate::ContentCoding;
+
+// Most bytes taken from a stream of unknown size at once.
+const STREAM_PIECE_SIZE: usize = 64 * 1024;
 
 pub struct Response {
 	http_ver: String,
@@ -13,12 +17,22 @@ pub struct Response {
 	header: Vec<HeaderData>,
 	content: Vec<u8>,
 	content_length: u64,
+	// False for streams whose size is only known at their end.
+	length_known: bool,
 
-	file_path: String,
-	bufreader: Option<BufReader<File>>,
+	file_path: PathBuf,
+	reader: Option<Box<dyn Read + Send>>,
 	read_buffer_size: u64,	// file > 5MiB (5000, customized in /config) will be treated as a big file ;)
 	bytes_read: u64,
-	is_ready: bool
+	is_ready: bool,
+
+	// Send streams of unknown size with `Transfer-Encoding: chunked`.
+	chunked: bool,
+	trailer: Vec<HeaderData>,
+	// HEAD: headers describe the body but it is not sent.
+	header_only: bool,
+	finished: bool,
+	failed: bool
 }
 
 pub struct HeaderData {
@@ -35,11 +49,17 @@ impl Response {
 			header: vec![],
 			content: vec![],
 			content_length: 0,
-			file_path: "".to_string(),
-			bufreader: None,
+			length_known: true,
+			file_path: PathBuf::new(),
+			reader: None,
 			read_buffer_size: 0,
 			bytes_read: 0,
-			is_ready: false
+			is_ready: false,
+			chunked: true,
+			trailer: vec![],
+			header_only: false,
+			finished: false,
+			failed: false
 		}
 	}
 
@@ -83,50 +103,97 @@ impl Response {
 		}
 	}
 
-	pub fn add_content(&mut self, filepath: String) -> Result<(), Error> {
+	pub fn add_content(&mut self, filepath: &Path) -> Result<(), Error> {
 		self.add_content_from_file(filepath)
 	}
 
 	// Enter file path!
 	pub fn add_content_from_string(&mut self, content: String) {
-		if !content.is_empty() {
-			self.content_length = content.len() as u64;
-			self.content = content.as_bytes().to_vec();
-		}
+		self.reader = None;
+		self.length_known = true;
+		self.content_length = content.len() as u64;
+		self.content = content.into_bytes();
 	}
 
-	pub fn add_content_from_file(&mut self, filepath: String) -> Result<(), Error> {
-		let fp: File = File::open(filepath.to_owned())?;
-		let mut fp_bufreader = BufReader::new(fp);
-		let file_size = metadata(&filepath).unwrap().len();
+	pub fn add_content_from_file(&mut self, filepath: &Path) -> Result<(), Error> {
+		let fp: File = File::open(filepath)?;
+		let file_size = fp.metadata()?.len();
 
-		self.bufreader = Some(fp_bufreader);
-		self.file_path = filepath;
-		self.content_length = file_size;
+		self.add_content_from_reader(Box::new(BufReader::new(fp)), Some(file_size));
+		self.file_path = filepath.to_path_buf();
 		Ok(())
 	}
 
-	pub fn check_ready(&mut self) -> bool {
-		if !self.is_ready {
-			if !self.http_ver.is_empty() && self.status_code != 0
-				&& !self.status_msg.is_empty() && self.header.len() > 0
-				&& self.content_length > 0 {
-					self.is_ready = true;
+	// Body read from any source. Without `length` it is streamed until the
+	// reader ends, in chunks for HTTP/1.1 clients.
+	pub fn add_content_from_reader(&mut self, reader: Box<dyn Read + Send>, length: Option<u64>) {
+		self.reader = Some(reader);
+		self.content = vec![];
+		self.length_known = length.is_some();
+		self.content_length = length.unwrap_or(0);
+	}
+
+	// Compress the body as it is sent, its length is then only known at the end.
+	pub fn set_content_encoding(&mut self, coding: ContentCoding) {
+		let body: Box<dyn Read + Send> = match self.reader.take() {
+			Some(reader) => reader,
+			None => Box::new(Cursor::new(mem::take(&mut self.content))),
+		};
+
+		self.add_content_from_reader(Box::new(CompressReader::new(body, coding)), None);
+		self.add_header("Content-Encoding", coding.get_name());
+	}
+
+	// Turn off chunked encoding, e.g. for HTTP/1.0 clients: a stream of
+	// unknown size then ends by closing the connection.
+	pub fn set_chunked(&mut self, chunked: bool) {
+		self.chunked = chunked;
+	}
+
+	// Trailer fields go after the last chunk, only chunked bodies have them.
+	pub fn add_trailer(&mut self, k: &str, v: &str) {
+		for hd in self.trailer.iter_mut() {
+			if hd.key.to_lowercase() == k.to_lowercase() {
+				hd.value = v.to_string();
+				return;
 			}
 		}
 
-		// println!("self.http_ver.is_empty(): {:?}", self.http_ver.is_empty());
-		// println!("self.status_code: {}", self.status_code);
-		// println!("self.status_msg.is_empty(): {:?}", self.status_msg.is_empty());
-		// println!("self.header.len(): {}", self.header.len());
-		// println!("self.content_length: {}", self.content_length);
+		self.trailer.push(HeaderData{ key: k.to_string(), value: v.to_string() });
+	}
+
+	pub fn set_header_only(&mut self, header_only: bool) {
+		self.header_only = header_only;
+	}
+
+	// 1xx, 204 and 304 never have a body.
+	pub fn has_body(&self) -> bool {
+		!(self.status_code >= 100 && self.status_code < 200)
+			&& self.status_code != 204 && self.status_code != 304
+	}
+
+	fn is_chunked(&self) -> bool {
+		self.has_body() && !self.length_known && self.chunked
+	}
+
+	// The connection can't carry another response after this one: the body
+	// has no length and no chunks, or reading it failed midway.
+	pub fn must_close(&self) -> bool {
+		self.failed || (self.has_body() && !self.header_only && !self.length_known && !self.chunked)
+	}
+
+	pub fn check_ready(&mut self) -> bool {
+		if !self.is_ready && !self.http_ver.is_empty() && self.status_code != 0
+			&& !self.status_msg.is_empty() && !self.header.is_empty() {
+				self.is_ready = true;
+		}
 
 		self.is_ready
 	}
 
 	pub fn build_header(&mut self) -> Result<String, Error> {
 		if !self.check_ready() {
-			return Err(Error::new(ErrorKind::Other, "response is incomplete."));
+			return Err(Error::other("response is incomplete."));
 		}
 
 		let mut res: String;
@@ -136,47 +203,131 @@ impl Response {
 			res += format!("{}: {}\r\n", hd.key, hd.value).as_str();
 		}
 
-		res += "Content-Length: ";
-		res += self.content_length.to_string().as_str();
-		res += "\r\n\r\n";
+		if self.is_chunked() {
+			res += "Transfer-Encoding: chunked\r\n";
+			if !self.trailer.is_empty() {
+				let names: Vec<&str> = self.trailer.iter().map(|hd| hd.key.as_str()).collect();
+				res += format!("Trailer: {}\r\n", names.join(", ")).as_str();
+			}
+		}
+		else if self.has_body() && self.length_known {
+			res += "Content-Length: ";
+			res += self.content_length.to_string().as_str();
+			res += "\r\n";
+		}
 
+		res += "\r\n";
 		Ok(res)
 	}
 
+	// Smaller pieces for engines serving many clients from one thread.
+	pub fn set_read_buffer_size(&mut self, size: u64) {
+		self.read_buffer_size = size;
+	}
+
 	// Return: contents + size of the rest of contents.
+	// Streams of unknown size report 1 until they end, then 0.
 	pub fn build_content(&mut self) -> (&[u8], u64) {
-		// Destroy old contents, prepare for new contents.
-		// self.content = vec![];
-		
-		// Buffer size must be over 5MiB to read less times.
-		if self.read_buffer_size < 1_000_000 {
-			// self.read_buffer_size = 2_000_000;
+		if self.finished || self.header_only || !self.has_body() {
+			self.finished = true;
+			return (&[], 0);
+		}
+
+		// Contents from string are already in memory.
+		if self.reader.is_none() {
+			self.finished = true;
+			self.bytes_read = self.content_length;
+			return (self.content.as_slice(), 0);
+		}
+
+		// Buffer size is 5MiB by default to read less times.
+		if self.read_buffer_size == 0 {
 			self.read_buffer_size = 5_000_000;
 		}
 
-		let mut u8_buff: Vec<u8>;
-		let mut numbytes: u64;
-		if self.content_length - self.bytes_read < self.read_buffer_size {
-			numbytes = self.content_length - self.bytes_read;
-			u8_buff = vec![0; numbytes as usize];
+		if self.length_known {
+			self.build_sized_content()
 		}
 		else {
-			numbytes = self.read_buffer_size;
-			u8_buff = vec![0;  numbytes as usize];
+			self.build_stream_content()
+		}
+	}
+
+	fn build_sized_content(&mut self) -> (&[u8], u64) {
+		let numbytes = (self.content_length - self.bytes_read).min(self.read_buffer_size);
+		let mut u8_buff: Vec<u8> = vec![0; numbytes as usize];
+
+		let read_ok = match self.reader {
+			Some(ref mut reader) => reader.read_exact(&mut u8_buff[..]).is_ok(),
+			None => false,
+		};
+
+		// The file changed under us, the body can't be completed.
+		if !read_ok {
+			self.failed = true;
+			self.finished = true;
+			return (&[], 0);
 		}
 
-		if let Some(ref mut br) = self.bufreader {
-			if let Ok(_) = br.read_exact(&mut u8_buff[..]) {
-				self.bytes_read += numbytes as u64;
+		self.bytes_read += numbytes;
+		self.content = u8_buff;
+
+		let remaining_bytes = self.content_length - self.bytes_read;
+		self.finished = remaining_bytes == 0;
+		(self.content.as_slice(), remaining_bytes)
+	}
+
+	fn build_stream_content(&mut self) -> (&[u8], u64) {
+		let piece_size = (self.read_buffer_size as usize).min(STREAM_PIECE_SIZE);
+		let mut u8_buff: Vec<u8> = vec![0; piece_size];
+
+		let numbytes = loop {
+			let read_result = match self.reader {
+				Some(ref mut reader) => reader.read(&mut u8_buff[..]),
+				None => Ok(0),
+			};
+
+			match read_result {
+				Ok(n) => break n,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+				Err(_) => {
+					// No last chunk: the client must see the body as broken.
+					self.failed = true;
+					self.finished = true;
+					return (&[], 0);
+				}
 			}
+		};
+
+		u8_buff.truncate(numbytes);
+		self.bytes_read += numbytes as u64;
+
+		if numbytes == 0 {
+			self.finished = true;
+			self.content = if self.chunked { self.build_last_chunk() } else { vec![] };
+			return (self.content.as_slice(), 0);
 		}
 
-		// println!("bytes read: {}", self.bytes_read);
+		self.content = if self.chunked {
+			let mut chunk = format!("{:x}\r\n", numbytes).into_bytes();
+			chunk.extend_from_slice(&u8_buff);
+			chunk.extend_from_slice(b"\r\n");
+			chunk
+		}
+		else {
+			u8_buff
+		};
 
-		self.content = u8_buff;
+		(self.content.as_slice(), 1)
+	}
 
-		// Ok((self.content.as_slice(), self.content_length - self.bytes_read))
-		(self.content.as_slice(), self.content_length - self.bytes_read)
+	fn build_last_chunk(&self) -> Vec<u8> {
+		let mut last_chunk = "0\r\n".to_string();
+		for hd in self.trailer.iter() {
+			last_chunk += format!("{}: {}\r\n", hd.key, hd.value).as_str();
+		}
+		last_chunk += "\r\n";
+		last_chunk.into_bytes()
 	}
 
 	pub fn get_status_code(&self) -> u16 {
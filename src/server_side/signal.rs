@@ -0,0 +1,49 @@
+/*
+ *	Unix signals, noted by the handler and picked up later by whoever polls
+ *	for them: nothing else is safe to do inside a handler.
+ */
+use std::io;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const SIGHUP: c_int = 1;
+pub const SIGINT: c_int = 2;
+pub const SIGTERM: c_int = 15;
+
+// Signals received and not taken yet, by number.
+static PENDING: [AtomicUsize; 32] = [const { AtomicUsize::new(0) }; 32];
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signum: c_int) {
+    if let Some(pending) = PENDING.get(signum as usize) {
+        pending.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Note `signum` instead of its default action.
+#[cfg(unix)]
+pub fn listen(signum: c_int) -> io::Result<()> {
+    // SIG_ERR is -1.
+    if unsafe { signal(signum, on_signal) } == usize::MAX {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn listen(signum: c_int) -> io::Result<()> {
+    Err(io::Error::other("signals are not supported here"))
+}
+
+// Whether `signum` came since the last call.
+pub fn take(signum: c_int) -> bool {
+    match PENDING.get(signum as usize) {
+        Some(pending) => pending.swap(0, Ordering::SeqCst) > 0,
+        None => false,
+    }
+}
@@ -1,17 +1,58 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct HttpStatus(u16, &'static str);
 
 impl HttpStatus {
-    pub fn to_string(&self) -> String {
-        format!("{}: {}", self.0, self.1)
+    pub fn get_code(&self) -> u16 {
+        self.0
+    }
+
+    pub fn get_message(&self) -> &'static str {
+        self.1
+    }
+}
+
+impl fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
     }
 }
 
-/// 	Define some status code
+/// Define some status code
 pub const REQUEST_OK: HttpStatus = HttpStatus(200, "OK");
+pub const CREATED: HttpStatus = HttpStatus(201, "Created");
+pub const NO_CONTENT: HttpStatus = HttpStatus(204, "No Content");
+pub const PARTIAL_CONTENT: HttpStatus = HttpStatus(206, "Partial Content");
+pub const MULTI_STATUS: HttpStatus = HttpStatus(207, "Multi-Status");
+pub const MOVED_PERMANENTLY: HttpStatus = HttpStatus(301, "Moved Permanently");
+pub const FOUND: HttpStatus = HttpStatus(302, "Found");
+pub const NOT_MODIFIED: HttpStatus = HttpStatus(304, "Not Modified");
+pub const TEMPORARY_REDIRECT: HttpStatus = HttpStatus(307, "Temporary Redirect");
+pub const PERMANENT_REDIRECT: HttpStatus = HttpStatus(308, "Permanent Redirect");
 
 pub const BAD_REQUEST: HttpStatus = HttpStatus(400, "Bad Request");
+pub const UNAUTHORIZED: HttpStatus = HttpStatus(401, "Unauthorized");
 pub const FORBIDDEN: HttpStatus = HttpStatus(403, "Forbidden");
 pub const NOT_FOUND: HttpStatus = HttpStatus(404, "Not Found");
+pub const METHOD_NOT_ALLOWED: HttpStatus = HttpStatus(405, "Method Not Allowed");
+pub const NOT_ACCEPTABLE: HttpStatus = HttpStatus(406, "Not Acceptable");
+pub const REQUEST_TIMEOUT: HttpStatus = HttpStatus(408, "Request Timeout");
+pub const CONFLICT: HttpStatus = HttpStatus(409, "Conflict");
+pub const PRECONDITION_FAILED: HttpStatus = HttpStatus(412, "Precondition Failed");
+pub const PAYLOAD_TOO_LARGE: HttpStatus = HttpStatus(413, "Payload Too Large");
+pub const URI_TOO_LONG: HttpStatus = HttpStatus(414, "URI Too Long");
+pub const UNSUPPORTED_MEDIA_TYPE: HttpStatus = HttpStatus(415, "Unsupported Media Type");
+pub const RANGE_NOT_SATISFIABLE: HttpStatus = HttpStatus(416, "Range Not Satisfiable");
+pub const EXPECTATION_FAILED: HttpStatus = HttpStatus(417, "Expectation Failed");
+pub const MISDIRECTED_REQUEST: HttpStatus = HttpStatus(421, "Misdirected Request");
+pub const LOCKED: HttpStatus = HttpStatus(423, "Locked");
+pub const FAILED_DEPENDENCY: HttpStatus = HttpStatus(424, "Failed Dependency");
+pub const REQUEST_HEADER_FIELDS_TOO_LARGE: HttpStatus =
+    HttpStatus(431, "Request Header Fields Too Large");
 
 pub const INTERNAL_SERVER_ERROR: HttpStatus = HttpStatus(500, "Internal Server Error");
+pub const NOT_IMPLEMENTED: HttpStatus = HttpStatus(501, "Not Implemented");
+pub const BAD_GATEWAY: HttpStatus = HttpStatus(502, "Bad Gateway");
+pub const SERVICE_UNAVAILABLE: HttpStatus = HttpStatus(503, "Service Unavailable");
+pub const HTTP_VERSION_NOT_SUPPORTED: HttpStatus = HttpStatus(505, "HTTP Version Not Supported");
@@ -0,0 +1,103 @@
+/*
+ *	Graceful stop: no new connections, idle keep-alive connections closed,
+ *	and the requests being answered given the drain timeout to finish.
+ */
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server_side::log;
+use server_side::monitor::Monitor;
+
+// How often the drain looks at the open connections.
+const DRAIN_TICK: Duration = Duration::from_millis(50);
+
+#[cfg(unix)]
+extern "C" {
+    fn shutdown(fd: std::os::raw::c_int, how: std::os::raw::c_int) -> std::os::raw::c_int;
+}
+
+#[derive(Debug)]
+pub struct Stopper {
+    // The server's listening socket.
+    listener: TcpListener,
+    monitor: Arc<Monitor>,
+}
+
+// How a drain ended.
+#[derive(Debug, PartialEq)]
+pub enum Drained {
+    // Every connection closed in time.
+    Done,
+    // The timeout passed, the rest was closed.
+    TimedOut(usize),
+    // `interrupted` said to stop waiting.
+    Interrupted(usize),
+}
+
+impl Stopper {
+    pub fn new(listener: TcpListener, monitor: Arc<Monitor>) -> Stopper {
+        Stopper { listener, monitor }
+    }
+
+    // Stop accepting and wait up to `timeout` for the open connections,
+    // `interrupted` is asked on each tick.
+    pub fn drain(&self, timeout: Duration, interrupted: &dyn Fn() -> bool) -> Drained {
+        self.monitor.set_stopping();
+        if let Err(e) = self.close_listener() {
+            log::warn(format_args!(
+                "Can't close the listening socket because: {}",
+                e
+            ));
+        }
+
+        let answering = self
+            .monitor
+            .get_connections()
+            .iter()
+            .filter(|conn| conn.path.is_some() || conn.pending)
+            .count();
+        if answering > 0 {
+            log::info(format_args!(
+                "Waiting up to {} ms for {} request(s) being answered.",
+                timeout.as_millis(),
+                answering
+            ));
+        }
+
+        let started = Instant::now();
+        loop {
+            // Connections become idle as their last response is sent.
+            self.monitor.close_idle();
+            if self.monitor.get_connections().is_empty() {
+                return Drained::Done;
+            }
+            if interrupted() {
+                return Drained::Interrupted(self.monitor.close_all());
+            }
+            if started.elapsed() >= timeout {
+                return Drained::TimedOut(self.monitor.close_all());
+            }
+            thread::sleep(DRAIN_TICK);
+        }
+    }
+
+    // Wake up and fail a blocked `accept`.
+    #[cfg(unix)]
+    fn close_listener(&self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        // SHUT_RDWR
+        if unsafe { shutdown(self.listener.as_raw_fd(), 2) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn close_listener(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,407 @@
+/*
+ *	Publishing files with PUT, DELETE and MKCOL, in the directories of
+ *	`upload_dir` only and for clients logged in as one of `upload_users`.
+ */
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use client_side::request::Request;
+use server_side::conditional::Validators;
+use server_side::config::SiteConf;
+use server_side::log;
+use server_side::resolver::PathResolver;
+use server_side::status;
+use server_side::status::HttpStatus;
+use server_side::utils;
+
+pub const UPLOAD_METHODS: [&str; 3] = ["PUT", "DELETE", "MKCOL"];
+
+// Tells apart temporary files of uploads running at the same time.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+pub struct Uploads {
+    dirs: Vec<PathBuf>,
+    // user, password
+    users: Vec<(String, String)>,
+}
+
+impl Uploads {
+    pub fn from_config(config: &SiteConf, resolver: &PathResolver) -> Uploads {
+        Uploads {
+            dirs: config
+                .upload_dirs
+                .iter()
+                .map(|dir| resolver.root_file(dir))
+                .collect(),
+            users: config
+                .upload_users
+                .iter()
+                .filter_map(|user| {
+                    let mut parts = user.splitn(2, ':');
+                    match (parts.next(), parts.next()) {
+                        (Some(name), Some(password)) if !name.is_empty() => {
+                            Some((name.to_string(), password.to_string()))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.users.is_empty() && !self.dirs.is_empty()
+    }
+
+    // Writes are possible at `path`: it is inside an upload dir and someone
+    // can log in to do them.
+    pub fn is_enabled_for(&self, path: &Path) -> bool {
+        !self.users.is_empty() && self.dirs.iter().any(|dir| path.starts_with(dir))
+    }
+
+    // One of the upload dirs themselves, they can't be deleted or moved.
+    pub fn is_upload_root(&self, path: &Path) -> bool {
+        self.dirs.iter().any(|dir| dir == path)
+    }
+
+    // Check `Authorization: Basic` against upload_users.
+    pub fn authorize(&self, req: &Request) -> bool {
+        let authorization = match req.get_header("Authorization".to_string()) {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+
+        let mut parts = authorization.trim().splitn(2, ' ');
+        let scheme = parts.next().unwrap_or("");
+        let credentials = parts.next().unwrap_or("").trim();
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+
+        let decoded = match utils::base64_decode(credentials) {
+            Some(decoded) => decoded,
+            None => return false,
+        };
+        let decoded = String::from_utf8_lossy(&decoded);
+        let mut parts = decoded.splitn(2, ':');
+        let (name, password) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+        // Look at every user, so the time taken tells nothing.
+        self.users.iter().fold(false, |found, user| {
+            let name_ok = constant_time_eq(user.0.as_bytes(), name.as_bytes());
+            let password_ok = constant_time_eq(user.1.as_bytes(), password.as_bytes());
+            found | (name_ok & password_ok)
+        })
+    }
+
+    // PUT: write the body to a temporary file next to `path`, then rename it
+    // over `path` so readers never see half a file.
+    pub fn put(&self, req: &Request, path: &Path) -> Result<HttpStatus, HttpStatus> {
+        let existing = fs::metadata(path).ok();
+        match existing {
+            Some(ref meta) if meta.is_dir() => return Err(status::METHOD_NOT_ALLOWED),
+            Some(ref meta) => {
+                if let Some(status) = Validators::from_metadata(meta, None).evaluate(req) {
+                    return Err(status::PRECONDITION_FAILED);
+                }
+            }
+            None if req.get_header("If-Match".to_string()).is_ok() => {
+                return Err(status::PRECONDITION_FAILED);
+            }
+            None => {}
+        }
+
+        let (parent, file_name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name),
+            _ => return Err(status::CONFLICT),
+        };
+        if !parent.is_dir() {
+            return Err(status::CONFLICT);
+        }
+
+        let temp_path = parent.join(format!(
+            ".{}.{}-{}.tmp",
+            file_name.to_string_lossy(),
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .and_then(|mut temp_file| {
+                temp_file.write_all(&req.content)?;
+                temp_file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, path));
+
+        match written {
+            Ok(_) if existing.is_some() => Ok(status::NO_CONTENT),
+            Ok(_) => Ok(status::CREATED),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                log::error(format_args!(
+                    "Can't write `{}` because: {}",
+                    path.display(),
+                    e
+                ));
+                Err(status::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    // DELETE: a file, or a directory with everything in it.
+    // The upload dirs themselves stay.
+    pub fn delete(&self, path: &Path) -> Result<HttpStatus, HttpStatus> {
+        if self.is_upload_root(path) {
+            return Err(status::FORBIDDEN);
+        }
+
+        let meta = fs::symlink_metadata(path).map_err(|_| status::NOT_FOUND)?;
+        let removed = if meta.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match removed {
+            Ok(_) => Ok(status::NO_CONTENT),
+            Err(e) => {
+                log::error(format_args!(
+                    "Can't delete `{}` because: {}",
+                    path.display(),
+                    e
+                ));
+                Err(status::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    // MKCOL: create one directory, its parent must exist.
+    pub fn mkcol(&self, req: &Request, path: &Path) -> Result<HttpStatus, HttpStatus> {
+        if !req.content.is_empty() {
+            return Err(status::UNSUPPORTED_MEDIA_TYPE);
+        }
+        if fs::symlink_metadata(path).is_ok() {
+            return Err(status::METHOD_NOT_ALLOWED);
+        }
+
+        match path.parent() {
+            Some(parent) if parent.is_dir() => {}
+            _ => return Err(status::CONFLICT),
+        }
+
+        match fs::create_dir(path) {
+            Ok(_) => Ok(status::CREATED),
+            Err(e) => {
+                log::error(format_args!(
+                    "Can't create `{}` because: {}",
+                    path.display(),
+                    e
+                ));
+                Err(status::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client_side::request::RequestParser;
+    use server_side::config::Configuration;
+    use std::env;
+    use std::fs::File;
+    use std::io::Read;
+
+    fn make_temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("aden-upload-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // `/www/up` of `root` open to `ann:secret` and `carl:p:a:ss`.
+    fn get_uploads(root: &Path) -> (Uploads, PathResolver) {
+        let mut site = Configuration::builtin(&[]).unwrap().site;
+        site.home_dir = "/www".to_string();
+        site.upload_dirs = vec!["/www/up".to_string()];
+        site.upload_users = vec!["ann:secret".to_string(), "carl:p:a:ss".to_string()];
+        let resolver = PathResolver::new(root, &site.home_dir);
+        fs::create_dir_all(resolver.root_file("/www/up")).unwrap();
+        (Uploads::from_config(&site, &resolver), resolver)
+    }
+
+    fn get_request(raw: &str) -> Request {
+        RequestParser::new()
+            .parse(&mut raw.as_bytes().to_vec())
+            .unwrap()
+            .unwrap()
+    }
+
+    fn put_request(body: &str, fields: &str) -> Request {
+        get_request(&format!(
+            "PUT /up/a.txt HTTP/1.1\r\nHost: x\r\n{}Content-Length: {}\r\n\r\n{}",
+            fields,
+            body.len(),
+            body
+        ))
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn put_creates_then_replaces_by_renaming() {
+        let root = make_temp_dir("put");
+        let (uploads, _) = get_uploads(&root);
+        let dir = root.join("www").join("up");
+        let path = dir.join("a.txt");
+
+        assert_eq!(
+            uploads.put(&put_request("first", ""), &path),
+            Ok(status::CREATED)
+        );
+        assert_eq!(read(&path), "first");
+
+        // A reader of the old file keeps it whole: the new one is a new file
+        // renamed over it, not the old one rewritten.
+        let mut reader = File::open(&path).unwrap();
+        assert_eq!(
+            uploads.put(&put_request("second!", ""), &path),
+            Ok(status::NO_CONTENT)
+        );
+        assert_eq!(read(&path), "second!");
+        let mut old = String::new();
+        reader.read_to_string(&mut old).unwrap();
+        assert_eq!(old, "first");
+
+        // No temporary file is left.
+        let names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["a.txt"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn put_refuses_what_it_cant_write() {
+        let root = make_temp_dir("put-refused");
+        let (uploads, _) = get_uploads(&root);
+        let dir = root.join("www").join("up");
+        fs::create_dir(dir.join("sub")).unwrap();
+
+        assert_eq!(
+            uploads.put(&put_request("x", ""), &dir.join("sub")),
+            Err(status::METHOD_NOT_ALLOWED)
+        );
+        assert_eq!(
+            uploads.put(&put_request("x", ""), &dir.join("missing").join("a.txt")),
+            Err(status::CONFLICT)
+        );
+        assert!(!dir.join("missing").exists());
+
+        // Preconditions on the file as it is.
+        let path = dir.join("a.txt");
+        let req = put_request("x", "If-Match: \"any\"\r\n");
+        assert_eq!(uploads.put(&req, &path), Err(status::PRECONDITION_FAILED));
+        assert_eq!(
+            uploads.put(&put_request("x", "If-None-Match: *\r\n"), &path),
+            Ok(status::CREATED)
+        );
+        let req = put_request("y", "If-None-Match: *\r\n");
+        assert_eq!(uploads.put(&req, &path), Err(status::PRECONDITION_FAILED));
+        assert_eq!(read(&path), "x");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn checks_basic_auth() {
+        let root = make_temp_dir("auth");
+        let (uploads, _) = get_uploads(&root);
+        let authorize = |authorization: &str| {
+            let fields = format!("Authorization: {}\r\n", authorization);
+            uploads.authorize(&put_request("", &fields))
+        };
+
+        assert!(authorize("Basic YW5uOnNlY3JldA=="));
+        assert!(authorize("basic  YW5uOnNlY3JldA== "));
+        // The password may hold `:`.
+        assert!(authorize("Basic Y2FybDpwOmE6c3M="));
+
+        // ann:wrong, bob:secret, ann:secret2, ann, ann:
+        for credentials in [
+            "YW5uOndyb25n",
+            "Ym9iOnNlY3JldA==",
+            "YW5uOnNlY3JldDI=",
+            "YW5u",
+            "YW5uOg==",
+        ]
+        .iter()
+        {
+            assert!(
+                !authorize(&format!("Basic {}", credentials)),
+                "{}",
+                credentials
+            );
+        }
+        assert!(!authorize("Bearer YW5uOnNlY3JldA=="));
+        assert!(!authorize("Basic !!!"));
+        assert!(!authorize("Basic"));
+        assert!(!uploads.authorize(&put_request("", "")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn writes_only_inside_the_upload_dirs() {
+        let root = make_temp_dir("inside");
+        let (uploads, resolver) = get_uploads(&root);
+        fs::create_dir_all(root.join("www").join("upload")).unwrap();
+        let is_enabled_for = |req_path: &str| {
+            let (_, path) = resolver.resolve_request(req_path).unwrap();
+            uploads.is_enabled_for(&path)
+        };
+
+        assert!(is_enabled_for("/up"));
+        assert!(is_enabled_for("/up/a.txt"));
+        assert!(is_enabled_for("/up/sub/a.txt"));
+        assert!(!is_enabled_for("/a.txt"));
+        // A sibling sharing the name's start.
+        assert!(!is_enabled_for("/upload/a.txt"));
+        assert!(!is_enabled_for("/up/../a.txt"));
+        assert!(!is_enabled_for("/up/%2e%2e/a.txt"));
+
+        // The upload dir itself stays.
+        let (_, up) = resolver.resolve_request("/up/").unwrap();
+        assert!(uploads.is_upload_root(&up));
+        assert_eq!(uploads.delete(&up), Err(status::FORBIDDEN));
+        assert!(up.is_dir());
+
+        // Nobody can log in: nothing is writable.
+        let closed = Uploads {
+            dirs: uploads.dirs.clone(),
+            users: vec![],
+        };
+        assert!(!closed.is_enabled() && !closed.is_enabled_for(&up));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
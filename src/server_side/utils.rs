@@ -1,9 +1,10 @@
-/**
+/*
  *	Utility funtions for server_side modules.
  */
-use std::io::{Error, ErrorKind};
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 // use server_side::config::Configuration;
 
 #[derive(Debug)]
@@ -62,21 +63,315 @@ impl Timer {
 }
 
 pub fn result_err(msg: &str) -> Result<(), Error> {
-    Err(Error::new(ErrorKind::Other, msg))
+    Err(Error::other(msg))
 }
 
 // Use 2 func to release
-pub fn get_root_path() -> String {
-    // Now it returns current working directory
-    std::env::current_dir()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string()
-}
-
-// http path to windows path
-pub fn to_root_path(p: &str, root_path: &String) -> String {
-    let windows_path = p.replace("/", r"\");
-    root_path.to_string() + windows_path.as_str()
+// Set by `--root` or `aden serve <dir>`, the current directory otherwise.
+static ROOT_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn get_root_path() -> PathBuf {
+    let root_path = ROOT_PATH.read().unwrap_or_else(|e| e.into_inner());
+    match *root_path {
+        Some(ref path) => path.clone(),
+        None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+    }
+}
+
+pub fn set_root_path(path: &Path) {
+    *ROOT_PATH.write().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+}
+
+// http path (`/config/config.conf`) to native path under `root_path`.
+// Each `/`-separated segment is pushed as its own component, so the result is
+// valid on every platform and a leading `/` never replaces `root_path`.
+pub fn to_root_path(p: &str, root_path: &Path) -> PathBuf {
+    let mut path = root_path.to_path_buf();
+    for component in p.split('/').filter(|c| !c.is_empty()) {
+        path.push(component);
+    }
+    path
+}
+
+// Decode `%XX` escapes of a request path. Returns None on a malformed escape.
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            // from_str_radix would take a sign, `%+1`.
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+// Value of `key` in a query string (`a=1&b=2`), `+` and escapes decoded.
+pub fn get_query_value(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        if parts.next()? != key {
+            return None;
+        }
+        let value = parts.next().unwrap_or("").replace('+', " ");
+        percent_decode(&value).map(|value| String::from_utf8_lossy(&value).into_owned())
+    })
+}
+
+// Decode standard base64 (with `=` padding), as in `Authorization: Basic`.
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let value_of = |c: u8| -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    };
+
+    let bytes = s.trim_end_matches('=').as_bytes();
+    if !s.len().is_multiple_of(4) || s.len() - bytes.len() > 2 {
+        return None;
+    }
+
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len() * 3 / 4);
+    for group in bytes.chunks(4) {
+        if group.len() < 2 {
+            return None;
+        }
+        let mut bits: u32 = 0;
+        for c in group {
+            bits = bits << 6 | value_of(*c)?;
+        }
+        bits <<= 6 * (4 - group.len() as u32);
+        let bytes_out = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        decoded.extend_from_slice(&bytes_out[..group.len() - 1]);
+    }
+    Some(decoded)
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// `SystemTime` to an IMF-fixdate: `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs(),
+        Err(_) => 0,
+    };
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[((days + 4) % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+// `SystemTime` to RFC 3339 in UTC: `1994-11-06T08:49:37Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs(),
+        Err(_) => 0,
+    };
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+// Percent-encode a path for use in a URL, `/` stays as is.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// Parse the three date formats HTTP/1.1 accepts: IMF-fixdate,
+// RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime (`Sun Nov  6 08:49:37 1994`).
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = s.split([' ', ',', '-']).filter(|t| !t.is_empty()).collect();
+    if tokens.len() < 5 {
+        return None;
+    }
+
+    let month_of = |name: &str| MONTHS.iter().position(|m| *m == name).map(|i| i as u32 + 1);
+    let (day, month, year, time) = match month_of(tokens[1]) {
+        // asctime: weekday month day time year
+        Some(month) if tokens.len() == 5 => (tokens[2], month, tokens[4], tokens[3]),
+        Some(_) => return None,
+        None if tokens.len() == 6 && tokens[5] == "GMT" => {
+            (tokens[1], month_of(tokens[2])?, tokens[3], tokens[4])
+        }
+        None => return None,
+    };
+
+    let day: u32 = day.parse().ok()?;
+    let mut year: i64 = year.parse().ok()?;
+    if year < 100 {
+        // RFC 850 two-digit years.
+        year += if year < 70 { 2000 } else { 1900 };
+    }
+
+    let hms: Vec<u64> = time
+        .split(':')
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if hms.len() != 3 || hms[0] > 23 || hms[1] > 59 || hms[2] > 60 {
+        return None;
+    }
+    // Four-digit years only: anything else would overflow the arithmetic.
+    if !(1970..=9999).contains(&year) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let secs = days
+        .checked_mul(86400)?
+        .checked_add(hms[0] * 3600 + hms[1] * 60 + hms[2])?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 to (year, month, day), proleptic Gregorian.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_root_path_pushes_each_segment() {
+        let root = Path::new("/srv/aden");
+        assert_eq!(
+            to_root_path("/config/config.conf", root),
+            Path::new("/srv/aden/config/config.conf")
+        );
+        assert_eq!(to_root_path("www//a/", root), Path::new("/srv/aden/www/a"));
+        assert_eq!(to_root_path("/", root), root);
+        assert_eq!(to_root_path("", root), root);
+    }
+
+    #[test]
+    fn to_root_path_never_replaces_root() {
+        let root = Path::new("/srv/aden");
+        assert!(to_root_path("/etc/passwd", root).starts_with(root));
+        assert!(to_root_path("//etc/passwd", root).starts_with(root));
+    }
+
+    #[test]
+    fn percent_decode_rejects_malformed_escapes() {
+        assert_eq!(percent_decode("/a%20b%2F").unwrap(), b"/a b/");
+        assert_eq!(percent_decode("%00").unwrap(), [0]);
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%g0"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%\u{e9}"), None);
+    }
+
+    #[test]
+    fn parses_the_three_date_formats() {
+        let expected = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(format_http_date(expected), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        for secs in [0, 951782400, 4107542399, 253402300799] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(parse_http_date(&format_http_date(time)), Some(time), "{}", secs);
+        }
+    }
+
+    #[test]
+    fn refuses_dates_out_of_range() {
+        for date in [
+            "Sun, 06 Nov 999999999999999999 08:49:37 GMT",
+            "Sun, 06 Nov 9223372036854775807 08:49:37 GMT",
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 99999999999",
+            "Sun, 31 Apr 2020 08:49:37 GMT",
+            "Sun, 30 Feb 2020 08:49:37 GMT",
+            "Sun, 29 Feb 2100 08:49:37 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "yesterday",
+            "",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+        assert!(parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT").is_some());
+        assert!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    }
 }
@@ -0,0 +1,382 @@
+/*
+ *	Name-based virtual hosts: the `Host` of a request picks the site
+ *	answering it, each with its own settings, aliases and WebDAV locks.
+ */
+use std::io::Error;
+use std::sync::Arc;
+
+use client_side::alias::Aliases;
+use client_side::request::Request;
+use server_side::config::{Configuration, SiteConf};
+use server_side::status;
+use server_side::status::HttpStatus;
+use server_side::webdav::DavState;
+
+#[derive(Debug)]
+pub struct Site {
+    // Names it answers to, `*.example.com` for any subdomain. Empty for
+    // the main site.
+    names: Vec<String>,
+    pub config: SiteConf,
+    pub aliases: Aliases,
+    // Kept by the same site across reloads.
+    pub dav: Arc<DavState>,
+}
+
+impl Site {
+    fn new(
+        names: &[String],
+        config: &SiteConf,
+        previous: Option<&VirtualHosts>,
+    ) -> Result<Site, Error> {
+        let previous_site = previous.and_then(|hosts| {
+            Some(&hosts.main)
+                .into_iter()
+                .chain(hosts.hosts.iter())
+                .find(|site| site.names == names)
+        });
+        Ok(Site {
+            names: names.to_vec(),
+            aliases: Aliases::from_file(&config.alias_file)?,
+            config: config.clone(),
+            dav: match previous_site {
+                Some(site) => Arc::clone(&site.dav),
+                None => Arc::new(DavState::new()),
+            },
+        })
+    }
+
+    fn is_named(&self, host: &str) -> bool {
+        self.names.iter().any(|name| name == host)
+    }
+
+    // Length of the longest `*.` name matching `host`, 0 for none.
+    fn get_wildcard_match(&self, host: &str) -> usize {
+        self.names
+            .iter()
+            .filter(|name| name.starts_with("*.") && host.ends_with(&name[1..]))
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualHosts {
+    // Site of [server].
+    main: Site,
+    hosts: Vec<Site>,
+    // Index in `hosts` of `default_host`, None for the main site.
+    default_host: Option<usize>,
+    // 421 for a Host no site answers to, instead of the default site.
+    reject_unknown_hosts: bool,
+}
+
+impl VirtualHosts {
+    // Sites of `config`, taking over the WebDAV state of the same sites in
+    // `previous` on reload.
+    pub fn new(
+        config: &Configuration,
+        previous: Option<&VirtualHosts>,
+    ) -> Result<VirtualHosts, Error> {
+        let mut hosts = vec![];
+        for host in config.hosts.iter() {
+            hosts.push(Site::new(&host.names, &host.site, previous)?);
+        }
+        // Checked with the config: it names a host when set.
+        let default_host = hosts
+            .iter()
+            .position(|site| site.is_named(&config.server.default_host));
+
+        Ok(VirtualHosts {
+            main: Site::new(&[], &config.site, previous)?,
+            hosts,
+            default_host,
+            reject_unknown_hosts: config.server.reject_unknown_hosts,
+        })
+    }
+
+    pub fn get_main(&self) -> &Site {
+        &self.main
+    }
+
+    // Site for the request: exact names first, then the longest wildcard,
+    // then the default site, or 421 with `reject_unknown_hosts`. Requests
+    // without a host name (HTTP/1.0) go to the default site.
+    pub fn select(&self, req: &Request) -> Result<&Site, HttpStatus> {
+        let host = match get_host_name(req)? {
+            Some(host) => host,
+            None => return Ok(self.get_default()),
+        };
+
+        if let Some(site) = self.hosts.iter().find(|site| site.is_named(&host)) {
+            return Ok(site);
+        }
+        let wildcard = self
+            .hosts
+            .iter()
+            .map(|site| (site.get_wildcard_match(&host), site))
+            .filter(|(length, _)| *length > 0)
+            .max_by_key(|(length, _)| *length);
+        match wildcard {
+            Some((_, site)) => Ok(site),
+            None if self.reject_unknown_hosts => Err(status::MISDIRECTED_REQUEST),
+            None => Ok(self.get_default()),
+        }
+    }
+
+    fn get_default(&self) -> &Site {
+        match self.default_host {
+            Some(i) => &self.hosts[i],
+            None => &self.main,
+        }
+    }
+}
+
+// Host name of the request, lowercased, without port or trailing dot.
+fn get_host_name(req: &Request) -> Result<Option<String>, HttpStatus> {
+    Ok(get_host(req)?.map(|host| host.0))
+}
+
+// `authority` (of a URL, without its `user@` part) names the host the
+// request was sent to, on the same port. `default_port` is the one of the
+// URL scheme, taken for a missing port on both sides. Without a host name
+// in the request (HTTP/1.0) there is nothing to compare, any is ours.
+pub fn is_same_host(req: &Request, authority: &str, default_port: u16) -> bool {
+    match (get_host(req), parse_authority(authority)) {
+        (Ok(None), Ok(_)) => true,
+        (Ok(Some((name, port))), Ok((other_name, other_port))) => {
+            name == other_name && port.unwrap_or(default_port) == other_port.unwrap_or(default_port)
+        }
+        _ => false,
+    }
+}
+
+// Host name and port of the request. An absolute-form target wins over
+// `Host`, but HTTP/1.1 must still send exactly one `Host` field: 400
+// otherwise, or for a malformed one.
+fn get_host(req: &Request) -> Result<Option<(String, Option<u16>)>, HttpStatus> {
+    let fields: Vec<&str> = req
+        .header
+        .iter()
+        .filter(|rd| rd.key.eq_ignore_ascii_case("host"))
+        .map(|rd| rd.value.trim())
+        .collect();
+    if fields.len() > 1 || (fields.is_empty() && req.http_ver == "HTTP/1.1") {
+        return Err(status::BAD_REQUEST);
+    }
+
+    let authority = match req.authority {
+        // Without the `user@` part a URL may have.
+        Some(ref authority) => authority.rsplit('@').next().unwrap_or(""),
+        None => fields.first().cloned().unwrap_or(""),
+    };
+    let (name, port) = parse_authority(authority)?;
+    Ok(if name.is_empty() { None } else { Some((name, port)) })
+}
+
+// `name[:port]`: the name lowercased without trailing dot, 400 if malformed.
+fn parse_authority(authority: &str) -> Result<(String, Option<u16>), HttpStatus> {
+    let name = if authority.starts_with('[') {
+        match authority.find(']') {
+            Some(end) => &authority[..end + 1],
+            None => return Err(status::BAD_REQUEST),
+        }
+    } else {
+        match authority.rfind(':') {
+            Some(colon) => &authority[..colon],
+            None => authority,
+        }
+    };
+
+    let port = &authority[name.len()..];
+    let port_valid =
+        port.is_empty() || (port.starts_with(':') && port[1..].bytes().all(|b| b.is_ascii_digit()));
+    let port = match port.get(1..) {
+        None | Some("") => None,
+        Some(digits) => Some(digits.parse::<u16>().map_err(|_| status::BAD_REQUEST)?),
+    };
+    let name_valid = if name.starts_with('[') {
+        name[1..name.len() - 1]
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
+    } else {
+        name.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+    };
+    if !port_valid || !name_valid {
+        return Err(status::BAD_REQUEST);
+    }
+
+    Ok((name.trim_end_matches('.').to_lowercase(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client_side::request::RequestParser;
+    use server_side::config::HostConf;
+
+    // Sites named by their home_dir: `/main` and one for each of `hosts`.
+    fn get_hosts(hosts: &[&str], default_host: &str, reject_unknown_hosts: bool) -> VirtualHosts {
+        let mut config = Configuration::builtin(&[]).unwrap();
+        config.site.home_dir = "/main".to_string();
+        for names in hosts.iter() {
+            let mut site = config.site.clone();
+            site.home_dir = format!("/{}", names);
+            config.hosts.push(HostConf {
+                names: names.split(' ').map(|name| name.to_string()).collect(),
+                site,
+            });
+        }
+        config.server.default_host = default_host.to_string();
+        config.server.reject_unknown_hosts = reject_unknown_hosts;
+        VirtualHosts::new(&config, None).unwrap()
+    }
+
+    fn get_request(raw: &str) -> Request {
+        RequestParser::new()
+            .parse(&mut raw.as_bytes().to_vec())
+            .unwrap()
+            .unwrap()
+    }
+
+    fn select(hosts: &VirtualHosts, host: &str) -> Result<String, HttpStatus> {
+        let req = get_request(&format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host));
+        hosts.select(&req).map(|site| site.config.home_dir.clone())
+    }
+
+    #[test]
+    fn exact_names_beat_wildcards_beat_the_default() {
+        let hosts = get_hosts(
+            &[
+                "*.example.com",
+                "*.api.example.com",
+                "www.example.com example.com",
+                "v1.api.example.com",
+            ],
+            "",
+            false,
+        );
+        assert_eq!(
+            select(&hosts, "www.example.com").unwrap(),
+            "/www.example.com example.com"
+        );
+        assert_eq!(
+            select(&hosts, "example.com").unwrap(),
+            "/www.example.com example.com"
+        );
+        assert_eq!(
+            select(&hosts, "v1.api.example.com").unwrap(),
+            "/v1.api.example.com"
+        );
+        // The longest wildcard.
+        assert_eq!(
+            select(&hosts, "v2.api.example.com").unwrap(),
+            "/*.api.example.com"
+        );
+        assert_eq!(select(&hosts, "a.b.example.com").unwrap(), "/*.example.com");
+        assert_eq!(select(&hosts, "api.example.com").unwrap(), "/*.example.com");
+        // `*.` needs a subdomain, and a whole label.
+        assert_eq!(select(&hosts, "notexample.com").unwrap(), "/main");
+        assert_eq!(select(&hosts, "other.org").unwrap(), "/main");
+
+        // default_host instead of the main site.
+        let hosts = get_hosts(&["a.com", "b.com"], "b.com", false);
+        assert_eq!(select(&hosts, "a.com").unwrap(), "/a.com");
+        assert_eq!(select(&hosts, "c.com").unwrap(), "/b.com");
+        let req = get_request("GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap().config.home_dir, "/b.com");
+    }
+
+    #[test]
+    fn compares_names_without_port_case_or_trailing_dot() {
+        let hosts = get_hosts(&["a.com", "[::1]", "127.0.0.1"], "", false);
+        for host in ["a.com:8080", "A.Com", "a.com.", "a.com.:80", "a.com:"].iter() {
+            assert_eq!(select(&hosts, host).unwrap(), "/a.com", "{}", host);
+        }
+        assert_eq!(select(&hosts, "[::1]").unwrap(), "/[::1]");
+        assert_eq!(select(&hosts, "[::1]:8080").unwrap(), "/[::1]");
+        assert_eq!(select(&hosts, "127.0.0.1:80").unwrap(), "/127.0.0.1");
+
+        // An absolute-form target names the host over `Host`.
+        let req = get_request("GET http://user@a.com:81/x HTTP/1.1\r\nHost: other\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap().config.home_dir, "/a.com");
+    }
+
+    #[test]
+    fn unknown_hosts_are_421_when_rejected() {
+        let hosts = get_hosts(&["a.com", "*.b.com"], "", true);
+        assert_eq!(select(&hosts, "a.com").unwrap(), "/a.com");
+        assert_eq!(select(&hosts, "x.b.com").unwrap(), "/*.b.com");
+        assert_eq!(
+            select(&hosts, "c.com").unwrap_err(),
+            status::MISDIRECTED_REQUEST
+        );
+        // HTTP/1.0 without Host can't be misdirected.
+        let req = get_request("GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap().config.home_dir, "/main");
+    }
+
+    #[test]
+    fn http_1_1_needs_exactly_one_good_host() {
+        let hosts = get_hosts(&["a.com"], "", false);
+        let req = get_request("GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap_err(), status::BAD_REQUEST);
+        let req = get_request("GET / HTTP/1.1\r\nHost: a.com\r\nHost: a.com\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap_err(), status::BAD_REQUEST);
+        let req = get_request("GET / HTTP/1.0\r\nHost: a.com\r\nhost: b.com\r\n\r\n");
+        assert_eq!(hosts.select(&req).unwrap_err(), status::BAD_REQUEST);
+        for host in [
+            "a.com:http",
+            "a.com:99999",
+            "a com",
+            "a/b",
+            "[::1",
+            "[::g]",
+            "a.com:80:80",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                select(&hosts, host).unwrap_err(),
+                status::BAD_REQUEST,
+                "{}",
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn parses_authorities() {
+        assert_eq!(
+            parse_authority("Example.COM.").unwrap(),
+            ("example.com".to_string(), None)
+        );
+        assert_eq!(
+            parse_authority("example.com:8080").unwrap(),
+            ("example.com".to_string(), Some(8080))
+        );
+        assert_eq!(
+            parse_authority("[2001:DB8::1]:443").unwrap(),
+            ("[2001:db8::1]".to_string(), Some(443))
+        );
+        assert_eq!(
+            parse_authority("[::ffff:1.2.3.4]").unwrap(),
+            ("[::ffff:1.2.3.4]".to_string(), None)
+        );
+        assert_eq!(parse_authority("").unwrap(), (String::new(), None));
+        assert_eq!(parse_authority("[::1]x").unwrap_err(), status::BAD_REQUEST);
+    }
+
+    #[test]
+    fn compares_hosts_with_default_ports() {
+        let req = get_request("GET / HTTP/1.1\r\nHost: a.com\r\n\r\n");
+        assert!(is_same_host(&req, "a.com", 80));
+        assert!(is_same_host(&req, "A.com:80", 80));
+        assert!(!is_same_host(&req, "a.com:8080", 80));
+        assert!(!is_same_host(&req, "b.com", 80));
+        let req = get_request("GET / HTTP/1.0\r\n\r\n");
+        assert!(is_same_host(&req, "anything:1", 80));
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ *	Just enough XML for WebDAV request bodies: elements with namespaces,
+ *	attributes and text. No DTDs, no external entities.
+ */
+
+// Deeper documents are refused, no WebDAV body needs this many levels.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    // Namespace URI, `DAV:` for WebDAV elements.
+    pub ns: String,
+    pub name: String,
+    // Attributes other than namespace declarations, as written.
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn new(ns: &str, name: &str) -> Element {
+        Element {
+            ns: ns.to_string(),
+            name: name.to_string(),
+            attributes: vec![],
+            children: vec![],
+        }
+    }
+
+    pub fn is(&self, ns: &str, name: &str) -> bool {
+        self.ns == ns && self.name == name
+    }
+
+    pub fn elements(&self) -> Vec<&Element> {
+        self.children
+            .iter()
+            .filter_map(|node| match *node {
+                Node::Element(ref element) => Some(element),
+                Node::Text(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+        self.elements().into_iter().find(|e| e.is(ns, name))
+    }
+
+    pub fn get_text(&self) -> String {
+        let mut text = String::new();
+        for node in self.children.iter() {
+            match *node {
+                Node::Text(ref t) => text.push_str(t),
+                Node::Element(ref e) => text.push_str(&e.get_text()),
+            }
+        }
+        text
+    }
+
+    // Serialize with the namespace declared on the element, so it can be
+    // pasted anywhere in another document.
+    pub fn to_xml(&self) -> String {
+        self.write_xml(None)
+    }
+
+    // Same element without its children, e.g. a property name.
+    pub fn to_empty_xml(&self) -> String {
+        format!("<{} xmlns=\"{}\"/>", self.name, escape(&self.ns))
+    }
+
+    fn write_xml(&self, parent_ns: Option<&str>) -> String {
+        let mut xml = format!("<{}", self.name);
+        if parent_ns != Some(self.ns.as_str()) {
+            xml += &format!(" xmlns=\"{}\"", escape(&self.ns));
+        }
+        for (key, value) in self.attributes.iter() {
+            xml += &format!(" {}=\"{}\"", key, escape(value));
+        }
+
+        if self.children.is_empty() {
+            return xml + "/>";
+        }
+
+        xml.push('>');
+        for node in self.children.iter() {
+            match *node {
+                Node::Text(ref text) => xml += &escape(text),
+                Node::Element(ref e) => xml += &e.write_xml(Some(&self.ns)),
+            }
+        }
+        xml + &format!("</{}>", self.name)
+    }
+}
+
+// Escape text for element content and attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Parse a document and return its root element.
+pub fn parse(input: &str) -> Option<Element> {
+    let mut parser = Parser {
+        input,
+        pos: 0,
+        namespaces: vec![
+            ("xml".to_string(), "http://www.w3.org/XML/1998/namespace".to_string()),
+            (String::new(), String::new()),
+        ],
+    };
+
+    parser.skip_misc()?;
+    let root = parser.parse_element(0)?;
+    parser.skip_misc()?;
+    if parser.pos == input.len() {
+        Some(root)
+    } else {
+        None
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    // (prefix, URI) in scope, innermost last. `""` is the default namespace.
+    namespaces: Vec<(String, String)>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // Skip to after `end`, None if it never comes.
+    fn skip_past(&mut self, end: &str) -> Option<()> {
+        let at = self.rest().find(end)?;
+        self.pos += at + end.len();
+        Some(())
+    }
+
+    // Whitespace, comments and processing instructions between elements.
+    fn skip_misc(&mut self) -> Option<()> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<!") {
+                // DOCTYPE and friends: entity definitions are not for us.
+                return None;
+            } else {
+                return Some(());
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return None;
+        }
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn lookup_namespace(&self, prefix: &str) -> Option<String> {
+        self.namespaces
+            .iter()
+            .rev()
+            .find(|ns| ns.0 == prefix)
+            .map(|ns| ns.1.to_owned())
+    }
+
+    fn parse_element(&mut self, depth: usize) -> Option<Element> {
+        if depth > MAX_DEPTH || !self.rest().starts_with('<') {
+            return None;
+        }
+        self.pos += 1;
+        let qname = self.parse_name()?;
+
+        let scope = self.namespaces.len();
+        let mut attributes: Vec<(String, String)> = vec![];
+        let self_closing = loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                break true;
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break false;
+            }
+
+            let key = self.parse_name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return None;
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let quote = self.rest().chars().next()?;
+            if quote != '"' && quote != '\'' {
+                return None;
+            }
+            self.pos += 1;
+            let end = self.rest().find(quote)?;
+            let value = unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+
+            if key == "xmlns" {
+                self.namespaces.push((String::new(), value));
+            } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                self.namespaces.push((prefix.to_string(), value));
+            } else {
+                attributes.push((key.to_string(), value));
+            }
+        };
+
+        let (prefix, name) = match qname.find(':') {
+            Some(colon) => (&qname[..colon], &qname[colon + 1..]),
+            None => ("", qname),
+        };
+        let mut element = Element {
+            ns: self.lookup_namespace(prefix)?,
+            name: name.to_string(),
+            attributes,
+            children: vec![],
+        };
+
+        if !self_closing {
+            self.parse_content(&mut element, depth)?;
+            let close = format!("</{}", qname);
+            if !self.rest().starts_with(&close) {
+                return None;
+            }
+            self.pos += close.len();
+            self.skip_whitespace();
+            if !self.rest().starts_with('>') {
+                return None;
+            }
+            self.pos += 1;
+        }
+
+        self.namespaces.truncate(scope);
+        Some(element)
+    }
+
+    // Children and text up to the closing tag.
+    fn parse_content(&mut self, element: &mut Element, depth: usize) -> Option<()> {
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                return Some(());
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let end = cdata.find("]]>")?;
+                element.children.push(Node::Text(cdata[..end].to_string()));
+                self.pos += "<![CDATA[".len() + end + 3;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with('<') {
+                let child = self.parse_element(depth + 1)?;
+                element.children.push(Node::Element(child));
+            } else if rest.is_empty() {
+                return None;
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                let text = unescape(&rest[..end])?;
+                self.pos += end;
+                // Indentation between elements means nothing.
+                if !text.trim().is_empty() {
+                    element.children.push(Node::Text(text));
+                }
+            }
+        }
+    }
+}
+
+// Replace entity and character references.
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let semi = rest.find(';')?;
+        let entity = &rest[..semi];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ if entity.starts_with("#x") => {
+                std::char::from_u32(u32::from_str_radix(&entity[2..], 16).ok()?)?
+            }
+            _ if entity.starts_with('#') => std::char::from_u32(entity[1..].parse().ok()?)?,
+            _ => return None,
+        };
+        unescaped.push(c);
+        rest = &rest[semi + 1..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}